@@ -0,0 +1,468 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{CacheConfig, ProviderConfig},
+    text_to_speach::provider::{TtsClient, TtsError, TtsProvider},
+    util::{hex, hex_hash},
+};
+
+const DEFAULT_MAX_SIZE_MB: u64 = 2048;
+/// Extension of audio still being copied in the cache
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// Audio synthesized by the providers, by hash of what produced it, so a
+/// chunk already paid for is never synthesized again.
+///
+/// `prune` removes the least recently used audio once it grows past its size.
+#[derive(Debug, Clone)]
+pub struct SynthesisCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+/// What `prune` did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Pruned {
+    pub removed: usize,
+    pub freed: u64,
+    /// Size left in the cache
+    pub size: u64,
+}
+
+impl SynthesisCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    pub fn from_config(config: &CacheConfig) -> Self {
+        let dir = config.dir().unwrap_or_else(|| {
+            std::env::var("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|_| std::env::var("HOME").map(|home| Path::new(&home).join(".cache")))
+                .unwrap_or(std::env::temp_dir())
+                .join("book2pod")
+        });
+        let max_size_mb = config.max_size_mb.unwrap_or(DEFAULT_MAX_SIZE_MB);
+        Self::new(dir, max_size_mb * 1024 * 1024)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Copies the audio of `key` to `output`, false when it's not cached
+    pub fn get(&self, key: &str, output: &Path) -> Result<bool> {
+        let path = self.path(key);
+        match fs::copy(&path, output) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        // a hit makes it the most recently used, unless a prune just took it
+        match fs::File::options().write(true).open(&path) {
+            Ok(file) => file.set_modified(SystemTime::now())?,
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(true)
+    }
+
+    /// Stores `audio` as `key`, nothing is evicted before the next `prune`
+    pub fn put(&self, key: &str, audio: &Path) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // renamed once complete, a run killed halfway leaves no broken audio
+        let partial = self.path(&format!("{key}.{PARTIAL_EXTENSION}"));
+        fs::copy(audio, &partial)?;
+        fs::rename(&partial, self.path(key))?;
+        Ok(())
+    }
+
+    /// Removes the least recently used audio until the cache takes at most
+    /// `max_size` bytes.
+    ///
+    /// Safe while other tasks use the cache: audio being stored is left alone
+    /// and audio removed by someone else is already gone.
+    pub fn prune(&self, max_size: u64) -> Result<Pruned> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Pruned::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut files = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == PARTIAL_EXTENSION) {
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(metadata) => files.push((metadata.modified()?, metadata.len(), path)),
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        files.sort();
+
+        let mut pruned = Pruned {
+            size: files.iter().map(|(_, len, _)| len).sum(),
+            ..Pruned::default()
+        };
+        for (_, len, path) in files {
+            if pruned.size <= max_size {
+                break;
+            }
+            pruned.size -= len;
+            match fs::remove_file(path) {
+                Ok(()) => {
+                    pruned.removed += 1;
+                    pruned.freed += len;
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(pruned)
+    }
+}
+
+/// Everything that changes the audio of a chunk beside its text
+#[derive(Debug, Clone)]
+pub struct SynthesisSettings {
+    pub provider: TtsProvider,
+    pub model: Option<String>,
+    pub voice: Option<String>,
+    pub speed: Option<String>,
+    pub language: Option<String>,
+    /// Another server can run another model
+    pub base_url: Option<String>,
+    /// Hash of the voice when it's a file, a coqui reference wav recorded
+    /// again under the same name is another voice
+    pub voice_file: Option<String>,
+    /// Stability, similarity boost and output format of elevenlabs
+    pub voice_settings: Option<String>,
+}
+
+impl SynthesisSettings {
+    pub fn new(provider: TtsProvider, config: &ProviderConfig, language: Option<String>) -> Self {
+        Self {
+            provider,
+            model: config.model.clone(),
+            voice: config.voice.clone(),
+            speed: config.speed.map(|s| format!("{s:?}")),
+            language,
+            base_url: config.base_url.clone(),
+            voice_file: config
+                .voice
+                .as_deref()
+                .and_then(|voice| fs::read(voice).ok())
+                .map(|content| hex_hash(&content)),
+            voice_settings: match (
+                config.stability,
+                config.similarity_boost,
+                &config.output_format,
+            ) {
+                (None, None, None) => None,
+                settings => Some(format!("{settings:?}")),
+            },
+        }
+    }
+
+    /// Hash of the settings, `text`, the text around it and `extension`, with
+    /// the extension appended so the cache stays browsable
+    pub fn key(
+        &self,
+        text: &str,
+        previous_text: Option<&str>,
+        next_text: Option<&str>,
+        extension: &str,
+    ) -> String {
+        let mut hasher = Sha256::new();
+        for field in [
+            Some(self.provider.to_string()).as_deref(),
+            self.model.as_deref(),
+            self.voice.as_deref(),
+            self.speed.as_deref(),
+            self.language.as_deref(),
+            self.base_url.as_deref(),
+            self.voice_file.as_deref(),
+            self.voice_settings.as_deref(),
+            Some(extension),
+            Some(text),
+            // elevenlabs speaks the same text differently next to other text
+            previous_text,
+            next_text,
+        ] {
+            // the tag keeps None apart from Some("")
+            match field {
+                Some(field) => {
+                    hasher.update([1]);
+                    hasher.update((field.len() as u64).to_le_bytes());
+                    hasher.update(field);
+                }
+                None => hasher.update([0]),
+            }
+        }
+        format!("{}.{extension}", hex(&hasher.finalize()))
+    }
+}
+
+/// Answers from the cache when it can, otherwise synthesizes with `client`
+/// and caches the audio
+#[derive(Clone)]
+pub struct CachedClient<C> {
+    client: C,
+    cache: Option<SynthesisCache>,
+    settings: SynthesisSettings,
+    previous_text: Option<String>,
+    next_text: Option<String>,
+}
+
+impl<C> CachedClient<C> {
+    /// Without a cache it's just `client`
+    pub fn new(client: C, cache: Option<SynthesisCache>, settings: SynthesisSettings) -> Self {
+        Self {
+            client,
+            cache,
+            settings,
+            previous_text: None,
+            next_text: None,
+        }
+    }
+}
+
+impl<C: TtsClient> TtsClient for CachedClient<C> {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let Some(cache) = &self.cache else {
+            return self.client.speak_to_file(text, path).await;
+        };
+        let extension = Path::new(&path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let key = self.settings.key(
+            &text,
+            self.previous_text.as_deref(),
+            self.next_text.as_deref(),
+            &extension,
+        );
+        // the cache is best effort, when it breaks we pay instead of failing
+        if cache.get(&key, Path::new(&path)).unwrap_or(false) {
+            return Ok(());
+        }
+
+        self.client.speak_to_file(text, path.clone()).await?;
+        let _ = cache.put(&key, Path::new(&path));
+        Ok(())
+    }
+
+    fn with_context(self, previous_text: Option<String>, next_text: Option<String>) -> Self {
+        Self {
+            client: self
+                .client
+                .with_context(previous_text.clone(), next_text.clone()),
+            previous_text,
+            next_text,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::text_to_speach::provider::SpeechSpeed;
+
+    use super::*;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Writes the text as audio and counts its calls
+    struct EchoClient;
+
+    impl TtsClient for EchoClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            fs::write(path, text).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    fn settings() -> SynthesisSettings {
+        SynthesisSettings::new(
+            TtsProvider::OpenAi,
+            &ProviderConfig {
+                voice: Some("nova".to_owned()),
+                speed: Some(SpeechSpeed::Quick),
+                ..ProviderConfig::default()
+            },
+            Some("en".to_owned()),
+        )
+    }
+
+    #[test]
+    fn cache_key() {
+        let key = settings().key("hello", None, None, "mp3");
+        assert_eq!(key, settings().key("hello", None, None, "mp3"));
+        assert!(key.ends_with(".mp3"));
+
+        assert_ne!(key, settings().key("hello!", None, None, "mp3"));
+        assert_ne!(key, settings().key("hello", None, None, "wav"));
+        let context = settings().key("hello", Some("hi"), None, "mp3");
+        assert_ne!(key, context);
+        assert_ne!(context, settings().key("hello", None, Some("hi"), "mp3"));
+        let mut other = settings();
+        other.voice = Some("alloy".to_owned());
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let mut other = settings();
+        other.speed = None;
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let mut other = settings();
+        other.provider = TtsProvider::ElevenLabs;
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let mut other = settings();
+        other.language = Some("it".to_owned());
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let mut other = settings();
+        other.base_url = Some("http://localhost:8000".to_owned());
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let mut other = settings();
+        other.voice_settings = Some(String::new());
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+        let other = SynthesisSettings::new(
+            TtsProvider::OpenAi,
+            &ProviderConfig {
+                voice: Some("nova".to_owned()),
+                speed: Some(SpeechSpeed::Quick),
+                stability: Some(0.3),
+                ..ProviderConfig::default()
+            },
+            Some("en".to_owned()),
+        );
+        assert_ne!(key, other.key("hello", None, None, "mp3"));
+    }
+
+    #[test]
+    fn cache_key_voice_file() {
+        let voice = std::env::temp_dir().join("book2pod-cache-voice.wav");
+        let config = ProviderConfig {
+            voice: Some(voice.to_string_lossy().to_string()),
+            ..ProviderConfig::default()
+        };
+        fs::write(&voice, "first take").unwrap();
+        let first = SynthesisSettings::new(TtsProvider::Coqui, &config, None);
+        fs::write(&voice, "second take").unwrap();
+        let second = SynthesisSettings::new(TtsProvider::Coqui, &config, None);
+        fs::remove_file(voice).unwrap();
+
+        assert!(first.voice_file.is_some());
+        assert_ne!(
+            first.key("hello", None, None, "wav"),
+            second.key("hello", None, None, "wav")
+        );
+        // a named voice is no file
+        assert_eq!(settings().voice_file, None);
+    }
+
+    #[tokio::test]
+    async fn cache_hit() {
+        let dir = std::env::temp_dir().join("book2pod-cache-hit");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cache = SynthesisCache::new(dir.join("cache"), 1024);
+        let settings = settings();
+        let path = dir.join("out.mp3").to_string_lossy().to_string();
+
+        for text in ["one", "two", "one"] {
+            CachedClient::new(EchoClient, Some(cache.clone()), settings.clone())
+                .speak_to_file(text.to_owned(), path.clone())
+                .await
+                .unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+        assert_eq!(fs::read_dir(cache.dir()).unwrap().count(), 2);
+
+        // the same text in another place
+        CachedClient::new(EchoClient, Some(cache.clone()), settings.clone())
+            .with_context(Some("zero".to_owned()), None)
+            .speak_to_file("one".to_owned(), path.clone())
+            .await
+            .unwrap();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        CachedClient::new(EchoClient, None, settings)
+            .speak_to_file("one".to_owned(), path)
+            .await
+            .unwrap();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cache_prune() {
+        let dir = std::env::temp_dir().join("book2pod-cache-prune");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = SynthesisCache::new(dir.clone(), 10);
+        let audio = std::env::temp_dir().join("book2pod-cache-prune.mp3");
+        fs::write(&audio, "1234").unwrap();
+
+        let old = SystemTime::now() - Duration::from_secs(60);
+        for key in ["a.mp3", "b.mp3"] {
+            cache.put(key, &audio).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(dir.join(key))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        // reading a makes b the least recently used
+        assert!(cache.get("a.mp3", &audio).unwrap());
+        cache.put("c.mp3", &audio).unwrap();
+        assert!(dir.join("b.mp3").exists());
+        // another task still copying its audio
+        fs::write(dir.join("d.mp3.partial"), "12345678").unwrap();
+
+        assert_eq!(
+            cache.prune(cache.max_size()).unwrap(),
+            Pruned {
+                removed: 1,
+                freed: 4,
+                size: 8
+            }
+        );
+        assert!(!dir.join("b.mp3").exists());
+        assert!(dir.join("a.mp3").exists() && dir.join("c.mp3").exists());
+        assert!(dir.join("d.mp3.partial").exists());
+        assert!(!cache.get("b.mp3", &audio).unwrap());
+
+        assert_eq!(
+            cache.prune(0).unwrap(),
+            Pruned {
+                removed: 2,
+                freed: 8,
+                size: 0
+            }
+        );
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_file(audio).unwrap();
+    }
+}
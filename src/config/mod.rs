@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::text_to_speach::provider::{SpeechSpeed, TtsError, TtsProvider};
+
+/// Read when no config is given and it exists in the current directory
+pub const DEFAULT_CONFIG_FILE: &str = "book2pod.toml";
+
+/// Settings of one provider, a `[providers.<name>]` section:
+///
+/// ```toml
+/// [providers.openai]
+/// api_key_file = "~/.secrets/openai"
+/// model = "tts-1-hd"
+/// voice = "nova"
+/// speed = "quick"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// Wins over the environment variables of the provider, for polly it is
+    /// `<access key id>:<secret access key>`
+    pub api_key: Option<String>,
+    /// File holding the api key, for google the json key of a service account
+    pub api_key_file: Option<PathBuf>,
+    /// Endpoint of the api, for self hosted or compatible servers
+    pub base_url: Option<String>,
+    /// Model of the provider, the engine for polly, the voice type for google
+    pub model: Option<String>,
+    pub voice: Option<String>,
+    pub speed: Option<SpeechSpeed>,
+    /// Requests sent at once, see `TtsProvider::limits` for the defaults
+    pub concurrency: Option<usize>,
+    pub requests_per_minute: Option<u32>,
+    /// For `book2pod estimate`, defaults to the list price of the model
+    pub price_per_million_chars: Option<f64>,
+    /// Voice settings of elevenlabs, between 0 and 1
+    pub stability: Option<f64>,
+    pub similarity_boost: Option<f64>,
+    /// Audio of elevenlabs, one of its mp3 formats like `mp3_44100_192`
+    pub output_format: Option<String>,
+}
+
+impl ProviderConfig {
+    /// `api_key`, or the content of `api_key_file`
+    pub fn api_key(&self) -> Result<Option<String>, TtsError> {
+        if let Some(api_key) = &self.api_key {
+            return Ok(Some(api_key.clone()));
+        }
+        self.api_key_file
+            .as_ref()
+            .map(|path| {
+                fs::read_to_string(expand_home(path))
+                    .map(|key| key.trim().to_owned())
+                    .map_err(|e| {
+                        TtsError::Unauthorized(format!("can't read {}: {e}", path.display()))
+                    })
+            })
+            .transpose()
+    }
+
+    /// `api_key_file` with `~` expanded
+    pub fn api_key_file(&self) -> Option<PathBuf> {
+        self.api_key_file.as_deref().map(expand_home)
+    }
+}
+
+/// The `[cache]` section, where synthesized chunks are kept between runs
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Defaults to `$XDG_CACHE_HOME/book2pod` or `~/.cache/book2pod`
+    pub dir: Option<PathBuf>,
+    /// The least recently used audio goes past this, defaults to 2048
+    pub max_size_mb: Option<u64>,
+}
+
+impl CacheConfig {
+    /// `dir` with `~` expanded
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.dir.as_deref().map(expand_home)
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var("HOME")) {
+        (Ok(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// By provider name
+    #[serde(default)]
+    providers: HashMap<String, ProviderConfig>,
+    #[serde(default)]
+    cache: CacheConfig,
+}
+
+impl Config {
+    /// Reads `path`, or `book2pod.toml` when there is one, or returns the
+    /// defaults
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Self::default()),
+        };
+        let toml = fs::read_to_string(path).with_context(|| format!("{}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("{}", path.display()))
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let config: Config = toml::from_str(toml)?;
+        // keyed by the canonical name, so aliases like espeak-ng work too
+        let providers = config
+            .providers
+            .into_iter()
+            .map(|(name, provider_config)| {
+                let provider = name
+                    .parse::<TtsProvider>()
+                    .map_err(|_| anyhow!("unknown provider section [providers.{name}]"))?;
+                Ok((provider.to_string(), provider_config))
+            })
+            .collect::<Result<HashMap<String, ProviderConfig>>>()?;
+        Ok(Self {
+            providers,
+            cache: config.cache,
+        })
+    }
+
+    pub fn provider(&self, provider: TtsProvider) -> ProviderConfig {
+        self.providers
+            .get(&provider.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn cache(&self) -> &CacheConfig {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [providers.openai]
+            api_key = "key"
+            model = "tts-1-hd"
+            voice = "nova"
+            speed = "very-quick"
+
+            [providers.espeak-ng]
+            voice = "+f3"
+
+            [providers.elevenlabs]
+            stability = 0.3
+            similarity_boost = 0.9
+            output_format = "mp3_44100_192"
+            "#,
+        )
+        .unwrap();
+
+        let openai = config.provider(TtsProvider::OpenAi);
+        assert_eq!(openai.api_key().unwrap().as_deref(), Some("key"));
+        assert_eq!(openai.model.as_deref(), Some("tts-1-hd"));
+        assert_eq!(openai.voice.as_deref(), Some("nova"));
+        assert!(matches!(openai.speed, Some(SpeechSpeed::VeryQuick)));
+        assert_eq!(
+            config.provider(TtsProvider::Espeak).voice.as_deref(),
+            Some("+f3")
+        );
+        let elevenlabs = config.provider(TtsProvider::ElevenLabs);
+        assert_eq!(elevenlabs.stability, Some(0.3));
+        assert_eq!(elevenlabs.similarity_boost, Some(0.9));
+        assert_eq!(elevenlabs.output_format.as_deref(), Some("mp3_44100_192"));
+        assert!(config.provider(TtsProvider::Azure).voice.is_none());
+        assert!(config.cache().dir.is_none());
+
+        let config = Config::from_toml("[cache]\ndir = \"/tmp/audio\"\nmax_size_mb = 10").unwrap();
+        assert_eq!(config.cache().dir(), Some(PathBuf::from("/tmp/audio")));
+        assert_eq!(config.cache().max_size_mb, Some(10));
+    }
+
+    #[test]
+    fn config_errors() {
+        assert!(Config::from_toml("[providers.nope]").is_err());
+        assert!(Config::from_toml("[providers.openai]\nkey = \"typo\"").is_err());
+        assert!(Config::from_toml("[providers.openai]\nspeed = \"warp\"").is_err());
+    }
+
+    #[test]
+    fn config_api_key_file() {
+        let path = std::env::temp_dir().join("book2pod-config-key");
+        fs::write(&path, "secret\n").unwrap();
+
+        let config = ProviderConfig {
+            api_key_file: Some(path.clone()),
+            ..ProviderConfig::default()
+        };
+        assert_eq!(config.api_key().unwrap().as_deref(), Some("secret"));
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(config.api_key(), Err(TtsError::Unauthorized(_))));
+    }
+}
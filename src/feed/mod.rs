@@ -0,0 +1,300 @@
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+use crate::text_to_speach::{
+    file_parser::{Cover, Metadata},
+    planner::PlannedEpisode,
+};
+
+const ITUNES_NAMESPACE: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "opus", "aac", "flac", "m4a"];
+
+/// A chapter that has already been synthesized to an audio file.
+pub struct Episode {
+    /// Position of the episode in the book, following `Content::order`
+    pub order: usize,
+    pub title: String,
+    /// Name of the audio file, relative to the feed base url
+    pub file_name: String,
+    /// Size of the audio file in bytes
+    pub length: u64,
+    pub mime: String,
+}
+
+impl Episode {
+    pub fn from_file(order: usize, title: String, path: &Path) -> Result<Self> {
+        let file_name = path
+            .file_name()
+            .ok_or(anyhow!("episode path has no file name: {path:?}"))?
+            .to_string_lossy()
+            .to_string();
+        Ok(Self {
+            order,
+            title,
+            mime: audio_mime(path).to_owned(),
+            length: std::fs::metadata(path)?.len(),
+            file_name,
+        })
+    }
+}
+
+pub fn episode_file_name(order: usize, extension: &str) -> String {
+    format!("{order:03}.{extension}")
+}
+
+/// Finds the audio file of each planned episode inside `dir`, episodes that
+/// were not synthesized yet are skipped.
+pub fn episodes_in_dir(planned: &[PlannedEpisode], dir: &Path) -> Result<Vec<Episode>> {
+    let mut episodes = vec![];
+    for episode in planned {
+        let path = AUDIO_EXTENSIONS
+            .iter()
+            .map(|ext| dir.join(episode_file_name(episode.number, ext)))
+            .find(|path| path.is_file());
+        if let Some(path) = path {
+            episodes.push(Episode::from_file(
+                episode.number,
+                episode.title.clone(),
+                &path,
+            )?);
+        }
+    }
+    Ok(episodes)
+}
+
+pub fn audio_mime(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("wav") => "audio/wav",
+        Some("ogg") => "audio/ogg",
+        Some("opus") => "audio/opus",
+        Some("aac") => "audio/aac",
+        Some("flac") => "audio/flac",
+        Some("m4a") => "audio/mp4",
+        _ => "audio/mpeg",
+    }
+}
+
+/// Name under which the cover is published next to the episodes
+pub fn cover_file_name(cover: &Cover) -> String {
+    let extension = match cover.mime.as_str() {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        _ => "jpg",
+    };
+    format!("cover.{extension}")
+}
+
+/// Writes a podcast RSS 2.0 document with the iTunes namespace.
+///
+/// Episodes are published as a serial show, ordered by [`Episode::order`], the
+/// `pubDate` of each one is spaced a minute apart ending at `published` so that
+/// players that sort by date keep the book order.
+pub fn write_feed<W: Write>(
+    sink: W,
+    base_url: &str,
+    metadata: &Metadata,
+    cover: Option<&Cover>,
+    episodes: &[Episode],
+    published: SystemTime,
+) -> Result<()> {
+    let base_url = base_url.trim_end_matches('/');
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(sink);
+
+    let title = metadata.title.clone().unwrap_or("Untitled".to_owned());
+    let authors = metadata.authors.join(", ");
+    let description = metadata.description.clone().unwrap_or(title.clone());
+
+    writer.write(
+        XmlEvent::start_element("rss")
+            .attr("version", "2.0")
+            .ns("itunes", ITUNES_NAMESPACE),
+    )?;
+    writer.write(XmlEvent::start_element("channel"))?;
+
+    write_text_element(&mut writer, "title", &title)?;
+    write_text_element(&mut writer, "link", base_url)?;
+    write_text_element(&mut writer, "description", &description)?;
+    if let Some(lang) = &metadata.lang {
+        write_text_element(&mut writer, "language", lang)?;
+    }
+    write_text_element(&mut writer, "itunes:author", &authors)?;
+    write_text_element(&mut writer, "itunes:summary", &description)?;
+    write_text_element(&mut writer, "itunes:type", "serial")?;
+    write_text_element(&mut writer, "itunes:explicit", "false")?;
+    if let Some(publisher) = &metadata.publisher {
+        writer.write(XmlEvent::start_element("itunes:owner"))?;
+        write_text_element(&mut writer, "itunes:name", publisher)?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    if let Some(cover) = cover {
+        let cover_url = format!("{base_url}/{}", cover_file_name(cover));
+        writer.write(XmlEvent::start_element("itunes:image").attr("href", &cover_url))?;
+        writer.write(XmlEvent::end_element())?;
+        writer.write(XmlEvent::start_element("image"))?;
+        write_text_element(&mut writer, "url", &cover_url)?;
+        write_text_element(&mut writer, "title", &title)?;
+        write_text_element(&mut writer, "link", base_url)?;
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    let mut episodes = episodes.iter().collect::<Vec<&Episode>>();
+    episodes.sort_by_key(|e| e.order);
+    let last_order = episodes.last().map(|e| e.order).unwrap_or_default();
+    for episode in episodes {
+        let url = format!("{base_url}/{}", episode.file_name);
+        let length = episode.length.to_string();
+        let guid = format!("{title}/{}", episode.file_name);
+        let order = episode.order.to_string();
+        let minutes_before_last = (last_order - episode.order) as u64;
+        let pub_date = httpdate::fmt_http_date(
+            published
+                .checked_sub(Duration::from_secs(minutes_before_last * 60))
+                .unwrap_or(published),
+        );
+
+        writer.write(XmlEvent::start_element("item"))?;
+        write_text_element(&mut writer, "title", &episode.title)?;
+        write_text_element(&mut writer, "itunes:title", &episode.title)?;
+        write_text_element(&mut writer, "itunes:author", &authors)?;
+        write_text_element(&mut writer, "itunes:episode", &order)?;
+        write_text_element(&mut writer, "itunes:episodeType", "full")?;
+        writer.write(
+            XmlEvent::start_element("enclosure")
+                .attr("url", &url)
+                .attr("length", &length)
+                .attr("type", &episode.mime),
+        )?;
+        writer.write(XmlEvent::end_element())?;
+        writer.write(XmlEvent::start_element("guid").attr("isPermaLink", "false"))?;
+        writer.write(XmlEvent::characters(&guid))?;
+        writer.write(XmlEvent::end_element())?;
+        write_text_element(&mut writer, "pubDate", &pub_date)?;
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+fn write_text_element<W: Write>(writer: &mut EventWriter<W>, name: &str, text: &str) -> Result<()> {
+    writer.write(XmlEvent::start_element(name))?;
+    writer.write(XmlEvent::characters(text))?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            authors: vec!["Sarah Louisa Forten Purvis".to_owned()],
+            title: Some("Poetry & Prose".to_owned()),
+            publisher: Some("Standard Ebooks".to_owned()),
+            description: Some("A collection of poems.".to_owned()),
+            lang: Some("en-US".to_owned()),
+        }
+    }
+
+    fn episode(order: usize) -> Episode {
+        Episode {
+            order,
+            title: format!("Chapter {order}"),
+            file_name: format!("{order:03}.mp3"),
+            length: 1000 + order as u64,
+            mime: "audio/mpeg".to_owned(),
+        }
+    }
+
+    fn render(cover: Option<&Cover>, episodes: &[Episode]) -> String {
+        let mut output = vec![];
+        write_feed(
+            &mut output,
+            "http://localhost:8080/",
+            &metadata(),
+            cover,
+            episodes,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn feed_is_well_formed() {
+        let feed = render(None, &[episode(1), episode(2)]);
+
+        for event in xml::reader::EventReader::new(feed.as_bytes()) {
+            event.unwrap();
+        }
+        assert!(feed.contains(r#"xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd""#));
+        assert!(feed.contains("<title>Poetry &amp; Prose</title>"));
+        assert!(feed.contains("<itunes:author>Sarah Louisa Forten Purvis</itunes:author>"));
+        assert!(feed.contains("<language>en-US</language>"));
+        assert!(!feed.contains("itunes:image"));
+    }
+
+    #[test]
+    fn feed_episodes() {
+        let feed = render(None, &[episode(2), episode(1)]);
+
+        assert!(feed.contains(
+            r#"<enclosure url="http://localhost:8080/001.mp3" length="1001" type="audio/mpeg" />"#
+        ));
+        assert!(feed.contains(r#"<guid isPermaLink="false">Poetry &amp; Prose/002.mp3</guid>"#));
+        assert!(feed.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 GMT</pubDate>"));
+        let first = feed.find("<itunes:episode>1</itunes:episode>").unwrap();
+        let second = feed.find("<itunes:episode>2</itunes:episode>").unwrap();
+        assert!(first < second);
+    }
+
+    #[test]
+    fn find_episodes() {
+        let dir = std::env::temp_dir().join("book2pod-feed-find-episodes");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("001.mp3"), [0u8; 10]).unwrap();
+        std::fs::write(dir.join("003.wav"), [0u8; 20]).unwrap();
+        let planned = (1..=3)
+            .map(|number| PlannedEpisode {
+                number,
+                title: format!("Chapter {number}"),
+                sources: vec![],
+                part: None,
+                text: "".to_owned(),
+                word_count: 0,
+            })
+            .collect::<Vec<PlannedEpisode>>();
+
+        let episodes = episodes_in_dir(&planned, &dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].file_name, "001.mp3");
+        assert_eq!(episodes[0].length, 10);
+        assert_eq!(episodes[1].title, "Chapter 3");
+        assert_eq!(episodes[1].mime, "audio/wav");
+    }
+
+    #[test]
+    fn feed_cover() {
+        let cover = Cover {
+            mime: "image/png".to_owned(),
+            content: vec![],
+        };
+        let feed = render(Some(&cover), &[episode(1)]);
+
+        assert!(feed.contains(r#"<itunes:image href="http://localhost:8080/cover.png" />"#));
+    }
+}
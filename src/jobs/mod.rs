@@ -0,0 +1,389 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util::hex_hash;
+
+/// Kept in the output directory of convert
+pub const JOURNAL_FILE: &str = ".book2pod-jobs.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A line of the journal, the last line about something wins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    Book {
+        title: String,
+        /// Hash of the provider settings, the work done with others is useless
+        settings: String,
+    },
+    Episode {
+        episode: usize,
+        title: String,
+        path: PathBuf,
+        chunks: usize,
+        status: Status,
+    },
+    Chunk {
+        episode: usize,
+        chunk: usize,
+        /// Hash of the text, a chunk that changed is synthesized again
+        hash: String,
+        status: Status,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    book: Option<Record>,
+    episodes: HashMap<usize, Record>,
+    chunks: HashMap<(usize, usize), Record>,
+}
+
+impl State {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Book { .. } => self.book = Some(record),
+            Record::Episode { episode, .. } => {
+                self.episodes.insert(episode, record);
+            }
+            Record::Chunk { episode, chunk, .. } => {
+                self.chunks.insert((episode, chunk), record);
+            }
+        }
+    }
+
+    /// Forgets the episodes and chunks `plan` doesn't have anymore, `plan`
+    /// being the chunks of each episode
+    fn retain_plan(&mut self, plan: &[&[String]]) {
+        self.episodes.retain(|episode, _| *episode < plan.len());
+        self.chunks.retain(|(episode, chunk), record| {
+            let text = plan.get(*episode).and_then(|chunks| chunks.get(*chunk));
+            matches!((record, text), (Record::Chunk { hash, .. }, Some(text)) if *hash == hex_hash(text.as_bytes()))
+        });
+    }
+
+    /// Every record in order, what was running when the process died is
+    /// pending again
+    fn records(&self) -> Vec<Record> {
+        let mut episodes = self.episodes.values().cloned().collect::<Vec<Record>>();
+        episodes.sort_by_key(|r| match r {
+            Record::Episode { episode, .. } => *episode,
+            _ => 0,
+        });
+        let mut chunks = self.chunks.iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|(key, _)| **key);
+
+        self.book
+            .iter()
+            .cloned()
+            .chain(episodes)
+            .chain(chunks.into_iter().map(|(_, r)| r.clone()))
+            .map(|mut record| {
+                if let Record::Episode { status, .. } | Record::Chunk { status, .. } = &mut record {
+                    if *status == Status::Running {
+                        *status = Status::Pending;
+                    }
+                }
+                record
+            })
+            .collect()
+    }
+}
+
+/// How many chunks are in each status
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub pending: usize,
+    pub running: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// Journal of a conversion: the book, its episodes and their chunks with
+/// their status, appended to as the work goes so a conversion killed at any
+/// point resumes where it stopped.
+pub struct JobStore {
+    file: Mutex<fs::File>,
+    state: Mutex<State>,
+}
+
+impl JobStore {
+    /// Opens the journal of `dir`, starting over when it was written for
+    /// other `settings` and keeping only what's still in `plan`, the chunks
+    /// of each episode
+    pub fn open(dir: &Path, title: &str, settings: &str, plan: &[&[String]]) -> Result<Self> {
+        let path = dir.join(JOURNAL_FILE);
+        let mut state = State::default();
+        match fs::read_to_string(&path) {
+            // a line cut by a crash is skipped
+            Ok(journal) => journal
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .for_each(|record| state.apply(record)),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("{}", path.display())),
+        }
+        if !matches!(&state.book, Some(Record::Book { settings: s, .. }) if s == settings) {
+            state = State::default();
+        }
+        state.apply(Record::Book {
+            title: title.to_owned(),
+            settings: settings.to_owned(),
+        });
+        // a book edited since has chunks that will never be synthesized again
+        state.retain_plan(plan);
+
+        // compacted, the journal only grows during a run
+        let records = state.records();
+        let mut journal = String::new();
+        for record in &records {
+            journal.push_str(&serde_json::to_string(record)?);
+            journal.push('\n');
+        }
+        let compacted = path.with_extension("jsonl.partial");
+        fs::write(&compacted, journal)?;
+        fs::rename(&compacted, &path)?;
+        let mut state = State::default();
+        records.into_iter().for_each(|record| state.apply(record));
+
+        let file = fs::File::options().append(true).open(&path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            state: Mutex::new(state),
+        })
+    }
+
+    fn append(&self, record: Record) -> Result<()> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        // one write per line, a crash can only cut the last one
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        self.state.lock().unwrap().apply(record);
+        Ok(())
+    }
+
+    /// Records the planned episode and its chunks as pending, keeping the
+    /// status of the chunks that didn't change
+    pub fn plan_episode(
+        &self,
+        episode: usize,
+        title: &str,
+        path: &Path,
+        chunks: &[String],
+    ) -> Result<()> {
+        if self.episode_status(episode, chunks) != Some(Status::Done) {
+            self.append(Record::Episode {
+                episode,
+                title: title.to_owned(),
+                path: path.to_owned(),
+                chunks: chunks.len(),
+                status: Status::Pending,
+            })?;
+        }
+        for (chunk, text) in chunks.iter().enumerate() {
+            if self.chunk_status(episode, chunk, text).is_none() {
+                self.set_chunk(episode, chunk, text, Status::Pending, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// None when the chunk isn't known or its text changed
+    pub fn chunk_status(&self, episode: usize, chunk: usize, text: &str) -> Option<Status> {
+        match self.state.lock().unwrap().chunks.get(&(episode, chunk)) {
+            Some(Record::Chunk { hash, status, .. }) if *hash == hex_hash(text.as_bytes()) => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn set_chunk(
+        &self,
+        episode: usize,
+        chunk: usize,
+        text: &str,
+        status: Status,
+        error: Option<String>,
+    ) -> Result<()> {
+        self.append(Record::Chunk {
+            episode,
+            chunk,
+            hash: hex_hash(text.as_bytes()),
+            status,
+            error,
+        })
+    }
+
+    /// None when the episode isn't known, it's only done while all its
+    /// `chunks` are
+    pub fn episode_status(&self, episode: usize, chunks: &[String]) -> Option<Status> {
+        let status = match self.state.lock().unwrap().episodes.get(&episode) {
+            Some(Record::Episode {
+                status,
+                chunks: count,
+                ..
+            }) if *count == chunks.len() => *status,
+            _ => return None,
+        };
+        let chunks_done = chunks
+            .iter()
+            .enumerate()
+            .all(|(chunk, text)| self.chunk_status(episode, chunk, text) == Some(Status::Done));
+        match status {
+            Status::Done if !chunks_done => Some(Status::Pending),
+            status => Some(status),
+        }
+    }
+
+    pub fn set_episode(&self, episode: usize, status: Status) -> Result<()> {
+        let record = self.state.lock().unwrap().episodes.get(&episode).cloned();
+        let Some(Record::Episode {
+            title,
+            path,
+            chunks,
+            ..
+        }) = record
+        else {
+            return Ok(());
+        };
+        self.append(Record::Episode {
+            episode,
+            title,
+            path,
+            chunks,
+            status,
+        })
+    }
+
+    pub fn progress(&self) -> Progress {
+        let mut progress = Progress::default();
+        for record in self.state.lock().unwrap().chunks.values() {
+            if let Record::Chunk { status, .. } = record {
+                match status {
+                    Status::Pending => progress.pending += 1,
+                    Status::Running => progress.running += 1,
+                    Status::Done => progress.done += 1,
+                    Status::Failed => progress.failed += 1,
+                }
+            }
+        }
+        progress
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunks(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn jobs_resume() {
+        let dir = std::env::temp_dir().join("book2pod-jobs-resume");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let episode = chunks(&["one", "two"]);
+        let plan = [episode.as_slice()];
+
+        let jobs = JobStore::open(&dir, "Book", "settings", &plan).unwrap();
+        jobs.plan_episode(0, "First", &dir.join("0.mp3"), &episode)
+            .unwrap();
+        assert_eq!(jobs.episode_status(0, &episode), Some(Status::Pending));
+        jobs.set_chunk(0, 0, "one", Status::Done, None).unwrap();
+        jobs.set_chunk(0, 1, "two", Status::Running, None).unwrap();
+        drop(jobs);
+        // killed while writing
+        fs::File::options()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .unwrap()
+            .write_all(br#"{"record":"chunk","epis"#)
+            .unwrap();
+
+        let jobs = JobStore::open(&dir, "Book", "settings", &plan).unwrap();
+        assert_eq!(jobs.chunk_status(0, 0, "one"), Some(Status::Done));
+        assert_eq!(jobs.chunk_status(0, 1, "two"), Some(Status::Pending));
+        assert_eq!(jobs.chunk_status(0, 1, "changed"), None);
+        assert_eq!(
+            jobs.progress(),
+            Progress {
+                pending: 1,
+                done: 1,
+                ..Progress::default()
+            }
+        );
+
+        jobs.set_chunk(0, 1, "two", Status::Done, None).unwrap();
+        jobs.set_episode(0, Status::Done).unwrap();
+        assert_eq!(jobs.episode_status(0, &episode), Some(Status::Done));
+        // a chunk edited since makes it pending again
+        assert_eq!(
+            jobs.episode_status(0, &chunks(&["one", "2"])),
+            Some(Status::Pending)
+        );
+        drop(jobs);
+
+        let jobs = JobStore::open(&dir, "Book", "other voice", &plan).unwrap();
+        assert_eq!(jobs.chunk_status(0, 0, "one"), None);
+        assert_eq!(jobs.episode_status(0, &episode), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn jobs_forget_what_left_the_plan() {
+        let dir = std::env::temp_dir().join("book2pod-jobs-plan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let first = chunks(&["one", "two"]);
+        let second = chunks(&["three"]);
+
+        let jobs = JobStore::open(&dir, "Book", "settings", &[&first, &second]).unwrap();
+        jobs.plan_episode(0, "First", &dir.join("0.mp3"), &first)
+            .unwrap();
+        jobs.plan_episode(1, "Second", &dir.join("1.mp3"), &second)
+            .unwrap();
+        jobs.set_chunk(0, 0, "one", Status::Done, None).unwrap();
+        jobs.set_chunk(0, 1, "two", Status::Done, None).unwrap();
+        jobs.set_chunk(1, 0, "three", Status::Failed, None).unwrap();
+        drop(jobs);
+
+        // the second chunk was edited and the second episode removed
+        let edited = chunks(&["one", "2"]);
+        let jobs = JobStore::open(&dir, "Book", "settings", &[&edited]).unwrap();
+        assert_eq!(jobs.chunk_status(0, 0, "one"), Some(Status::Done));
+        assert_eq!(jobs.chunk_status(0, 1, "two"), None);
+        assert_eq!(jobs.chunk_status(1, 0, "three"), None);
+        assert_eq!(jobs.episode_status(1, &second), None);
+        assert_eq!(
+            jobs.progress(),
+            Progress {
+                done: 1,
+                ..Progress::default()
+            }
+        );
+        let journal = fs::read_to_string(dir.join(JOURNAL_FILE)).unwrap();
+        assert_eq!(journal.lines().count(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
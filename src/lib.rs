@@ -1 +1,7 @@
+pub mod cache;
+pub mod config;
+pub mod feed;
+pub mod jobs;
+pub mod server;
 pub mod text_to_speach;
+mod util;
@@ -1,15 +1,432 @@
-async fn say_world() {
-    println!("world");
+use std::{fs, path::PathBuf, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, Result};
+use book2pod_rss::{
+    cache::{CachedClient, SynthesisCache, SynthesisSettings},
+    config::{Config, ProviderConfig},
+    feed,
+    jobs::JobStore,
+    server::{self, ServerState},
+    text_to_speach::{
+        chunker,
+        estimator::Estimate,
+        file_parser::{Book, UniversalFileParser},
+        planner::{self, EpisodePlan, PlannerOptions},
+        provider::{self, retry::RetryPolicy, TtsClientDispatcher, TtsProvider},
+        scheduler::{EpisodeJob, Scheduler},
+    },
+};
+use clap::{Args, Parser, Subcommand};
+use locale_codes::language::LanguageInfo;
+use tokio::net::TcpListener;
+
+#[derive(Parser)]
+#[command(name = "book2pod", version, about = "Turn a book into a podcast")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show how the book will be split in episodes without synthesizing anything
+    Plan {
+        /// Path to the book, see the README for the supported formats
+        book: PathBuf,
+        #[command(flatten)]
+        plan: PlanArgs,
+    },
+    /// Estimate the cost and listening time of converting a book, without synthesizing anything
+    Estimate {
+        /// Path to the book, see the README for the supported formats
+        book: PathBuf,
+        /// Text to speech provider: openai, elevenlabs, azure, polly, google, coqui, piper, espeak or gtts (demo only)
+        #[arg(short, long, default_value = "openai")]
+        provider: TtsProvider,
+        /// Provider settings (model, speed, price_per_million_chars), defaults to ./book2pod.toml when it exists
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[command(flatten)]
+        plan: PlanArgs,
+    },
+    /// Synthesize every episode of a book into a numbered audio file
+    Convert {
+        /// Path to the book, see the README for the supported formats
+        book: PathBuf,
+        /// Directory where the episodes are written
+        output_dir: PathBuf,
+        /// Text to speech provider: openai, elevenlabs, azure, polly, google, coqui, piper, espeak or gtts (demo only)
+        #[arg(short, long, default_value = "openai")]
+        provider: TtsProvider,
+        /// Synthesize without network access using espeak-ng, same as `--provider espeak`
+        #[arg(long, conflicts_with = "provider")]
+        offline: bool,
+        /// Audio format of the episodes, mp3 or wav, defaults to wav for piper, espeak and coqui and mp3 for the others
+        #[arg(long)]
+        format: Option<String>,
+        /// Provider settings (keys, model, voice, speed), defaults to ./book2pod.toml when it exists
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Url the output directory will be published at, when set a feed.xml is written too
+        #[arg(long)]
+        base_url: Option<String>,
+        /// Synthesize every chunk again instead of reusing the cached audio
+        #[arg(long)]
+        no_cache: bool,
+        #[command(flatten)]
+        plan: PlanArgs,
+    },
+    /// Serve the feed, cover and episodes of an already converted book
+    Serve {
+        /// Path to the book, see the README for the supported formats
+        book: PathBuf,
+        /// Directory where the episodes were written by convert
+        episodes_dir: PathBuf,
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        bind: String,
+        /// Public url of the server, defaults to the Host header of each request
+        #[arg(long)]
+        base_url: Option<String>,
+        #[command(flatten)]
+        plan: PlanArgs,
+    },
+    /// List the text to speech providers and what they support
+    Providers,
+    /// List the voices of a provider, what `voice` takes in the config
+    Voices {
+        provider: TtsProvider,
+        /// Only voices speaking this language, like `it`
+        #[arg(short, long)]
+        language: Option<String>,
+        /// Provider settings (keys, model, voice, speed), defaults to ./book2pod.toml when it exists
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Manage the audio cached by convert
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove the least recently used audio until the cache fits its size
+    Prune {
+        /// Size to shrink the cache to, defaults to `max_size_mb` of the config, 0 empties it
+        #[arg(long)]
+        max_size_mb: Option<u64>,
+        /// Cache settings, defaults to ./book2pod.toml when it exists
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
+
+/// How chapters are grouped in episodes, must match between convert and serve
+#[derive(Args)]
+struct PlanArgs {
+    /// Chapters with fewer words are merged with the following ones
+    #[arg(long, default_value_t = PlannerOptions::default().min_words)]
+    min_words: usize,
+    /// Chapters estimated to last longer are split in parts
+    #[arg(long, default_value_t = PlannerOptions::default().max_minutes)]
+    max_minutes: usize,
+    /// Narration speed used to estimate durations
+    #[arg(long, default_value_t = PlannerOptions::default().words_per_minute)]
+    words_per_minute: usize,
+    /// Also read front and back matter like titlepage, imprint and colophon
+    #[arg(long)]
+    keep_all: bool,
+}
+
+impl PlanArgs {
+    fn options(&self) -> PlannerOptions {
+        PlannerOptions {
+            min_words: self.min_words,
+            max_minutes: self.max_minutes,
+            words_per_minute: self.words_per_minute,
+            skip_front_and_back_matter: !self.keep_all,
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() {
-    // Calling `say_world()` does not execute the body of `say_world()`.
-    let op = say_world();
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Plan { book, plan } => {
+            let (_, plan) = plan_book(book, &plan)?;
+            print!("{plan}");
+            Ok(())
+        }
+        Command::Estimate {
+            book,
+            provider,
+            config,
+            plan,
+        } => {
+            let config = Config::load(config.as_deref())?;
+            let (_, plan) = plan_book(book, &plan)?;
+            print!(
+                "{}",
+                Estimate::new(&plan, provider, &config.provider(provider))
+            );
+            Ok(())
+        }
+        Command::Convert {
+            book,
+            output_dir,
+            provider,
+            offline,
+            format,
+            config,
+            base_url,
+            no_cache,
+            plan,
+        } => {
+            let provider = if offline {
+                TtsProvider::Espeak
+            } else {
+                provider
+            };
+            let config = Config::load(config.as_deref())?;
+            let cache = (!no_cache).then(|| SynthesisCache::from_config(config.cache()));
+            let format = format.unwrap_or(provider.audio_extension().to_owned());
+            match (provider.audio_extension(), format.as_str()) {
+                (native, format) if native == format => (),
+                // the wav parts are joined and encoded once
+                ("wav", "mp3") => provider::check_ffmpeg().await?,
+                (_, format) => return Err(anyhow!("{provider} can't write {format} episodes")),
+            }
+            convert(
+                book, output_dir, provider, &format, &config, cache, base_url, plan,
+            )
+            .await
+        }
+        Command::Serve {
+            book,
+            episodes_dir,
+            bind,
+            base_url,
+            plan,
+        } => serve(book, episodes_dir, bind, base_url, plan).await,
+        Command::Providers => {
+            for provider in TtsProvider::ALL {
+                let capabilities = provider
+                    .capabilities()
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<String>>();
+                println!("{provider}: {}", capabilities.join(", "));
+            }
+            Ok(())
+        }
+        Command::Voices {
+            provider,
+            language,
+            config,
+        } => {
+            let config = Config::load(config.as_deref())?;
+            let language = language
+                .map(|l| provider::book_language(&l).ok_or(anyhow!("unknown language {l}")))
+                .transpose()?;
+            for voice in provider
+                .list_voices(&config.provider(provider), language)
+                .await?
+            {
+                println!("{voice}");
+            }
+            Ok(())
+        }
+        Command::Cache {
+            command:
+                CacheCommand::Prune {
+                    max_size_mb,
+                    config,
+                },
+        } => {
+            let cache = SynthesisCache::from_config(Config::load(config.as_deref())?.cache());
+            let max_size = max_size_mb.map_or(cache.max_size(), |mb| mb * 1024 * 1024);
+            let pruned = cache.prune(max_size)?;
+            println!(
+                "removed {} files ({} MB) from {}, {} MB left",
+                pruned.removed,
+                pruned.freed / 1024 / 1024,
+                cache.dir().display(),
+                pruned.size / 1024 / 1024
+            );
+            Ok(())
+        }
+    }
+}
+
+fn plan_book(book: PathBuf, plan: &PlanArgs) -> Result<(Book, EpisodePlan)> {
+    let book = book
+        .to_str()
+        .ok_or(anyhow!("book path is not valid utf-8: {book:?}"))?;
+    let mut book = UniversalFileParser::parse_book(book)?;
+    let plan = planner::plan_episodes(std::mem::take(&mut book.chapters), &plan.options());
+    Ok((book, plan))
+}
+
+async fn serve(
+    book: PathBuf,
+    episodes_dir: PathBuf,
+    bind: String,
+    base_url: Option<String>,
+    plan: PlanArgs,
+) -> Result<()> {
+    let (book, plan) = plan_book(book, &plan)?;
+    let episodes = feed::episodes_in_dir(&plan.episodes, &episodes_dir)?;
+    let published = episodes
+        .iter()
+        .filter_map(|e| fs::metadata(episodes_dir.join(&e.file_name)).ok())
+        .filter_map(|m| m.modified().ok())
+        .max()
+        .unwrap_or(SystemTime::now());
+
+    let listener = TcpListener::bind(&bind).await?;
+    println!(
+        "serving {} episodes on http://{}/feed.xml",
+        episodes.len(),
+        listener.local_addr()?
+    );
+    server::serve(
+        listener,
+        ServerState {
+            metadata: book.metadata,
+            cover: book.cover,
+            episodes,
+            episodes_dir,
+            base_url,
+            published,
+        },
+    )
+    .await
+}
+
+/// Built on a blocking thread, espeak runs its binary and coqui asks its
+/// server to check the voice
+async fn new_client(
+    provider: TtsProvider,
+    config: &ProviderConfig,
+    language: Option<&'static LanguageInfo>,
+) -> Result<TtsClientDispatcher> {
+    let config = config.clone();
+    let client = tokio::task::spawn_blocking(move || provider.client(&config, language)).await??;
+    Ok(client)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn convert(
+    book: PathBuf,
+    output_dir: PathBuf,
+    provider: TtsProvider,
+    format: &str,
+    config: &Config,
+    cache: Option<SynthesisCache>,
+    base_url: Option<String>,
+    plan: PlanArgs,
+) -> Result<()> {
+    let provider_config = config.provider(provider);
+    // fails on missing credentials before reading the book
+    new_client(provider, &provider_config, None).await?;
+    let (book, plan) = plan_book(book, &plan)?;
+    print!("{plan}");
+    // voices are picked for the language of the book, and fail when they can't speak it
+    let language = book
+        .metadata
+        .lang
+        .as_deref()
+        .and_then(provider::book_language);
+    let client = new_client(provider, &provider_config, language).await?;
+    fs::create_dir_all(&output_dir)?;
+    let settings = SynthesisSettings::new(
+        provider,
+        &provider_config,
+        language.and_then(|l| l.short_code.clone()),
+    );
+    // the audio of other settings is no good to resume from
+    let fingerprint = settings.key("", None, None, format);
+
+    let limits = provider.limits(&provider_config);
+    let client = CachedClient::new(client, cache.clone(), settings);
 
-    // This println! comes first
-    println!("hello");
+    let episodes = plan
+        .episodes
+        .iter()
+        .map(|episode| EpisodeJob {
+            chunks: chunker::split_text(&episode.text, provider.max_input_length()),
+            path: output_dir.join(feed::episode_file_name(episode.number, format)),
+            part_extension: provider.audio_extension(),
+        })
+        .collect::<Vec<EpisodeJob>>();
+    // a run that was killed or failed goes on from where it stopped
+    let title = book.metadata.title.clone().unwrap_or_default();
+    let chunks = episodes
+        .iter()
+        .map(|job| job.chunks.as_slice())
+        .collect::<Vec<&[String]>>();
+    let jobs = JobStore::open(&output_dir, &title, &fingerprint, &chunks)?;
+    for (i, (episode, job)) in plan.episodes.iter().zip(&episodes).enumerate() {
+        jobs.plan_episode(i, &episode.title, &job.path, &job.chunks)?;
+    }
+    let progress = jobs.progress();
+    if progress.done > 0 {
+        println!(
+            "resuming, {} of {} chunks already synthesized",
+            progress.done,
+            progress.done + progress.pending + progress.failed
+        );
+    }
+    let total = episodes.len();
+    println!(
+        "synthesizing {total} episodes, {} requests at a time",
+        limits.concurrency
+    );
+    let mut done = 0;
+    let synthesized = Scheduler::new(client, limits)
+        // rate limits and hiccups of the provider shouldn't kill a long book
+        .with_retry(RetryPolicy::default())
+        .with_jobs(Arc::new(jobs))
+        .speak_episodes(episodes, |i| {
+            done += 1;
+            let episode = &plan.episodes[i];
+            println!(
+                "[{done}/{total}] {} -> {}",
+                episode.title,
+                feed::episode_file_name(episode.number, format)
+            );
+        })
+        .await;
+    // evicted once per run, not while the chunks are stored
+    if let Some(cache) = cache {
+        if let Err(e) = cache.prune(cache.max_size()) {
+            eprintln!("can't prune the cache in {}: {e}", cache.dir().display());
+        }
+    }
+    synthesized?;
 
-    // Calling `.await` on `op` starts executing `say_world`.
-    op.await;
+    if let Some(base_url) = base_url {
+        if let Some(cover) = &book.cover {
+            fs::write(
+                output_dir.join(feed::cover_file_name(cover)),
+                &cover.content,
+            )?;
+        }
+        let episodes = feed::episodes_in_dir(&plan.episodes, &output_dir)?;
+        let feed_path = output_dir.join("feed.xml");
+        feed::write_feed(
+            fs::File::create(&feed_path)?,
+            &base_url,
+            &book.metadata,
+            book.cover.as_ref(),
+            &episodes,
+            SystemTime::now(),
+        )?;
+        println!("feed written to {}", feed_path.display());
+    }
+    Ok(())
 }
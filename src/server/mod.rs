@@ -0,0 +1,190 @@
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tokio::net::TcpListener;
+use tower_http::services::ServeFile;
+
+use crate::{
+    feed::{self, Episode},
+    text_to_speach::file_parser::{Cover, Metadata},
+};
+
+/// Everything needed to publish a converted book as a podcast.
+pub struct ServerState {
+    pub metadata: Metadata,
+    pub cover: Option<Cover>,
+    pub episodes: Vec<Episode>,
+    /// Directory holding the episode audio files
+    pub episodes_dir: PathBuf,
+    /// Public url of the server, when missing it's derived from the `Host` header
+    pub base_url: Option<String>,
+    pub published: SystemTime,
+}
+
+/// Serves `/feed.xml`, the cover and the episode files, anything else in the
+/// episodes directory (the job journal, parts left by a failed run) is a 404.
+///
+/// Episodes are served with HTTP range support so players can seek and resume.
+pub fn router(state: ServerState) -> Router {
+    let cover_path = state
+        .cover
+        .as_ref()
+        .map(|cover| format!("/{}", feed::cover_file_name(cover)));
+
+    let mut router = Router::new().route("/feed.xml", get(get_feed));
+    if let Some(cover_path) = cover_path {
+        router = router.route(&cover_path, get(get_cover));
+    }
+    for episode in &state.episodes {
+        router = router.route_service(
+            &format!("/{}", episode.file_name),
+            ServeFile::new(state.episodes_dir.join(&episode.file_name)),
+        );
+    }
+    router.with_state(Arc::new(state))
+}
+
+pub async fn serve(listener: TcpListener, state: ServerState) -> Result<()> {
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+async fn get_feed(State(state): State<Arc<ServerState>>, headers: HeaderMap) -> Response {
+    let base_url = match &state.base_url {
+        Some(base_url) => base_url.clone(),
+        None => {
+            let host = headers
+                .get(header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("localhost");
+            format!("http://{host}")
+        }
+    };
+
+    let mut body = vec![];
+    let result = feed::write_feed(
+        &mut body,
+        &base_url,
+        &state.metadata,
+        state.cover.as_ref(),
+        &state.episodes,
+        state.published,
+    );
+    match result {
+        Ok(()) => ([(header::CONTENT_TYPE, "application/rss+xml")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_cover(State(state): State<Arc<ServerState>>) -> Response {
+    match &state.cover {
+        Some(cover) => (
+            [(header::CONTENT_TYPE, cover.mime.clone())],
+            cover.content.clone(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn start_server(name: &str) -> (String, PathBuf) {
+        let episodes_dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&episodes_dir).unwrap();
+        let audio = (0..100).collect::<Vec<u8>>();
+        std::fs::write(episodes_dir.join("001.mp3"), &audio).unwrap();
+        std::fs::write(episodes_dir.join(crate::jobs::JOURNAL_FILE), "{}").unwrap();
+        std::fs::write(episodes_dir.join("002.part0.mp3"), &audio).unwrap();
+
+        let state = ServerState {
+            metadata: Metadata {
+                authors: vec!["Author".to_owned()],
+                title: Some("Title".to_owned()),
+                publisher: None,
+                description: None,
+                lang: None,
+            },
+            cover: Some(Cover {
+                mime: "image/png".to_owned(),
+                content: vec![1, 2, 3],
+            }),
+            episodes: vec![
+                Episode::from_file(1, "One".to_owned(), &episodes_dir.join("001.mp3")).unwrap(),
+            ],
+            episodes_dir: episodes_dir.clone(),
+            base_url: None,
+            published: SystemTime::now(),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, state));
+        (address, episodes_dir)
+    }
+
+    #[tokio::test]
+    async fn serves_feed_and_cover() {
+        let (address, episodes_dir) = start_server("book2pod-server-feed").await;
+
+        let feed = reqwest::get(format!("{address}/feed.xml")).await.unwrap();
+        assert_eq!(feed.headers()[header::CONTENT_TYPE], "application/rss+xml");
+        let feed = feed.text().await.unwrap();
+        assert!(feed.contains(&format!(r#"<enclosure url="{address}/001.mp3""#)));
+        assert!(feed.contains(&format!(r#"<itunes:image href="{address}/cover.png""#)));
+
+        let cover = reqwest::get(format!("{address}/cover.png")).await.unwrap();
+        assert_eq!(cover.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(cover.bytes().await.unwrap().as_ref(), &[1, 2, 3]);
+
+        std::fs::remove_dir_all(episodes_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_episode_ranges() {
+        let (address, episodes_dir) = start_server("book2pod-server-range").await;
+        let client = reqwest::Client::new();
+
+        let full = client
+            .get(format!("{address}/001.mp3"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(full.bytes().await.unwrap().len(), 100);
+
+        let partial = client
+            .get(format!("{address}/001.mp3"))
+            .header(header::RANGE, "bytes=10-19")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers()[header::CONTENT_RANGE], "bytes 10-19/100");
+        assert_eq!(
+            partial.bytes().await.unwrap().as_ref(),
+            (10..20).collect::<Vec<u8>>().as_slice()
+        );
+
+        // only episodes of the feed are served
+        for missing in ["002.mp3", "002.part0.mp3", crate::jobs::JOURNAL_FILE] {
+            let missing = client
+                .get(format!("{address}/{missing}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        }
+
+        std::fs::remove_dir_all(episodes_dir).unwrap();
+    }
+}
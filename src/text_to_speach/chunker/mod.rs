@@ -0,0 +1,230 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::provider::{write_wav, TtsError};
+
+pub mod wav;
+
+/// Splits `text` in chunks of at most `max_len` characters.
+///
+/// Paragraphs are kept together when they fit, otherwise they are split on
+/// sentence boundaries, then on words and as a last resort in the middle of a
+/// word.
+pub fn split_text(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+    for paragraph in text.split('\n').map(str::trim).filter(|p| !p.is_empty()) {
+        for (i, piece) in split_paragraph(paragraph, max_len).into_iter().enumerate() {
+            let separator = if i == 0 { "\n" } else { " " };
+            push_piece(&mut chunks, &mut current, &piece, separator, max_len);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn push_piece(
+    chunks: &mut Vec<String>,
+    current: &mut String,
+    piece: &str,
+    separator: &str,
+    max_len: usize,
+) {
+    if current.is_empty() {
+        current.push_str(piece);
+    } else if char_len(current) + char_len(separator) + char_len(piece) <= max_len {
+        current.push_str(separator);
+        current.push_str(piece);
+    } else {
+        chunks.push(std::mem::take(current));
+        current.push_str(piece);
+    }
+}
+
+/// Splits a paragraph in pieces that are at most `max_len` characters long
+fn split_paragraph(paragraph: &str, max_len: usize) -> Vec<String> {
+    if char_len(paragraph) <= max_len {
+        return vec![paragraph.to_owned()];
+    }
+
+    let mut pieces = vec![];
+    for sentence in split_sentences(paragraph) {
+        if char_len(sentence) <= max_len {
+            pieces.push(sentence.to_owned());
+            continue;
+        }
+        let mut current = String::new();
+        for word in sentence.split_whitespace() {
+            if char_len(word) <= max_len {
+                push_piece(&mut pieces, &mut current, word, " ", max_len);
+                continue;
+            }
+            let chars = word.chars().collect::<Vec<char>>();
+            for part in chars.chunks(max_len) {
+                push_piece(
+                    &mut pieces,
+                    &mut current,
+                    &part.iter().collect::<String>(),
+                    "",
+                    max_len,
+                );
+            }
+        }
+        if !current.is_empty() {
+            pieces.push(current);
+        }
+    }
+    pieces
+}
+
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?' | '…' | ';') {
+            continue;
+        }
+        if let Some((next_i, next)) = chars.peek() {
+            if next.is_whitespace() {
+                sentences.push(text[start..*next_i].trim());
+                start = *next_i;
+            }
+        }
+    }
+    sentences.push(text[start..].trim());
+    sentences.into_iter().filter(|s| !s.is_empty()).collect()
+}
+
+/// Where chunk `index` of `path` is synthesized, as the `extension` the
+/// provider makes, before the parts are joined
+pub fn part_path(path: &Path, index: usize, extension: &str) -> PathBuf {
+    path.with_extension(format!("part{index}.{extension}"))
+}
+
+/// Concatenates audio files by appending their bytes, this works for frame
+/// based formats like mp3. Wav parts get a single header for all of them,
+/// and are encoded once when `output` is not a wav.
+pub async fn concat_audio_files<P: AsRef<Path>>(
+    parts: &[P],
+    output: &Path,
+) -> Result<(), TtsError> {
+    if parts
+        .iter()
+        .all(|p| p.as_ref().extension().is_some_and(|e| e == "wav"))
+    {
+        let parts = parts
+            .iter()
+            .map(fs::read)
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        let wav = wav::concat_wav(&parts).map_err(|e| TtsError::NoContent(e.to_string()))?;
+        return write_wav(wav, &output.to_string_lossy()).await;
+    }
+
+    let mut file =
+        fs::File::create(output).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))?;
+    for part in parts {
+        let bytes = fs::read(part).map_err(|e| TtsError::NoContent(e.to_string()))?;
+        file.write_all(&bytes)
+            .map_err(|e| TtsError::WriteToFileFailure(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        let chunks = split_text("Hello there.\nGeneral Kenobi.", 100);
+
+        assert_eq!(chunks, vec!["Hello there.\nGeneral Kenobi."]);
+    }
+
+    #[test]
+    fn paragraphs_are_packed() {
+        let chunks = split_text("aaaa aaaa\n\nbbbb bbbb\ncccc cccc\n", 20);
+
+        assert_eq!(chunks, vec!["aaaa aaaa\nbbbb bbbb", "cccc cccc"]);
+    }
+
+    #[test]
+    fn long_paragraph_splits_on_sentences() {
+        let text = "The first sentence is here. The second one follows! Is this the third? Yes.";
+        let chunks = split_text(text, 30);
+
+        assert_eq!(
+            chunks,
+            vec![
+                "The first sentence is here.",
+                "The second one follows!",
+                "Is this the third? Yes."
+            ]
+        );
+    }
+
+    #[test]
+    fn long_sentence_splits_on_words() {
+        let chunks = split_text("one two three four five six seven", 10);
+
+        assert_eq!(chunks, vec!["one two", "three four", "five six", "seven"]);
+        assert!(chunks.iter().all(|c| char_len(c) <= 10));
+    }
+
+    #[test]
+    fn long_word_is_cut() {
+        let chunks = split_text("àààààààààààà", 5);
+
+        assert_eq!(chunks, vec!["ààààà", "ààààà", "àà"]);
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(split_text(" \n\n \n", 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn concat() {
+        let parts = ["chunker.concat.0.mp3", "chunker.concat.1.mp3"];
+        fs::write(parts[0], [1, 2]).unwrap();
+        fs::write(parts[1], [3]).unwrap();
+        let output = Path::new("chunker.concat.mp3");
+
+        concat_audio_files(&parts, output).await.unwrap();
+        let bytes = fs::read(output).unwrap();
+        for path in parts {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(output).unwrap();
+
+        assert_eq!(bytes, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn concat_wav() {
+        let parts = ["chunker.concat.0.wav", "chunker.concat.1.wav"];
+        fs::write(parts[0], wav::pcm_to_wav(&[1, 2], 16000, 1, 16)).unwrap();
+        fs::write(parts[1], wav::pcm_to_wav(&[3, 4], 16000, 1, 16)).unwrap();
+        let output = Path::new("chunker.concat.wav");
+
+        concat_audio_files(&parts, output).await.unwrap();
+        let bytes = fs::read(output).unwrap();
+        for path in parts {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(output).unwrap();
+
+        assert_eq!(bytes, wav::pcm_to_wav(&[1, 2, 3, 4], 16000, 1, 16));
+    }
+}
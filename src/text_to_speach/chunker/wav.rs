@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+
+/// Wraps raw little endian pcm samples in a wav container
+pub fn pcm_to_wav(pcm: &[u8], sample_rate: u32, channels: u16, bits_per_sample: u16) -> Vec<u8> {
+    let block_align = channels * bits_per_sample / 8;
+    let mut fmt = vec![];
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+    write_wav(&fmt, pcm)
+}
+
+/// Joins wav files sharing the same format in a single one, the headers of
+/// the files can't just be appended like mp3 frames.
+pub fn concat_wav<B: AsRef<[u8]>>(files: &[B]) -> Result<Vec<u8>> {
+    let mut format: Option<&[u8]> = None;
+    let mut data = vec![];
+    for file in files {
+        let (fmt, samples) = read_wav(file.as_ref())?;
+        match format {
+            None => format = Some(fmt),
+            Some(format) if format != fmt => {
+                return Err(anyhow!("wav files have different formats"));
+            }
+            Some(_) => (),
+        }
+        data.extend_from_slice(samples);
+    }
+    let format = format.ok_or(anyhow!("no wav file to join"))?;
+    Ok(write_wav(format, &data))
+}
+
+fn write_wav(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(data.len() + fmt.len() + 20);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&((4 + 8 + fmt.len() + 8 + data.len()) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+    wav.extend_from_slice(fmt);
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(data);
+    wav
+}
+
+/// Returns the `fmt ` and `data` chunks of a wav file
+fn read_wav(bytes: &[u8]) -> Result<(&[u8], &[u8])> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(anyhow!("not a wav file"));
+    }
+
+    let mut fmt = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into()?) as usize;
+        let start = position + 8;
+        // streamed wavs can have a placeholder size for the data chunk
+        let end = (start + size).min(bytes.len());
+        match id {
+            b"fmt " => fmt = Some(&bytes[start..end]),
+            b"data" => {
+                let fmt = fmt.ok_or(anyhow!("wav data before its format"))?;
+                return Ok((fmt, &bytes[start..end]));
+            }
+            _ => (),
+        }
+        position = end + size % 2;
+    }
+    Err(anyhow!("wav file without data"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wav_round_trip() {
+        let wav = pcm_to_wav(&[1, 2, 3, 4], 22050, 1, 16);
+
+        assert_eq!(wav.len(), 44 + 4);
+        assert_eq!(&wav[0..4], b"RIFF");
+        let (fmt, data) = read_wav(&wav).unwrap();
+        assert_eq!(fmt.len(), 16);
+        assert_eq!(data, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn joins_wavs() {
+        let first = pcm_to_wav(&[1, 2], 22050, 1, 16);
+        let second = pcm_to_wav(&[3, 4, 5, 6], 22050, 1, 16);
+
+        let joined = concat_wav(&[first, second]).unwrap();
+
+        assert_eq!(joined, pcm_to_wav(&[1, 2, 3, 4, 5, 6], 22050, 1, 16));
+        let other_rate = pcm_to_wav(&[1, 2], 16000, 1, 16);
+        assert!(concat_wav(&[joined, other_rate]).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt::Display;
+
+use crate::config::ProviderConfig;
+
+use super::{
+    chunker,
+    planner::EpisodePlan,
+    provider::{SpeechSpeed, TtsProvider},
+};
+
+/// What converting a plan would cost and last, without making any request
+#[derive(Debug)]
+pub struct Estimate {
+    pub provider: TtsProvider,
+    pub episodes: Vec<EpisodeEstimate>,
+    /// Dollars per million characters, None when we don't know it
+    pub price_per_million_chars: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct EpisodeEstimate {
+    pub number: usize,
+    pub title: String,
+    /// Billed characters, what is sent in the chunks
+    pub characters: usize,
+    pub requests: usize,
+    pub minutes: f64,
+}
+
+impl EpisodeEstimate {
+    pub fn cost(&self, price_per_million_chars: Option<f64>) -> Option<f64> {
+        price_per_million_chars.map(|price| self.characters as f64 * price / 1_000_000.0)
+    }
+}
+
+impl Estimate {
+    /// Chunks every episode like convert would for `provider`, the minutes
+    /// come from the words per minute of the plan at the speed of `config`,
+    /// as `provider` reads it
+    pub fn new(plan: &EpisodePlan, provider: TtsProvider, config: &ProviderConfig) -> Self {
+        let speed = provider.speed_factor(config.speed.unwrap_or(SpeechSpeed::Normal));
+        let episodes = plan
+            .episodes
+            .iter()
+            .map(|episode| {
+                let chunks = chunker::split_text(&episode.text, provider.max_input_length());
+                EpisodeEstimate {
+                    number: episode.number,
+                    title: episode.title.clone(),
+                    characters: chunks.iter().map(|c| c.chars().count()).sum(),
+                    requests: chunks.len(),
+                    minutes: episode.estimated_minutes(plan.words_per_minute) / speed,
+                }
+            })
+            .collect();
+        Self {
+            provider,
+            episodes,
+            price_per_million_chars: provider.price_per_million_chars(config),
+        }
+    }
+
+    pub fn total_characters(&self) -> usize {
+        self.episodes.iter().map(|e| e.characters).sum()
+    }
+
+    pub fn total_minutes(&self) -> f64 {
+        self.episodes.iter().map(|e| e.minutes).sum()
+    }
+
+    pub fn total_cost(&self) -> Option<f64> {
+        self.price_per_million_chars
+            .map(|price| self.total_characters() as f64 * price / 1_000_000.0)
+    }
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    cost.map_or("?".to_owned(), |cost| format!("${cost:.2}"))
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>4}  {:>9}  {:>8}  {:>6}  {:>8}  title",
+            "#", "chars", "requests", "~min", "cost"
+        )?;
+        for episode in &self.episodes {
+            writeln!(
+                f,
+                "{:>4}  {:>9}  {:>8}  {:>6.1}  {:>8}  {}",
+                episode.number,
+                episode.characters,
+                episode.requests,
+                episode.minutes,
+                format_cost(episode.cost(self.price_per_million_chars)),
+                episode.title
+            )?;
+        }
+        writeln!(
+            f,
+            "{} episodes, {} characters in {} requests, ~{:.0} minutes, {} with {}",
+            self.episodes.len(),
+            self.total_characters(),
+            self.episodes.iter().map(|e| e.requests).sum::<usize>(),
+            self.total_minutes(),
+            format_cost(self.total_cost()),
+            self.provider
+        )?;
+        if self.price_per_million_chars.is_none() {
+            writeln!(
+                f,
+                "set price_per_million_chars in [providers.{}] to estimate the cost",
+                self.provider
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::text_to_speach::{
+        file_parser::Chapter,
+        planner::{plan_episodes, PlannerOptions},
+    };
+
+    use super::*;
+
+    #[test]
+    fn estimate_cost_and_minutes() {
+        let chapters = ["One", "Two"]
+            .iter()
+            .map(|name| Chapter {
+                id: format!("{name}.xhtml"),
+                order: 0,
+                name: name.to_string(),
+                // 3000 words, 29999 characters
+                text: vec!["wordwordw"; 3000].join(" "),
+            })
+            .collect();
+        let plan = plan_episodes(chapters, &PlannerOptions::default());
+        let config = ProviderConfig {
+            speed: Some(SpeechSpeed::VeryQuick),
+            ..ProviderConfig::default()
+        };
+
+        let estimate = Estimate::new(&plan, TtsProvider::OpenAi, &config);
+        assert_eq!(estimate.episodes.len(), 2);
+        let episode = &estimate.episodes[0];
+        // the chunks lose the spaces they are split on
+        assert!(episode.characters <= 29999 && episode.characters > 29900);
+        assert!(episode.requests >= 29999_usize.div_ceil(TtsProvider::OpenAi.max_input_length()));
+        // openai reads very quick at 1.5x
+        assert!((episode.minutes - 3000.0 / 150.0 / 1.5).abs() < 0.01);
+        let cost = estimate.total_cost().unwrap();
+        assert!((cost - estimate.total_characters() as f64 * 15.0 / 1_000_000.0).abs() < 1e-9);
+
+        let config = ProviderConfig {
+            price_per_million_chars: Some(100.0),
+            ..ProviderConfig::default()
+        };
+        let estimate = Estimate::new(&plan, TtsProvider::ElevenLabs, &config);
+        assert!((estimate.episodes[0].minutes - 20.0).abs() < 0.01);
+        assert!(estimate.total_cost().unwrap() > 5.9);
+        let estimate = Estimate::new(&plan, TtsProvider::ElevenLabs, &ProviderConfig::default());
+        assert_eq!(estimate.total_cost(), None);
+        assert!(estimate.to_string().contains("[providers.elevenlabs]"));
+
+        // coqui has no speed, a compatible server has no list price
+        let config = ProviderConfig {
+            speed: Some(SpeechSpeed::VeryQuick),
+            base_url: Some("http://localhost:8880/v1".to_owned()),
+            ..ProviderConfig::default()
+        };
+        let estimate = Estimate::new(&plan, TtsProvider::Coqui, &config);
+        assert!((estimate.episodes[0].minutes - 20.0).abs() < 0.01);
+        assert_eq!(estimate.total_cost(), Some(0.0));
+        assert_eq!(
+            Estimate::new(&plan, TtsProvider::OpenAi, &config).total_cost(),
+            None
+        );
+    }
+}
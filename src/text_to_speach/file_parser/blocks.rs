@@ -0,0 +1,82 @@
+use anyhow::{anyhow, Result};
+
+use super::Content;
+
+/// Text of a single file book, the ids used in the table of contents are the
+/// index of the block where the chapter starts.
+pub(super) enum Block {
+    /// Start of a chapter, used as table of contents entry
+    Heading(String),
+    Text(String),
+}
+
+pub(super) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Pushes `line` as a text block, unless it is blank, and clears it.
+pub(super) fn flush_line(blocks: &mut Vec<Block>, line: &mut String) {
+    let text = collapse_whitespace(line);
+    if !text.is_empty() {
+        blocks.push(Block::Text(text));
+    }
+    line.clear();
+}
+
+pub(super) fn first_heading(blocks: &[Block]) -> Option<String> {
+    blocks.iter().find_map(|b| match b {
+        Block::Heading(heading) => Some(heading.clone()),
+        Block::Text(_) => None,
+    })
+}
+
+/// One entry for every heading, text before the first heading gets an entry
+/// named `opening_name`, or "Opening" when missing.
+pub(super) fn table_of_contents(blocks: &[Block], opening_name: Option<String>) -> Vec<Content> {
+    let mut table_of_contents = vec![];
+    if matches!(blocks.first(), Some(Block::Text(_))) {
+        table_of_contents.push((0, opening_name.unwrap_or("Opening".to_owned())));
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        if let Block::Heading(heading) = block {
+            table_of_contents.push((i, heading.clone()));
+        }
+    }
+
+    table_of_contents
+        .into_iter()
+        .enumerate()
+        .map(|(i, (block, name))| Content {
+            id: block.to_string(),
+            order: i + 1,
+            name,
+        })
+        .collect()
+}
+
+pub(super) fn extract_text(
+    blocks: &[Block],
+    from_id: String,
+    to_id: Option<String>,
+) -> Result<String> {
+    let from = from_id
+        .parse::<usize>()
+        .map_err(|_| anyhow!("id is not correct {from_id}"))?;
+    let to = match to_id {
+        Some(id) => id
+            .parse::<usize>()
+            .map_err(|_| anyhow!("id is not correct {id}"))?,
+        None => blocks.len(),
+    };
+
+    let blocks = blocks
+        .get(from..to.clamp(from, blocks.len()))
+        .ok_or(anyhow!("id is out of the text {from_id}"))?;
+    Ok(blocks
+        .iter()
+        .map(|b| match b {
+            Block::Heading(text) | Block::Text(text) => text.as_str(),
+        })
+        .collect::<Vec<&str>>()
+        .join("\n"))
+}
@@ -0,0 +1,292 @@
+use std::{collections::HashSet, io::Read, marker::PhantomData};
+
+use anyhow::{anyhow, Result};
+use xml::reader::XmlEvent;
+
+use super::{
+    blocks::{self, collapse_whitespace, flush_line, Block},
+    office::{document_metadata, open_archive, read_entry},
+    xml_attribute, Content, Cover, FileParserV2, Metadata,
+};
+
+/// Elements never read: text boxes and drawings (read twice because of
+/// their fallback copy) and tracked deletions
+const SKIPPED_ELEMENTS: &[&str] = &["txbxContent", "AlternateContent", "pict", "del"];
+
+/// Parser for Word documents, chapters start at `Heading 1` to `Heading 3`
+/// paragraphs.
+pub struct DocxParser<R> {
+    blocks: Vec<Block>,
+    metadata: Metadata,
+    reader: PhantomData<R>,
+}
+
+impl<R> FileParserV2<R> for DocxParser<R>
+where
+    R: Read,
+{
+    fn from_reader(input: R) -> Result<Self> {
+        let mut archive = open_archive(input)?;
+        let document = read_entry(&mut archive, "word/document.xml")?
+            .ok_or(anyhow!("word/document.xml is missing, not a docx file"))?;
+        let styles = match read_entry(&mut archive, "word/styles.xml")? {
+            Some(styles) => read_styles(&styles)?,
+            None => Styles::default(),
+        };
+        let metadata = match read_entry(&mut archive, "docProps/core.xml")? {
+            Some(core) => document_metadata(&core)?,
+            None => Metadata::default(),
+        };
+
+        Ok(Self {
+            blocks: read_document(&document, &styles)?,
+            metadata,
+            reader: PhantomData,
+        })
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        Ok(blocks::table_of_contents(
+            &self.blocks,
+            self.metadata.title.clone(),
+        ))
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        blocks::extract_text(&self.blocks, from_id, to_id)
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        None
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+/// Paragraph style ids by role, ids are localized so they are looked up by
+/// the style name in `word/styles.xml`
+struct Styles {
+    headings: HashSet<String>,
+    table_of_contents: HashSet<String>,
+}
+
+impl Default for Styles {
+    fn default() -> Self {
+        Self {
+            headings: ["Heading1", "Heading2", "Heading3"]
+                .map(|s| s.to_owned())
+                .into(),
+            table_of_contents: HashSet::new(),
+        }
+    }
+}
+
+fn read_styles(xml: &str) -> Result<Styles> {
+    let mut styles = Styles::default();
+    let mut style_id = None;
+
+    for event in xml::reader::EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "style" => style_id = xml_attribute(&attributes, "styleId").map(|s| s.to_owned()),
+                "name" => {
+                    let (Some(id), Some(name)) = (&style_id, xml_attribute(&attributes, "val"))
+                    else {
+                        continue;
+                    };
+                    let name = name.to_lowercase();
+                    if matches!(name.as_str(), "heading 1" | "heading 2" | "heading 3") {
+                        styles.headings.insert(id.clone());
+                    } else if name.starts_with("toc ") || name == "toc heading" {
+                        styles.table_of_contents.insert(id.clone());
+                    }
+                }
+                _ => (),
+            },
+            XmlEvent::EndElement { name } if name.local_name == "style" => style_id = None,
+            _ => (),
+        }
+    }
+    Ok(styles)
+}
+
+fn read_document(xml: &str, styles: &Styles) -> Result<Vec<Block>> {
+    let mut blocks = vec![];
+    let mut paragraph = String::new();
+    let mut style: Option<String> = None;
+    let mut run = String::new();
+    let mut run_is_note_anchor = false;
+    let mut stack: Vec<String> = vec![];
+    // depth of the stack where the skipped element started
+    let mut skipped_from: Option<usize> = None;
+
+    for event in xml::reader::EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                if skipped_from.is_none() && SKIPPED_ELEMENTS.contains(&name.as_str()) {
+                    skipped_from = Some(stack.len());
+                }
+                if skipped_from.is_none() {
+                    match name.as_str() {
+                        "p" => {
+                            paragraph.clear();
+                            style = None;
+                        }
+                        "pStyle" => style = xml_attribute(&attributes, "val").map(|s| s.to_owned()),
+                        "r" => {
+                            run.clear();
+                            run_is_note_anchor = false;
+                        }
+                        "footnoteReference" | "endnoteReference" => run_is_note_anchor = true,
+                        "tab" | "br" | "cr" if stack.last().is_some_and(|p| p == "r") => {
+                            run.push(' ')
+                        }
+                        _ => (),
+                    }
+                }
+                stack.push(name);
+            }
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text)
+                if skipped_from.is_none() && stack.last().is_some_and(|e| e == "t") =>
+            {
+                run.push_str(&text)
+            }
+            XmlEvent::EndElement { .. } => {
+                let name = stack.pop().unwrap_or_default();
+                if skipped_from == Some(stack.len()) {
+                    skipped_from = None;
+                    continue;
+                }
+                if skipped_from.is_some() {
+                    continue;
+                }
+                match name.as_str() {
+                    "r" if !run_is_note_anchor => paragraph.push_str(&run),
+                    "p" => match &style {
+                        Some(style) if styles.table_of_contents.contains(style) => (),
+                        Some(style) if styles.headings.contains(style) => {
+                            let heading = collapse_whitespace(&paragraph);
+                            if !heading.is_empty() {
+                                blocks.push(Block::Heading(heading));
+                            }
+                        }
+                        _ => flush_line(&mut blocks, &mut paragraph),
+                    },
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:body>
+    <w:p><w:pPr><w:pStyle w:val="TOC1"/></w:pPr><w:r><w:t>Chapter One 1</w:t></w:r></w:p>
+    <w:p><w:r><w:t>Before the first heading.</w:t></w:r></w:p>
+    <w:p><w:pPr><w:pStyle w:val="Titre1"/></w:pPr><w:r><w:t>Chapter One</w:t></w:r></w:p>
+    <w:p>
+      <w:r><w:t xml:space="preserve">Some </w:t></w:r>
+      <w:r><w:rPr><w:b/></w:rPr><w:t>bold</w:t></w:r>
+      <w:r><w:rPr><w:rStyle w:val="FootnoteReference"/></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="1"/><w:t>*</w:t></w:r>
+      <w:r><w:tab/><w:t>text.</w:t></w:r>
+    </w:p>
+    <w:p><w:r><w:delText>deleted</w:delText></w:r><w:del><w:r><w:t>gone</w:t></w:r></w:del></w:p>
+    <w:p><w:pPr><w:pStyle w:val="Heading4"/></w:pPr><w:r><w:t>Not a chapter</w:t></w:r></w:p>
+    <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Chapter </w:t></w:r><w:r><w:t>Two</w:t></w:r></w:p>
+    <w:tbl><w:tr><w:tc><w:p><w:r><w:t>a cell</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+  </w:body>
+</w:document>"#;
+
+    const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+  <w:style w:type="paragraph" w:styleId="Titre1"><w:name w:val="heading 1"/></w:style>
+  <w:style w:type="paragraph" w:styleId="Heading4"><w:name w:val="heading 4"/></w:style>
+  <w:style w:type="paragraph" w:styleId="TOC1"><w:name w:val="toc 1"/></w:style>
+</w:styles>"#;
+
+    const CORE: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <dc:title>A Draft</dc:title>
+  <dc:subject>About drafts</dc:subject>
+  <dc:creator>Jane Doe; John Roe</dc:creator>
+  <dc:language>en-US</dc:language>
+</cp:coreProperties>"#;
+
+    fn docx() -> Vec<u8> {
+        let mut archive = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in [
+            ("word/document.xml", DOCUMENT),
+            ("word/styles.xml", STYLES),
+            ("docProps/core.xml", CORE),
+        ] {
+            archive
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(content.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn docx_toc() {
+        let mut parser = DocxParser::from_reader(Cursor::new(docx())).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(names, vec!["A Draft", "Chapter One", "Chapter Two"]);
+    }
+
+    #[test]
+    fn docx_text() {
+        let mut parser = DocxParser::from_reader(Cursor::new(docx())).unwrap();
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[0].id.clone(), Some(toc[1].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Before the first heading.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[1].id.clone(), Some(toc[2].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Chapter One\nSome bold text.\nNot a chapter");
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), None)
+            .unwrap();
+        assert_eq!(text, "Chapter Two\na cell");
+    }
+
+    #[test]
+    fn docx_metadata() {
+        let mut parser = DocxParser::from_reader(Cursor::new(docx())).unwrap();
+
+        let metadata = parser.get_metadata();
+
+        assert_eq!(metadata.title, Some("A Draft".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe", "John Roe"]);
+        assert_eq!(metadata.description, Some("About drafts".to_owned()));
+        assert_eq!(metadata.lang, Some("en-US".to_owned()));
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    io::{Cursor, Read},
+    marker::PhantomData,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use xml::reader::XmlEvent;
+
+use super::{
+    blocks::{self, collapse_whitespace, flush_line, Block},
+    xml_attribute, Content, Cover, FileParserV2, Metadata,
+};
+
+/// Elements of a body that end the current line of text
+const BLOCK_ELEMENTS: &[&str] = &[
+    "section",
+    "p",
+    "v",
+    "subtitle",
+    "text-author",
+    "empty-line",
+    "epigraph",
+    "cite",
+    "poem",
+    "stanza",
+    "table",
+    "tr",
+];
+
+/// Parser for FictionBook books, plain `.fb2` or zipped `.fb2.zip`.
+/// Chapters start at the `<title>` of every `<section>`, nested ones included.
+pub struct Fb2Parser<R> {
+    blocks: Vec<Block>,
+    metadata: Metadata,
+    cover: Option<Cover>,
+    reader: PhantomData<R>,
+}
+
+struct Binary {
+    id: String,
+    mime: String,
+    content: String,
+}
+
+impl<R> FileParserV2<R> for Fb2Parser<R>
+where
+    R: Read,
+{
+    fn from_reader(mut input: R) -> Result<Self> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        if bytes.starts_with(b"PK\x03\x04") {
+            bytes = unzip_fb2(bytes)?;
+        }
+        parse_fb2(&bytes)
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        Ok(blocks::table_of_contents(
+            &self.blocks,
+            self.metadata.title.clone(),
+        ))
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        blocks::extract_text(&self.blocks, from_id, to_id)
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        self.cover.clone()
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+/// Reads the first `.fb2` file of a zip archive
+fn unzip_fb2(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let name = archive
+        .file_names()
+        .find(|name| name.to_lowercase().ends_with(".fb2"))
+        .ok_or(anyhow!("no fb2 file in the archive"))?
+        .to_owned();
+
+    let mut fb2 = vec![];
+    archive.by_name(&name)?.read_to_end(&mut fb2)?;
+    Ok(fb2)
+}
+
+fn parse_fb2<R>(bytes: &[u8]) -> Result<Fb2Parser<R>> {
+    let mut metadata = Metadata::default();
+    let mut cover_id = None;
+    let mut binaries: Vec<Binary> = vec![];
+
+    let mut blocks = vec![];
+    let mut line = String::new();
+    let mut heading: Option<String> = None;
+    let mut author: Vec<String> = vec![];
+    let mut annotation = String::new();
+    let mut field = String::new();
+    // depth of the stack where the skipped element (notes, footnote anchors) started
+    let mut skipped_from: Option<usize> = None;
+    let mut stack: Vec<String> = vec![];
+
+    for event in xml::reader::EventReader::new(bytes) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                let parent = stack.last().map(|p| p.as_str());
+                if skipped_from.is_none() {
+                    let is_notes = name == "body"
+                        && xml_attribute(&attributes, "name")
+                            .is_some_and(|n| n == "notes" || n == "comments");
+                    let is_note_anchor =
+                        name == "a" && xml_attribute(&attributes, "type") == Some("note");
+                    if is_notes || is_note_anchor {
+                        skipped_from = Some(stack.len());
+                    }
+                }
+
+                match name.as_str() {
+                    "image" if parent == Some("coverpage") => {
+                        cover_id = xml_attribute(&attributes, "href")
+                            .map(|href| href.trim_start_matches('#').to_owned());
+                    }
+                    "binary" => binaries.push(Binary {
+                        id: xml_attribute(&attributes, "id")
+                            .unwrap_or_default()
+                            .to_owned(),
+                        mime: xml_attribute(&attributes, "content-type")
+                            .unwrap_or_default()
+                            .to_owned(),
+                        content: String::new(),
+                    }),
+                    "title" if parent == Some("section") => {
+                        flush_line(&mut blocks, &mut line);
+                        heading = Some(String::new());
+                    }
+                    "title" => flush_line(&mut blocks, &mut line),
+                    "p" if heading.is_some() => heading.iter_mut().for_each(|h| h.push(' ')),
+                    "p" if parent == Some("annotation") => annotation.push('\n'),
+                    block if BLOCK_ELEMENTS.contains(&block) => flush_line(&mut blocks, &mut line),
+                    _ => (),
+                }
+                stack.push(name);
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                let path = stack.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                match path.as_slice() {
+                    _ if skipped_from.is_some() => (),
+                    [.., "binary"] => binaries
+                        .last_mut()
+                        .iter_mut()
+                        .for_each(|b| b.content.push_str(&text)),
+                    _ if path.contains(&"annotation") => annotation.push_str(&text),
+                    [.., "author", _]
+                    | [.., "title-info", "book-title" | "lang"]
+                    | [.., "publish-info", "publisher"] => field.push_str(&text),
+                    _ if path.contains(&"description") => (),
+                    _ => match heading.as_mut() {
+                        Some(heading) => heading.push_str(&text),
+                        None => line.push_str(&text),
+                    },
+                }
+            }
+            XmlEvent::Whitespace(_) => match heading.as_mut() {
+                Some(heading) => heading.push(' '),
+                None => line.push(' '),
+            },
+            XmlEvent::EndElement { .. } => {
+                let name = stack.pop().unwrap_or_default();
+                let parent = stack.last().map(|p| p.as_str());
+                if skipped_from == Some(stack.len()) {
+                    skipped_from = None;
+                }
+
+                let value = collapse_whitespace(&field);
+                match (parent, name.as_str()) {
+                    (Some("author"), _) => {
+                        if !value.is_empty() {
+                            author.push(value);
+                        }
+                        field.clear();
+                    }
+                    (_, "author") => {
+                        if parent == Some("title-info") && !author.is_empty() {
+                            metadata.authors.push(author.join(" "));
+                        }
+                        author.clear();
+                    }
+                    (Some("title-info"), "book-title") => {
+                        metadata.title = Some(value).filter(|v| !v.is_empty());
+                        field.clear();
+                    }
+                    (Some("title-info"), "lang") => {
+                        metadata.lang = Some(value).filter(|v| !v.is_empty());
+                        field.clear();
+                    }
+                    (Some("title-info"), "annotation") => {
+                        let description = annotation
+                            .lines()
+                            .map(collapse_whitespace)
+                            .filter(|l| !l.is_empty())
+                            .collect::<Vec<String>>()
+                            .join("\n");
+                        metadata.description = Some(description).filter(|d| !d.is_empty());
+                    }
+                    (Some("publish-info"), "publisher") => {
+                        metadata.publisher = Some(value).filter(|v| !v.is_empty());
+                        field.clear();
+                    }
+                    (Some("section"), "title") => {
+                        let title = collapse_whitespace(&heading.take().unwrap_or_default());
+                        if !title.is_empty() {
+                            blocks.push(Block::Heading(title));
+                        }
+                    }
+                    (_, block) if block == "title" || BLOCK_ELEMENTS.contains(&block) => {
+                        flush_line(&mut blocks, &mut line)
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    flush_line(&mut blocks, &mut line);
+
+    let cover = cover_id
+        .and_then(|id| binaries.into_iter().find(|b| b.id == id))
+        .and_then(|binary| {
+            let content = binary
+                .content
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .concat();
+            Some(Cover {
+                mime: binary.mime,
+                content: STANDARD.decode(content).ok()?,
+            })
+        });
+
+    Ok(Fb2Parser {
+        blocks,
+        metadata,
+        cover,
+        reader: PhantomData,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    const BOOK: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <genre>prose</genre>
+      <author><first-name>Jane</first-name><middle-name>Q.</middle-name><last-name>Doe</last-name></author>
+      <author><nickname>anon</nickname></author>
+      <book-title>A Fiction Book</book-title>
+      <annotation><p>First line.</p><p>Second line.</p></annotation>
+      <coverpage><image l:href="#cover.jpg"/></coverpage>
+      <lang>en</lang>
+    </title-info>
+    <document-info><author><nickname>converter</nickname></author></document-info>
+    <publish-info><publisher>Some House</publisher></publish-info>
+  </description>
+  <body>
+    <title><p>A Fiction Book</p></title>
+    <epigraph><p>An epigraph.</p></epigraph>
+    <section>
+      <title><p>Part One</p><p>The Start</p></title>
+      <p>Part text<a l:href="#n1" type="note">[1]</a>.</p>
+      <section>
+        <title><p>Chapter 1</p></title>
+        <p>First <emphasis>chapter</emphasis> text.</p>
+        <empty-line/>
+        <poem><stanza><v>A verse</v><v>another verse</v></stanza></poem>
+      </section>
+    </section>
+    <section>
+      <title><p>Chapter 2</p></title>
+      <subtitle>A subtitle</subtitle>
+      <p>Last text.</p>
+    </section>
+  </body>
+  <body name="notes">
+    <section id="n1"><title><p>1</p></title><p>A footnote.</p></section>
+  </body>
+  <binary id="cover.jpg" content-type="image/jpeg">AQID
+  BA==</binary>
+</FictionBook>"##;
+
+    #[test]
+    fn fb2_toc() {
+        let mut parser = Fb2Parser::from_reader(BOOK.as_bytes()).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "A Fiction Book",
+                "Part One The Start",
+                "Chapter 1",
+                "Chapter 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn fb2_text() {
+        let mut parser = Fb2Parser::from_reader(BOOK.as_bytes()).unwrap();
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[0].id.clone(), Some(toc[1].id.clone()))
+            .unwrap();
+        assert_eq!(text, "A Fiction Book\nAn epigraph.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[1].id.clone(), Some(toc[2].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Part One The Start\nPart text.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), Some(toc[3].id.clone()))
+            .unwrap();
+        assert_eq!(
+            text,
+            "Chapter 1\nFirst chapter text.\nA verse\nanother verse"
+        );
+
+        let text = parser
+            .extract_text_for_chapters(toc[3].id.clone(), None)
+            .unwrap();
+        assert_eq!(text, "Chapter 2\nA subtitle\nLast text.");
+    }
+
+    #[test]
+    fn fb2_metadata_and_cover() {
+        let mut parser = Fb2Parser::from_reader(BOOK.as_bytes()).unwrap();
+
+        let metadata = parser.get_metadata();
+        assert_eq!(metadata.title, Some("A Fiction Book".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Q. Doe", "anon"]);
+        assert_eq!(
+            metadata.description,
+            Some("First line.\nSecond line.".to_owned())
+        );
+        assert_eq!(metadata.publisher, Some("Some House".to_owned()));
+        assert_eq!(metadata.lang, Some("en".to_owned()));
+
+        let cover = parser.get_cover().unwrap();
+        assert_eq!(cover.mime, "image/jpeg");
+        assert_eq!(cover.content, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fb2_zip() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(vec![]));
+        archive
+            .start_file("book.fb2", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(BOOK.as_bytes()).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+
+        let mut parser = Fb2Parser::from_reader(bytes.as_slice()).unwrap();
+
+        assert_eq!(parser.get_table_of_contents().unwrap().len(), 4);
+        assert_eq!(
+            parser.get_metadata().title,
+            Some("A Fiction Book".to_owned())
+        );
+    }
+}
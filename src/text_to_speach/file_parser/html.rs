@@ -0,0 +1,254 @@
+use std::{io::Read, marker::PhantomData};
+
+use anyhow::Result;
+use ego_tree::NodeRef;
+use scraper::{Html, Node, Selector};
+
+use super::{
+    blocks::{self, collapse_whitespace, flush_line, Block},
+    is_media_element, Content, Cover, FileParserV2, Metadata,
+};
+
+/// Elements that are never read, on top of the media ones
+const SKIPPED_ELEMENTS: &[&str] = &["head", "style", "noscript", "template", "nav"];
+/// Elements that end the current line of text
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "li",
+    "ul",
+    "ol",
+    "dt",
+    "dd",
+    "blockquote",
+    "pre",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "aside",
+    "main",
+    "table",
+    "tr",
+    "td",
+    "th",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "figcaption",
+];
+
+/// Parser for single file html books, sections start at `h1`-`h3` headings.
+pub struct HtmlParser<R> {
+    blocks: Vec<Block>,
+    metadata: HtmlMetadata,
+    reader: PhantomData<R>,
+}
+
+#[derive(Default)]
+pub(super) struct HtmlMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
+}
+
+impl<R> HtmlParser<R> {
+    pub(super) fn from_html(html: &str) -> Self {
+        let document = Html::parse_document(html);
+
+        let meta = |name: &str| {
+            let selector = Selector::parse(&format!(r#"meta[name="{name}"]"#)).ok()?;
+            document
+                .select(&selector)
+                .filter_map(|m| m.value().attr("content"))
+                .map(|c| c.trim().to_owned())
+                .find(|c| !c.is_empty())
+        };
+        let title = Selector::parse("title")
+            .ok()
+            .and_then(|s| document.select(&s).next())
+            .map(|t| collapse_whitespace(&t.text().collect::<String>()))
+            .filter(|t| !t.is_empty());
+        let metadata = HtmlMetadata {
+            title,
+            authors: meta("author").into_iter().collect(),
+            description: meta("description"),
+            lang: document
+                .root_element()
+                .value()
+                .attr("lang")
+                .map(|l| l.to_owned()),
+        };
+
+        let mut blocks = vec![];
+        let mut line = String::new();
+        collect_blocks(*document.root_element(), &mut blocks, &mut line);
+        flush_line(&mut blocks, &mut line);
+
+        Self {
+            blocks,
+            metadata,
+            reader: PhantomData,
+        }
+    }
+
+    pub(super) fn set_metadata(&mut self, metadata: HtmlMetadata) {
+        self.metadata = metadata;
+    }
+
+    pub(super) fn first_heading(&self) -> Option<String> {
+        blocks::first_heading(&self.blocks)
+    }
+}
+
+impl<R> FileParserV2<R> for HtmlParser<R>
+where
+    R: Read,
+{
+    fn from_reader(mut input: R) -> Result<Self> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        Ok(Self::from_html(&String::from_utf8_lossy(&bytes)))
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        Ok(blocks::table_of_contents(
+            &self.blocks,
+            self.metadata.title.clone(),
+        ))
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        blocks::extract_text(&self.blocks, from_id, to_id)
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        None
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        Metadata {
+            authors: self.metadata.authors.clone(),
+            title: self.metadata.title.clone(),
+            publisher: None,
+            description: self.metadata.description.clone(),
+            lang: self.metadata.lang.clone(),
+        }
+    }
+}
+
+fn collect_blocks(node: NodeRef<Node>, blocks: &mut Vec<Block>, line: &mut String) {
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) => line.push_str(text),
+            Node::Element(element) => {
+                let name = element.name();
+                if is_media_element(name) || SKIPPED_ELEMENTS.contains(&name) {
+                    continue;
+                }
+                if matches!(name, "h1" | "h2" | "h3") {
+                    flush_line(blocks, line);
+                    let mut heading = String::new();
+                    collect_blocks(child, &mut vec![], &mut heading);
+                    let heading = collapse_whitespace(&heading);
+                    if !heading.is_empty() {
+                        blocks.push(Block::Heading(heading));
+                    }
+                    continue;
+                }
+
+                let is_block = BLOCK_ELEMENTS.contains(&name);
+                if is_block {
+                    flush_line(blocks, line);
+                }
+                collect_blocks(child, blocks, line);
+                if is_block {
+                    flush_line(blocks, line);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <title>An Article</title>
+  <meta name="author" content="Jane Doe">
+  <meta name="description" content="About things">
+  <style>p { color: red; }</style>
+</head>
+<body>
+  <p>Intro with <em>inline</em> text.</p>
+  <h1>First <small>section</small></h1>
+  <p>First paragraph<br>second line</p>
+  <img src="a.png" alt="ignored">
+  <script>var ignored = true;</script>
+  <h2>Second</h2>
+  <ul><li>one</li><li>two</li></ul>
+  <svg><text>ignored too</text></svg>
+  <h4>Not in the toc</h4>
+  <p>Last words
+     over two lines.</p>
+</body>
+</html>"#;
+
+    #[test]
+    fn html_toc() {
+        let mut parser = HtmlParser::from_reader(PAGE.as_bytes()).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(names, vec!["An Article", "First section", "Second"]);
+        assert_eq!(toc[2].order, 3);
+    }
+
+    #[test]
+    fn html_text() {
+        let mut parser = HtmlParser::from_reader(PAGE.as_bytes()).unwrap();
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[0].id.clone(), Some(toc[1].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Intro with inline text.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[1].id.clone(), Some(toc[2].id.clone()))
+            .unwrap();
+        assert_eq!(text, "First section\nFirst paragraph\nsecond line");
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), None)
+            .unwrap();
+        assert_eq!(
+            text,
+            "Second\none\ntwo\nNot in the toc\nLast words over two lines."
+        );
+    }
+
+    #[test]
+    fn html_metadata() {
+        let mut parser = HtmlParser::from_reader(PAGE.as_bytes()).unwrap();
+
+        let metadata = parser.get_metadata();
+
+        assert_eq!(metadata.title, Some("An Article".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe"]);
+        assert_eq!(metadata.description, Some("About things".to_owned()));
+        assert_eq!(metadata.lang, Some("en".to_owned()));
+    }
+}
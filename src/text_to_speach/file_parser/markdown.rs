@@ -0,0 +1,167 @@
+use std::io::Read;
+
+use anyhow::Result;
+use pulldown_cmark::{html, Options, Parser};
+
+use super::{
+    html::{HtmlMetadata, HtmlParser},
+    Content, Cover, FileParserV2, Metadata,
+};
+
+/// Parser for markdown books, the markdown is rendered to html and read by
+/// [`HtmlParser`], so sections start at `#`, `##` and `###` headings.
+pub struct MarkdownParser<R> {
+    html: HtmlParser<R>,
+}
+
+impl<R> FileParserV2<R> for MarkdownParser<R>
+where
+    R: Read,
+{
+    fn from_reader(mut input: R) -> Result<Self> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let (front_matter, markdown) = split_front_matter(&text);
+
+        let mut rendered = String::new();
+        html::push_html(
+            &mut rendered,
+            Parser::new_ext(
+                markdown,
+                Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+            ),
+        );
+
+        let mut html = HtmlParser::from_html(&rendered);
+        let field = |name: &str| {
+            front_matter
+                .iter()
+                .find_map(|(key, value)| key.eq_ignore_ascii_case(name).then(|| value.to_owned()))
+        };
+        html.set_metadata(HtmlMetadata {
+            title: field("title").or(html.first_heading()),
+            authors: field("author").into_iter().collect(),
+            description: field("description"),
+            lang: field("lang").or(field("language")),
+        });
+
+        Ok(Self { html })
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        self.html.get_table_of_contents()
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        self.html.extract_text_for_chapters(from_id, to_id)
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        None
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        self.html.get_metadata()
+    }
+}
+
+/// Splits a `---` delimited front matter of `key: value` lines from the markdown
+fn split_front_matter(text: &str) -> (Vec<(String, String)>, &str) {
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (vec![], text);
+    };
+    let Some(end) = rest.find("\n---") else {
+        return (vec![], text);
+    };
+
+    let fields = rest[..end]
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .map(|(key, value)| {
+            (
+                key.trim().to_owned(),
+                value.trim().trim_matches(['"', '\'']).to_owned(),
+            )
+        })
+        .collect();
+    let markdown = rest[end + 4..]
+        .split_once('\n')
+        .map_or("", |(_, markdown)| markdown);
+    (fields, markdown)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOOK: &str = r#"---
+title: "A Markdown Book"
+author: Jane Doe
+---
+Some intro.
+
+# Chapter One
+
+Text of *the* first chapter.
+
+![an image](image.png)
+
+## A Section
+
+<script>alert("ignored")</script>
+
+Section text.
+
+#### Small heading
+
+More text.
+"#;
+
+    #[test]
+    fn markdown_toc() {
+        let mut parser = MarkdownParser::from_reader(BOOK.as_bytes()).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(names, vec!["A Markdown Book", "Chapter One", "A Section"]);
+    }
+
+    #[test]
+    fn markdown_text() {
+        let mut parser = MarkdownParser::from_reader(BOOK.as_bytes()).unwrap();
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[1].id.clone(), Some(toc[2].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Chapter One\nText of the first chapter.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), None)
+            .unwrap();
+        assert_eq!(text, "A Section\nSection text.\nSmall heading\nMore text.");
+    }
+
+    #[test]
+    fn markdown_metadata() {
+        let mut parser = MarkdownParser::from_reader(BOOK.as_bytes()).unwrap();
+        let metadata = parser.get_metadata();
+        assert_eq!(metadata.title, Some("A Markdown Book".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe"]);
+
+        let mut parser =
+            MarkdownParser::from_reader("# Only A Heading\n\ntext".as_bytes()).unwrap();
+        let metadata = parser.get_metadata();
+        assert_eq!(metadata.title, Some("Only A Heading".to_owned()));
+        assert!(metadata.authors.is_empty());
+    }
+}
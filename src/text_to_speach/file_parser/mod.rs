@@ -1,14 +1,32 @@
 use std::{
-    collections::{HashMap, HashSet},
-    default, fs,
+    collections::HashSet,
+    fs,
     io::{Cursor, Read, Seek},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, Result};
 use epub::doc::EpubDoc;
 use xml::{attribute::OwnedAttribute, reader::XmlEvent};
 
+mod blocks;
+mod docx;
+mod fb2;
+mod html;
+mod markdown;
+mod odt;
+mod office;
+mod pdf;
+mod txt;
+
+pub use docx::DocxParser;
+pub use fb2::Fb2Parser;
+pub use html::HtmlParser;
+pub use markdown::MarkdownParser;
+pub use odt::OdtParser;
+pub use pdf::PdfParser;
+pub use txt::TxtParserV2;
+
 trait FileParser<R>
 where
     R: Read,
@@ -16,26 +34,42 @@ where
     fn parse_bytes(input: R) -> Result<Vec<String>>;
 }
 
-struct Content {
-    id: String,
-    order: usize,
-    name: String,
+pub struct Content {
+    pub id: String,
+    pub order: usize,
+    pub name: String,
+}
+
+#[derive(Clone)]
+pub struct Cover {
+    pub mime: String,
+    pub content: Vec<u8>,
 }
 
-struct Cover {
-    mime: String,
-    content: Vec<u8>,
+#[derive(Clone, Default)]
+pub struct Metadata {
+    pub authors: Vec<String>,
+    pub title: Option<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
 }
 
-struct Metadata {
-    authors: Vec<String>,
-    title: Option<String>,
-    publisher: Option<String>,
-    description: Option<String>,
-    lang: Option<String>,
+/// A table of contents entry together with the text that belongs to it.
+pub struct Chapter {
+    pub id: String,
+    pub order: usize,
+    pub name: String,
+    pub text: String,
 }
 
-trait FileParserV2<R>
+pub struct Book {
+    pub metadata: Metadata,
+    pub cover: Option<Cover>,
+    pub chapters: Vec<Chapter>,
+}
+
+pub trait FileParserV2<R>
 where
     R: Read,
 {
@@ -63,8 +97,11 @@ where
     doc: EpubDoc<R>,
 }
 
-impl<'a> FileParserV2<Cursor<&'a [u8]>> for EpubParserV2<Cursor<&'a [u8]>> {
-    fn from_reader(input: Cursor<&'a [u8]>) -> Result<Self> {
+impl<R> FileParserV2<R> for EpubParserV2<R>
+where
+    R: Read + Seek,
+{
+    fn from_reader(input: R) -> Result<Self> {
         Ok(Self {
             doc: EpubDoc::from_reader(input)?,
         })
@@ -91,7 +128,7 @@ impl<'a> FileParserV2<Cursor<&'a [u8]>> for EpubParserV2<Cursor<&'a [u8]>> {
         let (from_uri, from_tag, to_tag, to_uri) = extract_positions(from_id, to_id)?;
 
         let mut table_of_contents = self.get_table_of_contents()?;
-        table_of_contents.sort_by(|a, b| a.order.cmp(&b.order));
+        table_of_contents.sort_by_key(|c| c.order);
 
         let content_to_read =
             filter_page_to_iterate_over(table_of_contents.iter(), &from_uri, &to_uri);
@@ -128,24 +165,7 @@ impl<'a> FileParserV2<Cursor<&'a [u8]>> for EpubParserV2<Cursor<&'a [u8]>> {
             for xml_event in page_xml {
                 match xml_event {
                     Ok(XmlEvent::Characters(c)) => {
-                        if !skip_text
-                            && !element_stack.iter().any(|e| {
-                                matches!(
-                                    e.name.as_str(),
-                                    "img"
-                                        | "media"
-                                        | "script"
-                                        | "video"
-                                        | "audio"
-                                        | "object"
-                                        | "embed"
-                                        | "iframe"
-                                        | "source"
-                                        | "track"
-                                        | "svg"
-                                )
-                            })
-                        {
+                        if !skip_text && !element_stack.iter().any(|e| is_media_element(&e.name)) {
                             final_string.push_str(format!("{c}\n").as_str())
                         }
                     }
@@ -213,9 +233,35 @@ impl<'a> FileParserV2<Cursor<&'a [u8]>> for EpubParserV2<Cursor<&'a [u8]>> {
     }
 }
 
+/// Elements whose text should never be read out loud
+fn is_media_element(name: &str) -> bool {
+    matches!(
+        name,
+        "img"
+            | "media"
+            | "script"
+            | "video"
+            | "audio"
+            | "object"
+            | "embed"
+            | "iframe"
+            | "source"
+            | "track"
+            | "svg"
+    )
+}
+
+/// Value of the attribute with the local name `name`, ignoring namespaces
+fn xml_attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.as_str())
+}
+
 fn filter_page_to_iterate_over<'a>(
     iterator: std::slice::Iter<'a, Content>,
-    from_uri: &PathBuf,
+    from_uri: &Path,
     to_uri: &Option<PathBuf>,
 ) -> Vec<&'a Content> {
     let content_to_read = iterator
@@ -232,10 +278,10 @@ fn filter_page_to_iterate_over<'a>(
     content_to_read
 }
 
-fn extract_positions(
-    from_id: String,
-    to_id: Option<String>,
-) -> Result<(PathBuf, Option<String>, Option<String>, Option<PathBuf>), anyhow::Error> {
+/// (from_uri, from_tag, to_tag, to_uri)
+type Positions = (PathBuf, Option<String>, Option<String>, Option<PathBuf>);
+
+fn extract_positions(from_id: String, to_id: Option<String>) -> Result<Positions, anyhow::Error> {
     let from_split = from_id.splitn(2, '#').collect::<Vec<&str>>();
     let from_uri = &PathBuf::from(
         from_split
@@ -257,7 +303,44 @@ fn extract_positions(
     Ok((from_uri.to_owned(), from_tag, to_tag, to_uri))
 }
 
-pub(crate) struct UniversalFileParser {}
+/// Reads every entry of the table of contents, in play order, as a [`Chapter`].
+///
+/// The text of an entry ends where the next entry starts.
+pub fn extract_chapters<R, P>(parser: &mut P) -> Result<Vec<Chapter>>
+where
+    R: Read,
+    P: FileParserV2<R>,
+{
+    let mut table_of_contents = parser.get_table_of_contents()?;
+    table_of_contents.sort_by_key(|c| c.order);
+
+    let mut chapters = vec![];
+    for (i, content) in table_of_contents.iter().enumerate() {
+        let next_id = table_of_contents.get(i + 1).map(|c| c.id.clone());
+        let text = parser.extract_text_for_chapters(content.id.clone(), next_id)?;
+        chapters.push(Chapter {
+            id: content.id.clone(),
+            order: content.order,
+            name: content.name.clone(),
+            text,
+        });
+    }
+    Ok(chapters)
+}
+
+pub fn read_book<R, P>(parser: &mut P) -> Result<Book>
+where
+    R: Read,
+    P: FileParserV2<R>,
+{
+    Ok(Book {
+        chapters: extract_chapters(parser)?,
+        metadata: parser.get_metadata(),
+        cover: parser.get_cover(),
+    })
+}
+
+pub struct UniversalFileParser {}
 
 impl UniversalFileParser {
     pub fn parse_file(file_path: &str) -> Result<Vec<String>> {
@@ -270,9 +353,58 @@ impl UniversalFileParser {
                 let bytes = fs::read(path)?;
                 EpubParser::parse_bytes(bytes.as_slice())
             }
+            path => Ok(Self::read_file(path)?
+                .chapters
+                .into_iter()
+                .map(|c| c.text)
+                .collect()),
+        }
+    }
+
+    /// Reads the book at `path` with the parser of its extension, txt and
+    /// epub have their own handling
+    fn read_file(path: &str) -> Result<Book> {
+        fn read<P: FileParserV2<fs::File>>(path: &str) -> Result<Book> {
+            read_book(&mut P::from_reader(fs::File::open(path)?)?)
+        }
+
+        match path {
+            path if path.ends_with(".pdf") => read::<PdfParser<_>>(path),
+            path if path.ends_with(".md") || path.ends_with(".markdown") => {
+                read::<MarkdownParser<_>>(path)
+            }
+            path if path.ends_with(".html") || path.ends_with(".htm") => {
+                read::<HtmlParser<_>>(path)
+            }
+            path if path.ends_with(".fb2") || path.ends_with(".fb2.zip") => {
+                read::<Fb2Parser<_>>(path)
+            }
+            path if path.ends_with(".docx") => read::<DocxParser<_>>(path),
+            path if path.ends_with(".odt") => read::<OdtParser<_>>(path),
             ext => Err(anyhow!("extension is unsupported: {ext}")),
         }
     }
+
+    pub fn parse_book(file_path: &str) -> Result<Book> {
+        match file_path {
+            path if path.ends_with(".txt") => {
+                let mut parser = TxtParserV2::from_reader(fs::File::open(path)?)?;
+                let mut book = read_book(&mut parser)?;
+                if book.metadata.title.is_none() {
+                    book.metadata.title = PathBuf::from(path)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string());
+                }
+                Ok(book)
+            }
+            path if path.ends_with(".epub") => {
+                let bytes = fs::read(path)?;
+                let mut parser = EpubParserV2::from_reader(Cursor::new(bytes.as_slice()))?;
+                read_book(&mut parser)
+            }
+            path => Self::read_file(path),
+        }
+    }
 }
 
 struct TxtParser;
@@ -296,6 +428,7 @@ struct EpubParser;
 #[derive(Default)]
 struct EpubElement {
     name: String,
+    #[allow(dead_code)]
     attributes: Vec<OwnedAttribute>,
 }
 
@@ -307,7 +440,7 @@ impl FileParser<&[u8]> for EpubParser {
             let page = doc.get_current_str().ok_or(anyhow!("can't read page"))?.0;
             let page_xml = xml::reader::EventReader::new(page.as_bytes());
             let mut final_string: String = "".to_owned();
-            let mut elementStack: Vec<EpubElement> = vec![];
+            let mut element_stack: Vec<EpubElement> = vec![];
             for xml_event in page_xml {
                 match xml_event {
                     Ok(XmlEvent::Characters(c)) => final_string.push_str(format!("{c}\n").as_str()),
@@ -315,16 +448,14 @@ impl FileParser<&[u8]> for EpubParser {
                         name,
                         attributes,
                         namespace: _,
-                    }) => elementStack.push(
-                        (EpubElement {
-                            name: name.local_name,
-                            attributes,
-                        }),
-                    ),
+                    }) => element_stack.push(EpubElement {
+                        name: name.local_name,
+                        attributes,
+                    }),
                     Ok(XmlEvent::EndElement { name }) => {
-                        for i in 0..elementStack.len() - 1 {
-                            if elementStack.get(i).unwrap().name == name.local_name {
-                                elementStack.remove(i);
+                        for i in 0..element_stack.len() - 1 {
+                            if element_stack.get(i).unwrap().name == name.local_name {
+                                element_stack.remove(i);
                                 break;
                             }
                         }
@@ -484,6 +615,17 @@ mod tests {
         assert_eq!(cover.mime, "image/jpeg");
     }
 
+    #[test]
+    fn chapters() {
+        let book = UniversalFileParser::parse_book("test.epub").unwrap();
+        let chapters = book.chapters;
+
+        assert_eq!(chapters.len(), 19);
+        assert_eq!(chapters[0].name, "Titlepage");
+        assert!(chapters[13].text.starts_with("Hours of Childhood"));
+        assert!(!chapters[13].text.contains("An Appeal to Woman"));
+    }
+
     #[test]
     fn universal() {
         let test_string = r#"
@@ -0,0 +1,231 @@
+use std::{io::Read, marker::PhantomData};
+
+use anyhow::{anyhow, Result};
+use xml::reader::XmlEvent;
+
+use super::{
+    blocks::{self, collapse_whitespace, flush_line, Block},
+    office::{document_metadata, open_archive, read_entry},
+    xml_attribute, Content, Cover, FileParserV2, Metadata,
+};
+
+/// Elements never read: footnotes, comments, tracked deletions, frames
+/// (images and text boxes) and the generated indexes
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "note",
+    "annotation",
+    "tracked-changes",
+    "frame",
+    "table-of-content",
+    "alphabetical-index",
+];
+
+/// Parser for OpenDocument text files, chapters start at the level 1 to 3
+/// headings.
+pub struct OdtParser<R> {
+    blocks: Vec<Block>,
+    metadata: Metadata,
+    reader: PhantomData<R>,
+}
+
+impl<R> FileParserV2<R> for OdtParser<R>
+where
+    R: Read,
+{
+    fn from_reader(input: R) -> Result<Self> {
+        let mut archive = open_archive(input)?;
+        let content = read_entry(&mut archive, "content.xml")?
+            .ok_or(anyhow!("content.xml is missing, not an odt file"))?;
+        let metadata = match read_entry(&mut archive, "meta.xml")? {
+            Some(meta) => document_metadata(&meta)?,
+            None => Metadata::default(),
+        };
+
+        Ok(Self {
+            blocks: read_content(&content)?,
+            metadata,
+            reader: PhantomData,
+        })
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        Ok(blocks::table_of_contents(
+            &self.blocks,
+            self.metadata.title.clone(),
+        ))
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        blocks::extract_text(&self.blocks, from_id, to_id)
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        None
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+fn read_content(xml: &str) -> Result<Vec<Block>> {
+    let mut blocks = vec![];
+    let mut line = String::new();
+    let mut heading_levels: Vec<bool> = vec![];
+    let mut depth = 0;
+    // depth where the skipped element started
+    let mut skipped_from: Option<usize> = None;
+
+    for event in xml::reader::EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                if skipped_from.is_none() && SKIPPED_ELEMENTS.contains(&name.as_str()) {
+                    skipped_from = Some(depth);
+                }
+                depth += 1;
+                if skipped_from.is_some() {
+                    continue;
+                }
+                match name.as_str() {
+                    "h" => {
+                        flush_line(&mut blocks, &mut line);
+                        let level = xml_attribute(&attributes, "outline-level")
+                            .and_then(|l| l.parse::<usize>().ok())
+                            .unwrap_or(1);
+                        heading_levels.push(level <= 3);
+                    }
+                    "p" => flush_line(&mut blocks, &mut line),
+                    "s" | "tab" | "line-break" => line.push(' '),
+                    _ => (),
+                }
+            }
+            XmlEvent::Characters(text) if skipped_from.is_none() => line.push_str(&text),
+            XmlEvent::EndElement { name } => {
+                depth -= 1;
+                if skipped_from == Some(depth) {
+                    skipped_from = None;
+                    continue;
+                }
+                if skipped_from.is_some() {
+                    continue;
+                }
+                match name.local_name.as_str() {
+                    "h" if heading_levels.pop() == Some(true) => {
+                        let heading = collapse_whitespace(&line);
+                        if !heading.is_empty() {
+                            blocks.push(Block::Heading(heading));
+                        }
+                        line.clear();
+                    }
+                    "h" | "p" => flush_line(&mut blocks, &mut line),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    flush_line(&mut blocks, &mut line);
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    const CONTENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:draw="urn:oasis:names:tc:opendocument:xmlns:drawing:1.0">
+  <office:body>
+    <office:text>
+      <text:table-of-content><text:index-body><text:p>Chapter One 1</text:p></text:index-body></text:table-of-content>
+      <text:p>Before the first heading.</text:p>
+      <text:h text:outline-level="1">Chapter <text:span>One</text:span></text:h>
+      <text:p>Some<text:s/>text<text:note text:note-class="footnote"><text:note-citation>1</text:note-citation><text:note-body><text:p>A footnote.</text:p></text:note-body></text:note>.</text:p>
+      <text:p><draw:frame><draw:image/></draw:frame>After<text:line-break/>a break.</text:p>
+      <text:h text:outline-level="4">Not a chapter</text:h>
+      <text:h text:outline-level="2">Chapter Two</text:h>
+      <text:list><text:list-item><text:p>an item</text:p></text:list-item></text:list>
+    </office:text>
+  </office:body>
+</office:document-content>"#;
+
+    const META: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-meta xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:meta="urn:oasis:names:tc:opendocument:xmlns:meta:1.0">
+  <office:meta>
+    <meta:initial-creator>Jane Doe</meta:initial-creator>
+    <dc:creator>An Editor</dc:creator>
+    <dc:title>An Odt Draft</dc:title>
+    <dc:description>About drafts</dc:description>
+    <dc:language>it</dc:language>
+  </office:meta>
+</office:document-meta>"#;
+
+    fn odt() -> Vec<u8> {
+        let mut archive = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in [
+            ("mimetype", "application/vnd.oasis.opendocument.text"),
+            ("content.xml", CONTENT),
+            ("meta.xml", META),
+        ] {
+            archive
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(content.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn odt_toc() {
+        let mut parser = OdtParser::from_reader(Cursor::new(odt())).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(names, vec!["An Odt Draft", "Chapter One", "Chapter Two"]);
+    }
+
+    #[test]
+    fn odt_text() {
+        let mut parser = OdtParser::from_reader(Cursor::new(odt())).unwrap();
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[0].id.clone(), Some(toc[1].id.clone()))
+            .unwrap();
+        assert_eq!(text, "Before the first heading.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[1].id.clone(), Some(toc[2].id.clone()))
+            .unwrap();
+        assert_eq!(
+            text,
+            "Chapter One\nSome text.\nAfter a break.\nNot a chapter"
+        );
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), None)
+            .unwrap();
+        assert_eq!(text, "Chapter Two\nan item");
+    }
+
+    #[test]
+    fn odt_metadata() {
+        let mut parser = OdtParser::from_reader(Cursor::new(odt())).unwrap();
+
+        let metadata = parser.get_metadata();
+
+        assert_eq!(metadata.title, Some("An Odt Draft".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe"]);
+        assert_eq!(metadata.description, Some("About drafts".to_owned()));
+        assert_eq!(metadata.lang, Some("it".to_owned()));
+    }
+}
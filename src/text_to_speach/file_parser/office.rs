@@ -0,0 +1,77 @@
+use std::io::{Cursor, Read};
+
+use anyhow::Result;
+use xml::reader::XmlEvent;
+use zip::{result::ZipError, ZipArchive};
+
+use super::{blocks::collapse_whitespace, Metadata};
+
+/// Zip container of docx and odt documents
+pub(super) type Archive = ZipArchive<Cursor<Vec<u8>>>;
+
+pub(super) fn open_archive<R: Read>(mut input: R) -> Result<Archive> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    Ok(ZipArchive::new(Cursor::new(bytes))?)
+}
+
+/// Content of the file `name` of the archive, `None` when missing
+pub(super) fn read_entry(archive: &mut Archive, name: &str) -> Result<Option<String>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(Some(content))
+}
+
+/// Reads the dublin core properties used by both `docProps/core.xml` (docx)
+/// and `meta.xml` (odt), the odt `initial-creator` wins over the last editor.
+pub(super) fn document_metadata(xml: &str) -> Result<Metadata> {
+    let mut metadata = Metadata::default();
+    let mut initial_creator = None;
+    let mut subject = None;
+    let mut element = String::new();
+    let mut value = String::new();
+
+    for event in xml::reader::EventReader::new(xml.as_bytes()) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                element = name.local_name;
+                value.clear();
+            }
+            XmlEvent::Characters(text) => value.push_str(&text),
+            XmlEvent::EndElement { .. } => {
+                let text = Some(collapse_whitespace(&value)).filter(|t| !t.is_empty());
+                match element.as_str() {
+                    "title" => metadata.title = text,
+                    "creator" => {
+                        metadata.authors = text
+                            .iter()
+                            .flat_map(|t| t.split(';'))
+                            .map(|a| a.trim().to_owned())
+                            .filter(|a| !a.is_empty())
+                            .collect()
+                    }
+                    "initial-creator" => initial_creator = text,
+                    "description" => metadata.description = text,
+                    "subject" => subject = text,
+                    "language" => metadata.lang = text,
+                    "publisher" => metadata.publisher = text,
+                    _ => (),
+                }
+                element.clear();
+                value.clear();
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(creator) = initial_creator {
+        metadata.authors = vec![creator];
+    }
+    metadata.description = metadata.description.or(subject);
+    Ok(metadata)
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Read,
+    marker::PhantomData,
+};
+
+use anyhow::{anyhow, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use super::{txt::is_roman_numeral, Content, Cover, FileParserV2, Metadata};
+
+/// Size of the sections used as table of contents when the pdf has no outline
+const PAGES_PER_SECTION: u32 = 10;
+
+pub struct PdfParser<R> {
+    doc: Document,
+    /// Text of every page with headers, footers and page numbers removed
+    pages_text: Option<BTreeMap<u32, String>>,
+    reader: PhantomData<R>,
+}
+
+impl<R> FileParserV2<R> for PdfParser<R>
+where
+    R: Read,
+{
+    fn from_reader(input: R) -> Result<Self> {
+        Ok(Self {
+            doc: Document::load_from(input)?,
+            pages_text: None,
+            reader: PhantomData,
+        })
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        let page_numbers = self
+            .doc
+            .get_pages()
+            .into_iter()
+            .map(|(number, id)| (id, number))
+            .collect::<HashMap<ObjectId, u32>>();
+
+        let mut outline = vec![];
+        if let Ok(first) = self
+            .doc
+            .catalog()
+            .and_then(|c| c.get(b"Outlines"))
+            .and_then(|o| self.doc.dereference(o))
+            .and_then(|(_, o)| o.as_dict())
+            .and_then(|o| o.get(b"First"))
+        {
+            self.collect_outline(first, &page_numbers, &mut outline, &mut HashSet::new());
+        }
+        outline.sort_by_key(|(page, _)| *page);
+
+        if outline.is_empty() {
+            let total_pages = page_numbers.len() as u32;
+            return Ok((0..total_pages.div_ceil(PAGES_PER_SECTION))
+                .map(|section| {
+                    let first = section * PAGES_PER_SECTION + 1;
+                    let last = (first + PAGES_PER_SECTION - 1).min(total_pages);
+                    Content {
+                        id: first.to_string(),
+                        order: section as usize + 1,
+                        name: format!("Pages {first}-{last}"),
+                    }
+                })
+                .collect());
+        }
+
+        Ok(outline
+            .into_iter()
+            .enumerate()
+            .map(|(i, (page, name))| Content {
+                id: page.to_string(),
+                order: i + 1,
+                name,
+            })
+            .collect())
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        let from_page = from_id
+            .parse::<u32>()
+            .map_err(|_| anyhow!("id is not correct {from_id}"))?;
+        let to_page = match to_id {
+            Some(id) => Some(
+                id.parse::<u32>()
+                    .map_err(|_| anyhow!("id is not correct {id}"))?,
+            ),
+            None => None,
+        };
+
+        let pages_text = self.pages_text()?;
+        // a page shared with the next entry is read there, so it's read once
+        Ok(pages_text
+            .iter()
+            .filter(|(page, _)| **page >= from_page && to_page.is_none_or(|to| **page < to))
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n"))
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        let (_, first_page) = self.doc.get_pages().into_iter().next()?;
+        let (resources, resource_ids) = self.doc.get_page_resources(first_page);
+        let resources = resources.into_iter().chain(
+            resource_ids
+                .into_iter()
+                .filter_map(|id| self.doc.get_dictionary(id).ok()),
+        );
+
+        let mut cover: Option<Cover> = None;
+        for resource in resources {
+            let Ok(xobjects) = resource
+                .get(b"XObject")
+                .and_then(|x| self.doc.dereference(x))
+                .and_then(|(_, x)| x.as_dict())
+            else {
+                continue;
+            };
+            for (_, xobject) in xobjects.iter() {
+                let Ok(stream) = self
+                    .doc
+                    .dereference(xobject)
+                    .and_then(|(_, x)| x.as_stream())
+                else {
+                    continue;
+                };
+                if !matches!(
+                    stream.dict.get(b"Subtype").and_then(Object::as_name),
+                    Ok(b"Image")
+                ) {
+                    continue;
+                }
+                let filter = match stream.dict.get(b"Filter") {
+                    Ok(Object::Array(filters)) => filters.last().and_then(|f| f.as_name().ok()),
+                    Ok(filter) => filter.as_name().ok(),
+                    Err(_) => None,
+                };
+                let mime = match filter {
+                    Some(b"DCTDecode") => "image/jpeg",
+                    Some(b"JPXDecode") => "image/jp2",
+                    _ => continue,
+                };
+                if cover
+                    .as_ref()
+                    .is_none_or(|c| c.content.len() < stream.content.len())
+                {
+                    cover = Some(Cover {
+                        mime: mime.to_owned(),
+                        content: stream.content.clone(),
+                    });
+                }
+            }
+        }
+        cover
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        let info = self
+            .doc
+            .trailer
+            .get(b"Info")
+            .and_then(|i| self.doc.dereference(i))
+            .and_then(|(_, i)| i.as_dict())
+            .ok();
+        let field = |key: &[u8]| {
+            info.and_then(|i| i.get(key).ok())
+                .and_then(|v| v.as_str().ok())
+                .map(decode_pdf_string)
+                .filter(|v| !v.trim().is_empty())
+        };
+
+        Metadata {
+            authors: field(b"Author")
+                .map(|a| {
+                    a.split(';')
+                        .map(|a| a.trim().to_owned())
+                        .filter(|a| !a.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            title: field(b"Title"),
+            publisher: None,
+            description: field(b"Subject"),
+            lang: self
+                .doc
+                .catalog()
+                .and_then(|c| c.get(b"Lang"))
+                .and_then(Object::as_str)
+                .map(decode_pdf_string)
+                .ok(),
+        }
+    }
+}
+
+impl<R> PdfParser<R> {
+    fn collect_outline(
+        &self,
+        item: &Object,
+        page_numbers: &HashMap<ObjectId, u32>,
+        outline: &mut Vec<(u32, String)>,
+        visited: &mut HashSet<ObjectId>,
+    ) {
+        let mut next = Some(item);
+        while let Some(item) = next {
+            let Ok((id, Object::Dictionary(entry))) = self.doc.dereference(item) else {
+                return;
+            };
+            if let Some(id) = id {
+                if !visited.insert(id) {
+                    return;
+                }
+            }
+
+            let title = entry
+                .get(b"Title")
+                .and_then(Object::as_str)
+                .map(decode_pdf_string)
+                .unwrap_or_default();
+            if let Some(page) = self
+                .destination_page(entry)
+                .and_then(|id| page_numbers.get(&id))
+            {
+                outline.push((*page, title.trim().to_owned()));
+            }
+            if let Ok(first) = entry.get(b"First") {
+                self.collect_outline(first, page_numbers, outline, visited);
+            }
+            next = entry.get(b"Next").ok();
+        }
+    }
+
+    /// Page referenced by an outline item, either directly or through a GoTo action
+    fn destination_page(&self, entry: &Dictionary) -> Option<ObjectId> {
+        let destination = entry.get(b"Dest").ok().or_else(|| {
+            entry
+                .get(b"A")
+                .and_then(|a| self.doc.dereference(a))
+                .and_then(|(_, a)| a.as_dict())
+                .and_then(|a| a.get(b"D"))
+                .ok()
+        })?;
+        let (_, destination) = self.doc.dereference(destination).ok()?;
+        let destination = match destination {
+            Object::Dictionary(d) => d.get(b"D").ok()?,
+            d => d,
+        };
+        destination.as_array().ok()?.first()?.as_reference().ok()
+    }
+
+    fn pages_text(&mut self) -> Result<&BTreeMap<u32, String>> {
+        if self.pages_text.is_none() {
+            let pages = self
+                .doc
+                .get_pages()
+                .keys()
+                .map(|page| Ok((*page, self.doc.extract_text(&[*page])?)))
+                .collect::<Result<Vec<(u32, String)>>>()?;
+            let (numbers, texts): (Vec<u32>, Vec<String>) = pages.into_iter().unzip();
+            self.pages_text = Some(
+                numbers
+                    .into_iter()
+                    .zip(strip_headers_and_footers(texts))
+                    .collect(),
+            );
+        }
+        Ok(self.pages_text.as_ref().unwrap())
+    }
+}
+
+/// Pdf text strings are either UTF-16BE with a byte order mark or PDFDocEncoding,
+/// which matches latin-1 for printable characters.
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    match bytes {
+        [0xfe, 0xff, rest @ ..] => String::from_utf16_lossy(
+            &rest
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<u16>>(),
+        ),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).to_string(),
+        bytes => bytes.iter().map(|b| *b as char).collect(),
+    }
+}
+
+/// Lines only made of a page number like `12`, `- 12 -`, `xiv` or `Page 3 of 10`
+fn is_page_number(line: &str) -> bool {
+    let line = line
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '–' | '—' | '|'))
+        .to_lowercase();
+    let line = line
+        .strip_prefix("page")
+        .or_else(|| line.strip_prefix("p."))
+        .unwrap_or(&line)
+        .trim();
+    let number = match line.split_once(" of ") {
+        Some((number, total)) if total.trim().chars().all(|c| c.is_ascii_digit()) => number.trim(),
+        _ => line,
+    };
+    !number.is_empty() && (number.chars().all(|c| c.is_ascii_digit()) || is_roman_numeral(number))
+}
+
+/// Running headers usually only differ by the page number they contain
+fn normalize_running_line(line: &str) -> String {
+    let mut normalized = String::new();
+    for c in line.trim().to_lowercase().chars() {
+        if c.is_ascii_digit() {
+            if !normalized.ends_with('#') {
+                normalized.push('#');
+            }
+        } else {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+/// Lines checked at the top and at the bottom of every page
+const RUNNING_LINES: usize = 2;
+
+/// Removes page numbers and the lines that repeat at the top or bottom of most pages.
+fn strip_headers_and_footers(pages: Vec<String>) -> Vec<String> {
+    let pages = pages
+        .iter()
+        .map(|page| {
+            page.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<&str>>()
+        })
+        .collect::<Vec<Vec<&str>>>();
+
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    for lines in &pages {
+        let edges = lines
+            .iter()
+            .take(RUNNING_LINES)
+            .chain(lines.iter().rev().take(RUNNING_LINES))
+            .map(|l| normalize_running_line(l))
+            .collect::<HashSet<String>>();
+        for line in edges {
+            *occurrences.entry(line).or_default() += 1;
+        }
+    }
+    let threshold = (pages.len() / 2).max(3);
+    let is_running = |line: &str| {
+        is_page_number(line)
+            || occurrences
+                .get(&normalize_running_line(line))
+                .is_some_and(|count| *count >= threshold)
+    };
+
+    pages
+        .into_iter()
+        .map(|mut lines| {
+            for _ in 0..RUNNING_LINES {
+                if lines.first().is_some_and(|l| is_running(l)) {
+                    lines.remove(0);
+                }
+                if lines.last().is_some_and(|l| is_running(l)) {
+                    lines.pop();
+                }
+            }
+            lines.join("\n")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use lopdf::{
+        content::{Content as PdfContent, Operation},
+        dictionary, Bookmark, Stream, StringFormat,
+    };
+
+    use super::*;
+
+    /// Builds a pdf with one page per entry of `pages`, each line is a text operation
+    fn build_pdf(pages: &[&[&str]], bookmarks: &[(&str, usize)]) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        let mut page_ids = vec![];
+        for lines in pages {
+            let mut operations = vec![];
+            for (i, line) in lines.iter().enumerate() {
+                operations.push(Operation::new("BT", vec![]));
+                operations.push(Operation::new("Tf", vec!["F1".into(), 12.into()]));
+                operations.push(Operation::new(
+                    "Td",
+                    vec![50.into(), (700 - 20 * i as i64).into()],
+                ));
+                operations.push(Operation::new(
+                    "Tj",
+                    vec![Object::string_literal(line.to_string())],
+                ));
+                operations.push(Operation::new("ET", vec![]));
+            }
+            let content = PdfContent { operations };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            page_ids.push(doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            }));
+        }
+
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => page_ids.iter().map(|id| Object::Reference(*id)).collect::<Vec<Object>>(),
+                "Count" => page_ids.len() as u32,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::String("A Pdf Book".into(), StringFormat::Literal),
+            "Author" => Object::String("Jane Doe; John Roe".into(), StringFormat::Literal),
+        });
+        doc.trailer.set("Info", info_id);
+
+        for (title, page) in bookmarks {
+            doc.add_bookmark(
+                Bookmark::new(title.to_string(), [0.0, 0.0, 0.0], 0, page_ids[*page]),
+                None,
+            );
+        }
+        if let Some(outline_id) = doc.build_outline() {
+            doc.catalog_mut().unwrap().set("Outlines", outline_id);
+        }
+
+        let mut output = vec![];
+        doc.save_to(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn pdf_outline_toc() {
+        let pdf = build_pdf(
+            &[&["one"], &["two"], &["three"]],
+            &[("First", 0), ("Second", 2)],
+        );
+        let mut parser = PdfParser::from_reader(Cursor::new(pdf)).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].name, "First");
+        assert_eq!(toc[0].id, "1");
+        assert_eq!(toc[1].name, "Second");
+        assert_eq!(toc[1].id, "3");
+        assert_eq!(toc[1].order, 2);
+
+        let text = parser
+            .extract_text_for_chapters(toc[0].id.clone(), Some(toc[1].id.clone()))
+            .unwrap();
+        assert!(text.contains("one"));
+        assert!(text.contains("two"));
+        assert!(!text.contains("three"));
+
+        // the page of two entries goes to the later one
+        let pdf = build_pdf(
+            &[&["one"], &["two"]],
+            &[("First", 0), ("Also first", 0), ("Second", 1)],
+        );
+        let mut parser = PdfParser::from_reader(Cursor::new(pdf)).unwrap();
+        let chapters = super::super::extract_chapters(&mut parser).unwrap();
+        assert_eq!(chapters.len(), 3);
+        assert!(!chapters[0].text.contains("one"));
+        assert!(chapters[1].text.contains("one") && !chapters[1].text.contains("two"));
+        assert!(chapters[2].text.contains("two"));
+    }
+
+    #[test]
+    fn pdf_page_ranges_toc() {
+        let pages = vec![&["text"][..]; 25];
+        let pdf = build_pdf(&pages, &[]);
+        let mut parser = PdfParser::from_reader(Cursor::new(pdf)).unwrap();
+
+        let toc = parser.get_table_of_contents().unwrap();
+
+        assert_eq!(toc.len(), 3);
+        assert_eq!(toc[0].name, "Pages 1-10");
+        assert_eq!(toc[2].name, "Pages 21-25");
+        assert_eq!(toc[2].id, "21");
+    }
+
+    #[test]
+    fn pdf_metadata() {
+        let pdf = build_pdf(&[&["one"]], &[]);
+        let mut parser = PdfParser::from_reader(Cursor::new(pdf)).unwrap();
+
+        let metadata = parser.get_metadata();
+
+        assert_eq!(metadata.title, Some("A Pdf Book".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe", "John Roe"]);
+        assert!(parser.get_cover().is_none());
+    }
+
+    #[test]
+    fn strips_running_lines() {
+        let words = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta"];
+        let pages = words
+            .iter()
+            .enumerate()
+            .map(|(page, word)| format!("My Book - Chapter {page}\n{word} is here\n- {page} -"))
+            .collect::<Vec<String>>();
+
+        let stripped = strip_headers_and_footers(pages);
+
+        assert_eq!(stripped[0], "alpha is here");
+        assert_eq!(stripped[5], "zeta is here");
+    }
+
+    #[test]
+    fn page_numbers() {
+        assert!(is_page_number("12"));
+        assert!(is_page_number("- 12 -"));
+        assert!(is_page_number("xiv"));
+        assert!(is_page_number("Page 3 of 10"));
+        assert!(!is_page_number("Chapter 3"));
+        assert!(!is_page_number("12 monkeys"));
+        for word in ["Did", "Mild", "Civil", "Vivid", "Dim"] {
+            assert!(!is_page_number(word), "{word}");
+        }
+        assert!(is_page_number("- XLII -"));
+    }
+
+    #[test]
+    fn pdf_strings() {
+        assert_eq!(decode_pdf_string(&[0xfe, 0xff, 0, b'h', 0, b'i']), "hi");
+        assert_eq!(decode_pdf_string(b"caf\xe9"), "café");
+    }
+}
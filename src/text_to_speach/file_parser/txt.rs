@@ -0,0 +1,480 @@
+use std::{io::Read, marker::PhantomData};
+
+use anyhow::{anyhow, Result};
+
+use super::{Content, Cover, FileParserV2, Metadata};
+
+/// Words that start a numbered heading like `Chapter 4` or `PART TWO`
+const NUMBERED_HEADINGS: &[&str] = &["chapter", "part", "book", "section", "volume"];
+/// Headings that stand on their own
+const NAMED_HEADINGS: &[&str] = &[
+    "prologue",
+    "epilogue",
+    "preface",
+    "foreword",
+    "introduction",
+    "afterword",
+    "conclusion",
+];
+const NUMBER_WORDS: &[&str] = &[
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+    "twenty",
+    "first",
+    "second",
+    "third",
+    "fourth",
+    "fifth",
+    "sixth",
+    "seventh",
+    "eighth",
+    "ninth",
+    "tenth",
+    "last",
+];
+/// Longest line that can still be a heading
+const MAX_HEADING_LENGTH: usize = 80;
+/// Lines at the start of the file searched for a title and an author
+const METADATA_LINES: usize = 40;
+
+/// Parser for plain text books, chapters are found by looking for headings.
+pub struct TxtParserV2<R> {
+    lines: Vec<String>,
+    /// `(line index, heading)` of every chapter, the heading is already cleaned up
+    headings: Vec<(usize, String)>,
+    metadata_title: Option<String>,
+    metadata_author: Option<String>,
+    reader: PhantomData<R>,
+}
+
+impl<R> FileParserV2<R> for TxtParserV2<R>
+where
+    R: Read,
+{
+    fn from_reader(mut input: R) -> Result<Self> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let lines = gutenberg_body(text.lines().map(|l| l.trim_end().to_owned()).collect());
+        let (metadata_title, metadata_author) = find_title_and_author(&lines);
+        let headings = find_headings(&lines);
+
+        Ok(Self {
+            lines,
+            headings,
+            metadata_title,
+            metadata_author,
+            reader: PhantomData,
+        })
+    }
+
+    fn get_table_of_contents(&mut self) -> Result<Vec<Content>> {
+        let mut table_of_contents = vec![];
+        let first_heading = self.headings.first().map(|(line, _)| *line);
+        let has_opening = match first_heading {
+            Some(line) => self.lines[..line].iter().any(|l| !l.trim().is_empty()),
+            None => true,
+        };
+        if has_opening {
+            table_of_contents.push((
+                0,
+                self.metadata_title.clone().unwrap_or("Opening".to_owned()),
+            ));
+        }
+        table_of_contents.extend(self.headings.iter().cloned());
+
+        Ok(table_of_contents
+            .into_iter()
+            .enumerate()
+            .map(|(i, (line, name))| Content {
+                id: line.to_string(),
+                order: i + 1,
+                name,
+            })
+            .collect())
+    }
+
+    fn extract_text_for_chapters(
+        &mut self,
+        from_id: String,
+        to_id: Option<String>,
+    ) -> Result<String> {
+        let from = from_id
+            .parse::<usize>()
+            .map_err(|_| anyhow!("id is not correct {from_id}"))?;
+        let to = match to_id {
+            Some(id) => id
+                .parse::<usize>()
+                .map_err(|_| anyhow!("id is not correct {id}"))?,
+            None => self.lines.len(),
+        };
+
+        let lines = self
+            .lines
+            .get(from..to.clamp(from, self.lines.len()))
+            .ok_or(anyhow!("id is out of the text {from_id}"))?;
+        Ok(lines
+            .iter()
+            .filter(|l| !is_underline(l))
+            .map(|l| l.trim_start_matches('#').trim())
+            .collect::<Vec<&str>>()
+            .join("\n")
+            .trim()
+            .to_owned())
+    }
+
+    fn get_cover(&mut self) -> Option<Cover> {
+        None
+    }
+
+    fn get_metadata(&mut self) -> Metadata {
+        Metadata {
+            authors: self.metadata_author.clone().into_iter().collect(),
+            title: self.metadata_title.clone(),
+            publisher: None,
+            description: None,
+            lang: None,
+        }
+    }
+}
+
+/// Keeps only the book when the text has Project Gutenberg start and end markers
+fn gutenberg_body(lines: Vec<String>) -> Vec<String> {
+    let start = lines
+        .iter()
+        .position(|l| l.starts_with("*** START OF") || l.starts_with("***START OF"));
+    let end = lines
+        .iter()
+        .position(|l| l.starts_with("*** END OF") || l.starts_with("***END OF"));
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => lines[start + 1..end].to_vec(),
+        (Some(start), None) => lines[start + 1..].to_vec(),
+        _ => lines,
+    }
+}
+
+fn find_title_and_author(lines: &[String]) -> (Option<String>, Option<String>) {
+    let first_lines = lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .take(METADATA_LINES)
+        .collect::<Vec<&str>>();
+
+    let field = |name: &str| {
+        first_lines.iter().find_map(|l| {
+            let (key, value) = l.split_once(':')?;
+            (key.trim().eq_ignore_ascii_case(name) && !value.trim().is_empty())
+                .then(|| value.trim().to_owned())
+        })
+    };
+    if let Some(title) = field("title") {
+        return (Some(title), field("author"));
+    }
+
+    // a title line followed by "by Someone"
+    match first_lines
+        .iter()
+        .take(5)
+        .position(|l| by_author(l).is_some())
+    {
+        Some(i) if i > 0 && !is_heading(first_lines[i - 1]) => (
+            Some(first_lines[i - 1].trim_start_matches('#').trim().to_owned()),
+            by_author(first_lines[i]).map(str::to_owned),
+        ),
+        _ => (None, None),
+    }
+}
+
+/// The author of a "by Someone" line, sliced with get since the third byte
+/// can be in the middle of a character
+fn by_author(line: &str) -> Option<&str> {
+    let author = line.get(3..)?.trim();
+    (line[..3].eq_ignore_ascii_case("by ") && !author.is_empty() && line.len() < MAX_HEADING_LENGTH)
+        .then_some(author)
+}
+
+fn is_underline(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && (line.chars().all(|c| c == '=') || line.chars().all(|c| c == '-'))
+}
+
+fn roman_value(c: char) -> Option<usize> {
+    match c {
+        'i' => Some(1),
+        'v' => Some(5),
+        'x' => Some(10),
+        'l' => Some(50),
+        'c' => Some(100),
+        'd' => Some(500),
+        'm' => Some(1000),
+        _ => None,
+    }
+}
+
+fn to_roman(mut value: usize) -> String {
+    const NUMERALS: &[(usize, &str)] = &[
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut roman = String::new();
+    for (numeral_value, numeral) in NUMERALS {
+        while value >= *numeral_value {
+            roman.push_str(numeral);
+            value -= numeral_value;
+        }
+    }
+    roman
+}
+
+/// Only canonical numerals count, so words like `did` or `mild` are not numbers
+/// Only canonical lowercase numerals like `xiv`, not any word made of their letters
+pub(super) fn is_roman_numeral(word: &str) -> bool {
+    let Some(values) = word
+        .chars()
+        .map(roman_value)
+        .collect::<Option<Vec<usize>>>()
+    else {
+        return false;
+    };
+    let value = values
+        .iter()
+        .enumerate()
+        .fold(0_isize, |total, (i, value)| match values.get(i + 1) {
+            Some(next) if next > value => total - *value as isize,
+            _ => total + *value as isize,
+        });
+    value > 0 && to_roman(value as usize) == word
+}
+
+fn is_number(word: &str) -> bool {
+    let word = word.trim_end_matches(['.', ':', ',']).to_lowercase();
+    !word.is_empty()
+        && (word.chars().all(|c| c.is_ascii_digit())
+            || is_roman_numeral(&word)
+            || NUMBER_WORDS.contains(&word.as_str()))
+}
+
+fn is_heading(line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || line.len() > MAX_HEADING_LENGTH {
+        return false;
+    }
+    if line.starts_with('#') {
+        let level = line.chars().take_while(|c| *c == '#').count();
+        return level <= 3 && line[level..].starts_with(' ');
+    }
+
+    let lower = line.to_lowercase();
+    let mut words = lower.split_whitespace();
+    let first = words.next().unwrap_or_default();
+    if NUMBERED_HEADINGS.contains(&first) {
+        // the number can only be followed by a title like `Chapter 2: The Morning`
+        return match (words.next(), words.next()) {
+            (Some(number), None) => is_number(number),
+            (Some(number), Some(next)) => {
+                is_number(number)
+                    && (number.ends_with(['.', ':']) || next.starts_with(['-', '–', '—', ':']))
+            }
+            _ => false,
+        };
+    }
+    NAMED_HEADINGS.contains(&lower.trim_end_matches(['.', ':']))
+}
+
+fn find_headings(lines: &[String]) -> Vec<(usize, String)> {
+    let mut headings = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let after_blank = i == 0 || lines[i - 1].trim().is_empty() || is_underline(&lines[i - 1]);
+        if !after_blank || line.trim().is_empty() {
+            continue;
+        }
+        let underlined = lines.get(i + 1).is_some_and(|next| is_underline(next))
+            && line.trim().len() <= MAX_HEADING_LENGTH
+            && !is_underline(line);
+        if underlined || is_heading(line) {
+            headings.push((i, line.trim_start_matches('#').trim().to_owned()));
+        }
+    }
+    headings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOOK: &str = "Title: A Test Book
+Author: Jane Doe
+
+Some opening words.
+
+CHAPTER I
+
+It was a dark night.
+
+Chapter 2: The Morning
+
+It was a bright morning.
+A second line.
+
+Part Two
+
+# The Markdown Heading
+
+Markdown text.
+
+Underlined Heading
+==================
+
+Underlined text.
+";
+
+    fn parser(text: &str) -> TxtParserV2<&[u8]> {
+        TxtParserV2::from_reader(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn txt_headings() {
+        let mut parser = parser(BOOK);
+
+        let toc = parser.get_table_of_contents().unwrap();
+        let names = toc.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(
+            names,
+            vec![
+                "A Test Book",
+                "CHAPTER I",
+                "Chapter 2: The Morning",
+                "Part Two",
+                "The Markdown Heading",
+                "Underlined Heading"
+            ]
+        );
+        assert_eq!(toc[3].order, 4);
+    }
+
+    #[test]
+    fn txt_chapter_text() {
+        let mut parser = parser(BOOK);
+        let toc = parser.get_table_of_contents().unwrap();
+
+        let text = parser
+            .extract_text_for_chapters(toc[2].id.clone(), Some(toc[3].id.clone()))
+            .unwrap();
+        assert_eq!(
+            text,
+            "Chapter 2: The Morning\n\nIt was a bright morning.\nA second line."
+        );
+
+        let text = parser
+            .extract_text_for_chapters(toc[5].id.clone(), None)
+            .unwrap();
+        assert_eq!(text, "Underlined Heading\n\nUnderlined text.");
+
+        let text = parser
+            .extract_text_for_chapters(toc[4].id.clone(), Some(toc[5].id.clone()))
+            .unwrap();
+        assert_eq!(text, "The Markdown Heading\n\nMarkdown text.");
+    }
+
+    #[test]
+    fn txt_metadata() {
+        let metadata = parser(BOOK).get_metadata();
+        assert_eq!(metadata.title, Some("A Test Book".to_owned()));
+        assert_eq!(metadata.authors, vec!["Jane Doe"]);
+
+        let metadata =
+            parser("The Raven\nby Edgar Allan Poe\n\nOnce upon a midnight dreary").get_metadata();
+        assert_eq!(metadata.title, Some("The Raven".to_owned()));
+        assert_eq!(metadata.authors, vec!["Edgar Allan Poe"]);
+
+        let metadata = parser("just some text\n\nand more").get_metadata();
+        assert_eq!(metadata.title, None);
+        assert!(metadata.authors.is_empty());
+
+        // the third byte is inside a character
+        let metadata = parser("Привет мир\nПиотр\n\nтекст").get_metadata();
+        assert_eq!(metadata.title, None);
+        let metadata = parser("Più luce\nby Dante\n\ntesto").get_metadata();
+        assert_eq!(metadata.title, Some("Più luce".to_owned()));
+        assert_eq!(metadata.authors, vec!["Dante"]);
+    }
+
+    #[test]
+    fn txt_without_headings() {
+        let mut parser = parser("just some text\n\nand more");
+
+        let toc = parser.get_table_of_contents().unwrap();
+
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].name, "Opening");
+        assert_eq!(
+            parser
+                .extract_text_for_chapters(toc[0].id.clone(), None)
+                .unwrap(),
+            "just some text\n\nand more"
+        );
+    }
+
+    #[test]
+    fn txt_gutenberg_markers() {
+        let mut parser = parser(
+            "license\n*** START OF THE PROJECT GUTENBERG EBOOK X ***\nChapter 1\n\ntext\n*** END OF THE PROJECT GUTENBERG EBOOK X ***\nmore license",
+        );
+
+        let toc = parser.get_table_of_contents().unwrap();
+
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].name, "Chapter 1");
+        assert_eq!(
+            parser
+                .extract_text_for_chapters(toc[0].id.clone(), None)
+                .unwrap(),
+            "Chapter 1\n\ntext"
+        );
+    }
+
+    #[test]
+    fn headings() {
+        assert!(is_heading("Chapter 12"));
+        assert!(is_heading("CHAPTER IV."));
+        assert!(is_heading("Part Two"));
+        assert!(is_heading("## Section"));
+        assert!(is_heading("Epilogue"));
+        assert!(is_heading("Book 3 - The Return"));
+        assert!(!is_heading("Chapter and verse were quoted"));
+        assert!(!is_heading("Part did not matter"));
+        assert!(!is_heading("Part mix of things."));
+        assert!(!is_heading("#hashtag"));
+        assert!(!is_heading("#### Too deep"));
+    }
+}
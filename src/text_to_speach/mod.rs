@@ -1,2 +1,6 @@
-mod file_parser;
-mod provider;
+pub mod chunker;
+pub mod estimator;
+pub mod file_parser;
+pub mod planner;
+pub mod provider;
+pub mod scheduler;
@@ -0,0 +1,329 @@
+use std::fmt::Display;
+
+use super::file_parser::Chapter;
+
+/// Table of contents entries that are skipped unless the plan keeps everything
+const FRONT_AND_BACK_MATTER: &[&str] = &[
+    "titlepage",
+    "title page",
+    "imprint",
+    "colophon",
+    "uncopyright",
+    "copyright",
+    "cover",
+    "table of contents",
+    "contents",
+    "toc",
+];
+
+pub struct PlannerOptions {
+    /// Entries with fewer words are merged with the following ones
+    pub min_words: usize,
+    /// Entries estimated to last longer are split in parts
+    pub max_minutes: usize,
+    /// Narration speed used to estimate the duration of an episode
+    pub words_per_minute: usize,
+    /// Skip front and back matter like titlepage, imprint and colophon
+    pub skip_front_and_back_matter: bool,
+}
+
+impl Default for PlannerOptions {
+    fn default() -> Self {
+        Self {
+            min_words: 500,
+            max_minutes: 60,
+            words_per_minute: 150,
+            skip_front_and_back_matter: true,
+        }
+    }
+}
+
+/// What will be synthesized, built before making any text to speech request.
+#[derive(Debug)]
+pub struct EpisodePlan {
+    pub episodes: Vec<PlannedEpisode>,
+    /// Names of the table of contents entries that won't be read
+    pub skipped: Vec<String>,
+    pub words_per_minute: usize,
+}
+
+#[derive(Debug)]
+pub struct PlannedEpisode {
+    /// Episode number, starting from 1 and following the book order
+    pub number: usize,
+    pub title: String,
+    /// Names of the table of contents entries read in this episode
+    pub sources: Vec<String>,
+    /// `(part, total parts)` when a single entry was split in more episodes
+    pub part: Option<(usize, usize)>,
+    pub text: String,
+    pub word_count: usize,
+}
+
+impl PlannedEpisode {
+    pub fn estimated_minutes(&self, words_per_minute: usize) -> f64 {
+        self.word_count as f64 / words_per_minute.max(1) as f64
+    }
+}
+
+impl EpisodePlan {
+    pub fn total_words(&self) -> usize {
+        self.episodes.iter().map(|e| e.word_count).sum()
+    }
+}
+
+impl Display for EpisodePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:>4}  {:>7}  {:>6}  title", "#", "words", "~min")?;
+        for episode in &self.episodes {
+            writeln!(
+                f,
+                "{:>4}  {:>7}  {:>6.1}  {}",
+                episode.number,
+                episode.word_count,
+                episode.estimated_minutes(self.words_per_minute),
+                episode.title
+            )?;
+        }
+        writeln!(
+            f,
+            "{} episodes, {} words, ~{:.0} minutes",
+            self.episodes.len(),
+            self.total_words(),
+            self.total_words() as f64 / self.words_per_minute.max(1) as f64
+        )?;
+        if !self.skipped.is_empty() {
+            writeln!(f, "skipped: {}", self.skipped.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn is_front_or_back_matter(chapter: &Chapter) -> bool {
+    let name = chapter.name.trim().to_lowercase();
+    let file_stem = chapter
+        .id
+        .split('#')
+        .next()
+        .and_then(|uri| uri.rsplit(['/', '\\']).next())
+        .and_then(|file| file.split('.').next())
+        .unwrap_or_default()
+        .to_lowercase();
+    FRONT_AND_BACK_MATTER
+        .iter()
+        .any(|matter| *matter == name || (*matter == file_stem && !chapter.id.contains('#')))
+}
+
+/// Groups chapters in episodes: front and back matter is dropped, tiny
+/// chapters are merged with the following ones and huge ones are split on
+/// paragraph boundaries.
+pub fn plan_episodes(chapters: Vec<Chapter>, options: &PlannerOptions) -> EpisodePlan {
+    let mut skipped = vec![];
+    let mut groups: Vec<Vec<Chapter>> = vec![];
+    let mut pending: Vec<Chapter> = vec![];
+    for chapter in chapters {
+        if options.skip_front_and_back_matter && is_front_or_back_matter(&chapter) {
+            skipped.push(chapter.name);
+            continue;
+        }
+        pending.push(chapter);
+        let pending_words: usize = pending.iter().map(|c| word_count(&c.text)).sum();
+        if pending_words >= options.min_words {
+            groups.push(std::mem::take(&mut pending));
+        }
+    }
+    if !pending.is_empty() {
+        match groups.last_mut() {
+            Some(last) => last.append(&mut pending),
+            None => groups.push(pending),
+        }
+    }
+
+    let max_words = (options.max_minutes * options.words_per_minute).max(1);
+    let mut episodes = vec![];
+    for group in groups {
+        let sources = group
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<String>>();
+        let title = match sources.as_slice() {
+            [] => String::new(),
+            [only] => only.clone(),
+            [first, .., last] => format!("{first} - {last}"),
+        };
+        let text = group
+            .into_iter()
+            .map(|c| c.text)
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let parts = split_words(&text, max_words);
+        let total_parts = parts.len();
+        for (i, part) in parts.into_iter().enumerate() {
+            episodes.push(PlannedEpisode {
+                number: episodes.len() + 1,
+                title: if total_parts > 1 {
+                    format!("{title} ({}/{total_parts})", i + 1)
+                } else {
+                    title.clone()
+                },
+                sources: sources.clone(),
+                part: (total_parts > 1).then_some((i + 1, total_parts)),
+                word_count: word_count(&part),
+                text: part,
+            });
+        }
+    }
+
+    EpisodePlan {
+        episodes,
+        skipped,
+        words_per_minute: options.words_per_minute,
+    }
+}
+
+/// Splits text in parts of similar length, with at most `max_words` each
+/// when the paragraphs allow it.
+fn split_words(text: &str, max_words: usize) -> Vec<String> {
+    let total = word_count(text);
+    if total <= max_words {
+        return vec![text.to_owned()];
+    }
+    let target = total.div_ceil(total.div_ceil(max_words));
+
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut current_words = 0;
+    for paragraph in text.split('\n') {
+        let words = word_count(paragraph);
+        if current_words > 0 && current_words + words > max_words {
+            parts.push(std::mem::take(&mut current));
+            current_words = 0;
+        }
+        current.push_str(paragraph);
+        current.push('\n');
+        current_words += words;
+        if current_words >= target {
+            parts.push(std::mem::take(&mut current));
+            current_words = 0;
+        }
+    }
+    if current_words > 0 {
+        parts.push(current);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chapter(id: &str, name: &str, words: usize) -> Chapter {
+        Chapter {
+            id: id.to_owned(),
+            order: 0,
+            name: name.to_owned(),
+            text: vec!["word"; words].join(" "),
+        }
+    }
+
+    #[test]
+    fn skips_front_and_back_matter() {
+        let chapters = vec![
+            chapter("text/titlepage.xhtml", "Titlepage", 10),
+            chapter("text/imprint.xhtml", "Imprint", 200),
+            chapter("text/one.xhtml", "One", 600),
+            chapter("text/colophon.xhtml", "The End Notes", 100),
+            chapter("text/uncopyright.xhtml", "Uncopyright", 300),
+        ];
+
+        let plan = plan_episodes(chapters, &PlannerOptions::default());
+
+        assert_eq!(plan.episodes.len(), 1);
+        assert_eq!(plan.episodes[0].title, "One");
+        assert_eq!(
+            plan.skipped,
+            vec!["Titlepage", "Imprint", "The End Notes", "Uncopyright"]
+        );
+    }
+
+    #[test]
+    fn keeps_everything_when_asked() {
+        let chapters = vec![
+            chapter("text/titlepage.xhtml", "Titlepage", 600),
+            chapter("text/one.xhtml", "One", 600),
+        ];
+        let options = PlannerOptions {
+            skip_front_and_back_matter: false,
+            ..PlannerOptions::default()
+        };
+
+        let plan = plan_episodes(chapters, &options);
+
+        assert_eq!(plan.episodes.len(), 2);
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn merges_small_chapters() {
+        let chapters = vec![
+            chapter("poems.xhtml#a", "A", 200),
+            chapter("poems.xhtml#b", "B", 200),
+            chapter("poems.xhtml#c", "C", 200),
+            chapter("poems.xhtml#d", "D", 600),
+            chapter("poems.xhtml#e", "E", 100),
+        ];
+
+        let plan = plan_episodes(chapters, &PlannerOptions::default());
+
+        assert_eq!(plan.episodes.len(), 2);
+        assert_eq!(plan.episodes[0].title, "A - C");
+        assert_eq!(plan.episodes[0].sources, vec!["A", "B", "C"]);
+        assert_eq!(plan.episodes[0].word_count, 600);
+        assert_eq!(plan.episodes[1].title, "D - E");
+        assert_eq!(plan.episodes[1].number, 2);
+        assert_eq!(plan.episodes[1].word_count, 700);
+    }
+
+    #[test]
+    fn splits_huge_chapters() {
+        let paragraph = vec!["word"; 100].join(" ");
+        let text = vec![paragraph; 25].join("\n");
+        let chapters = vec![Chapter {
+            id: "long.xhtml".to_owned(),
+            order: 1,
+            name: "Long".to_owned(),
+            text,
+        }];
+        let options = PlannerOptions {
+            max_minutes: 10,
+            words_per_minute: 100,
+            ..PlannerOptions::default()
+        };
+
+        let plan = plan_episodes(chapters, &options);
+
+        assert_eq!(plan.episodes.len(), 3);
+        assert_eq!(plan.episodes[0].title, "Long (1/3)");
+        assert_eq!(plan.episodes[2].part, Some((3, 3)));
+        assert!(plan.episodes.iter().all(|e| e.word_count <= 1000));
+        assert_eq!(plan.total_words(), 2500);
+    }
+
+    #[test]
+    fn plan_is_printable() {
+        let plan = plan_episodes(
+            vec![chapter("one.xhtml", "One", 1500)],
+            &PlannerOptions::default(),
+        );
+
+        let table = plan.to_string();
+
+        assert!(table.contains("   1     1500    10.0  One"));
+        assert!(table.contains("1 episodes, 1500 words, ~10 minutes"));
+    }
+}
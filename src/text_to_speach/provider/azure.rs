@@ -0,0 +1,490 @@
+use locale_codes::language::LanguageInfo;
+use reqwest::header;
+use xml::writer::{EmitterConfig, XmlEvent};
+
+use super::{
+    error_response, json_response, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder,
+    TtsError, Voice, VoiceGender,
+};
+
+const SSML_NAMESPACE: &str = "http://www.w3.org/2001/10/synthesis";
+
+/// Neural voice used for a language when none is chosen
+const DEFAULT_VOICES: &[(&str, &str)] = &[
+    ("en", "en-US-JennyNeural"),
+    ("it", "it-IT-ElsaNeural"),
+    ("es", "es-ES-ElviraNeural"),
+    ("fr", "fr-FR-DeniseNeural"),
+    ("de", "de-DE-KatjaNeural"),
+    ("pt", "pt-BR-FranciscaNeural"),
+    ("nl", "nl-NL-ColetteNeural"),
+    ("pl", "pl-PL-ZofiaNeural"),
+    ("ru", "ru-RU-SvetlanaNeural"),
+    ("ja", "ja-JP-NanamiNeural"),
+    ("zh", "zh-CN-XiaoxiaoNeural"),
+];
+
+#[derive(Clone)]
+pub struct AzureTtsClient {
+    api_key: Option<String>,
+    endpoint: String,
+    voice: String,
+    rate: &'static str,
+    output_format: String,
+}
+
+impl TtsClient for AzureTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let ssml = ssml(&text, &self.voice, self.rate)
+            .map_err(|e| TtsError::Unknown(format!("can't write the ssml: {e}")))?;
+
+        let mut request = reqwest::Client::new()
+            .post(&self.endpoint)
+            .header(header::CONTENT_TYPE, "application/ssml+xml")
+            .header("X-Microsoft-OutputFormat", &self.output_format)
+            .header(header::USER_AGENT, "book2pod")
+            .body(ssml);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Ocp-Apim-Subscription-Key", api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(error_response(response).await);
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        std::fs::write(&path, bytes).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+    }
+}
+
+/// Locale of a voice name, `it-IT` for `it-IT-ElsaNeural`
+fn voice_locale(voice: &str) -> String {
+    voice
+        .splitn(3, '-')
+        .take(2)
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+/// Wraps the text in a `<voice>` and `<prosody>`, with a `<break>` between paragraphs
+fn ssml(text: &str, voice: &str, rate: &str) -> Result<String, xml::writer::Error> {
+    let mut ssml = vec![];
+    let mut writer = EmitterConfig::new()
+        .write_document_declaration(false)
+        .create_writer(&mut ssml);
+
+    let locale = voice_locale(voice);
+    writer.write(
+        XmlEvent::start_element("speak")
+            .default_ns(SSML_NAMESPACE)
+            .attr("version", "1.0")
+            .attr("xml:lang", &locale),
+    )?;
+    writer.write(XmlEvent::start_element("voice").attr("name", voice))?;
+    writer.write(XmlEvent::start_element("prosody").attr("rate", rate))?;
+    for (i, paragraph) in text
+        .split('\n')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .enumerate()
+    {
+        if i > 0 {
+            writer.write(XmlEvent::start_element("break").attr("time", "750ms"))?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::characters(paragraph))?;
+    }
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(String::from_utf8_lossy(&ssml).to_string())
+}
+
+pub struct AzureTtsClientBuilder {
+    api_key: Option<String>,
+    region: Option<String>,
+    endpoint: Option<String>,
+    voice: Option<String>,
+    language: Option<String>,
+    rate: &'static str,
+    output_format: Option<String>,
+}
+
+impl AzureTtsClientBuilder {
+    /// Replaces `https://<region>.tts.speech.microsoft.com/cognitiveservices/v1`,
+    /// the api key becomes optional
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self {
+            endpoint: Some(endpoint),
+            ..self
+        }
+    }
+
+    /// Azure region of the speech resource, defaults to `AZURE_SPEECH_REGION`
+    pub fn with_region(self, region: String) -> Self {
+        Self {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    /// Value of `X-Microsoft-OutputFormat`, defaults to
+    /// `audio-24khz-96kbitrate-mono-mp3`
+    pub fn with_output_format(self, output_format: String) -> Self {
+        Self {
+            output_format: Some(output_format),
+            ..self
+        }
+    }
+
+    /// Synthesis endpoint and the key to send to it
+    fn endpoint(&self) -> Result<(String, Option<String>), TtsError> {
+        match (&self.endpoint, &self.region, &self.api_key) {
+            (Some(endpoint), _, api_key) => Ok((endpoint.clone(), api_key.clone())),
+            (None, Some(region), Some(api_key)) => Ok((
+                format!("https://{region}.tts.speech.microsoft.com/cognitiveservices/v1"),
+                Some(api_key.clone()),
+            )),
+            (None, None, _) => Err(TtsError::Unauthorized(
+                "AZURE_SPEECH_REGION is required".to_owned(),
+            )),
+            (None, Some(_), None) => Err(TtsError::Unauthorized(
+                "AZURE_SPEECH_KEY or an api_key in the config is required".to_owned(),
+            )),
+        }
+    }
+}
+
+impl TtsClientBuilder<AzureTtsClient> for AzureTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::SpeechSpeedChoice,
+            TtsCapabilites::RequiresAuth,
+        ]
+    }
+
+    /// a request can't produce more than 10 minutes of audio, this stays well below
+    fn max_input_length() -> usize {
+        5000
+    }
+
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            region: None,
+            endpoint: None,
+            voice: None,
+            language: None,
+            rate: "+0%",
+            output_format: None,
+        }
+    }
+
+    fn authorize(self) -> Self {
+        Self {
+            api_key: std::env::var("AZURE_SPEECH_KEY").ok(),
+            region: self
+                .region
+                .or_else(|| std::env::var("AZURE_SPEECH_REGION").ok()),
+            ..self
+        }
+    }
+
+    fn with_api_key(self, api_key: String) -> Self {
+        Self {
+            api_key: Some(api_key),
+            ..self
+        }
+    }
+
+    /// Name of a neural voice, like `it-IT-ElsaNeural`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            rate: match speed {
+                SpeechSpeed::VeryVerySlow => "-50%",
+                SpeechSpeed::VerySlow => "-30%",
+                SpeechSpeed::Slow => "-15%",
+                SpeechSpeed::Normal => "+0%",
+                SpeechSpeed::Quick => "+15%",
+                SpeechSpeed::VeryQuick => "+30%",
+                SpeechSpeed::VeryVeryQuick => "+50%",
+            },
+            ..self
+        }
+    }
+
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        match speed {
+            SpeechSpeed::VeryVerySlow => 0.5,
+            SpeechSpeed::VerySlow => 0.7,
+            SpeechSpeed::Slow => 0.85,
+            SpeechSpeed::Normal => 1.0,
+            SpeechSpeed::Quick => 1.15,
+            SpeechSpeed::VeryQuick => 1.3,
+            SpeechSpeed::VeryVeryQuick => 1.5,
+        }
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let (endpoint, api_key) = self.endpoint()?;
+        // the list lives next to the synthesis endpoint
+        let url = match endpoint.trim_end_matches('/').strip_suffix("/v1") {
+            Some(root) => format!("{root}/voices/list"),
+            None => format!("{}/voices/list", endpoint.trim_end_matches('/')),
+        };
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(api_key) = &api_key {
+            request = request.header("Ocp-Apim-Subscription-Key", api_key);
+        }
+        let voices = json_response(request.send().await).await?;
+        Ok(voices
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|voice| {
+                let languages = voice["Locale"]
+                    .as_str()
+                    .into_iter()
+                    .chain(
+                        voice["SecondaryLocaleList"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|l| l.as_str()),
+                    )
+                    .map(str::to_owned)
+                    .collect();
+                Voice {
+                    id: voice["ShortName"].as_str().unwrap_or_default().to_owned(),
+                    name: voice["DisplayName"]
+                        .as_str()
+                        .or(voice["LocalName"].as_str())
+                        .unwrap_or_default()
+                        .to_owned(),
+                    languages,
+                    gender: voice["Gender"].as_str().and_then(VoiceGender::parse),
+                    // it's a string in the response
+                    sample_rate: voice["SampleRateHertz"]
+                        .as_str()
+                        .and_then(|rate| rate.parse().ok()),
+                }
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<AzureTtsClient, TtsError> {
+        let (endpoint, api_key) = self.endpoint()?;
+        let voice = match self.voice {
+            // names are always <language>-<region>-<name>
+            Some(voice) if voice.splitn(3, '-').count() < 3 => {
+                return Err(TtsError::InvalidVoice(format!(
+                    "{voice} is not an azure voice name, like it-IT-ElsaNeural"
+                )))
+            }
+            Some(voice) => voice,
+            None => {
+                let language = self.language.unwrap_or("en".to_owned());
+                DEFAULT_VOICES
+                    .iter()
+                    .find(|(l, _)| *l == language)
+                    .map(|(_, voice)| voice.to_string())
+                    .ok_or(TtsError::InvalidVoice(format!(
+                        "no default azure voice for {language}, choose one"
+                    )))?
+            }
+        };
+
+        Ok(AzureTtsClient {
+            api_key,
+            endpoint,
+            voice,
+            rate: self.rate,
+            output_format: self
+                .output_format
+                .unwrap_or("audio-24khz-96kbitrate-mono-mp3".to_owned()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::Method;
+
+    use crate::{
+        config::ProviderConfig,
+        text_to_speach::provider::{
+            mock_server::{Answer, MockServer, Requests},
+            TtsClientDispatcher, TtsProvider,
+        },
+    };
+
+    use super::*;
+
+    /// Starts a stub of the azure endpoint answering with `[1, 2, 3]`
+    async fn start_mock_server() -> (String, Requests) {
+        MockServer::new()
+            .route(Method::POST, "/cognitiveservices/v1", |_| {
+                Answer::bytes([1, 2, 3])
+            })
+            .route(Method::GET, "/cognitiveservices/voices/list", |_| {
+                Answer::json(serde_json::json!([{
+                    "ShortName": "it-IT-ElsaNeural",
+                    "DisplayName": "Elsa",
+                    "LocalName": "Elsa",
+                    "Locale": "it-IT",
+                    "Gender": "Female",
+                    "SampleRateHertz": "48000",
+                }, {
+                    "ShortName": "en-US-AndrewMultilingualNeural",
+                    "DisplayName": "Andrew Multilingual",
+                    "Locale": "en-US",
+                    "SecondaryLocaleList": ["it-IT", "es-ES"],
+                    "Gender": "Male",
+                    "SampleRateHertz": "24000",
+                }]))
+            })
+            .start_at("/cognitiveservices/v1")
+            .await
+    }
+
+    #[test]
+    fn azure_ssml() {
+        let ssml = ssml("First & one.\n\nSecond <two>.", "it-IT-ElsaNeural", "+15%").unwrap();
+
+        assert_eq!(
+            ssml,
+            r#"<speak xmlns="http://www.w3.org/2001/10/synthesis" version="1.0" xml:lang="it-IT"><voice name="it-IT-ElsaNeural"><prosody rate="+15%">First &amp; one.<break time="750ms" />Second &lt;two&gt;.</prosody></voice></speak>"#
+        );
+    }
+
+    #[test]
+    fn azure_voice_for_language() {
+        let builder = || AzureTtsClientBuilder::default().with_endpoint("http://stub".to_owned());
+
+        let client = builder().build().unwrap();
+        assert_eq!(client.voice, "en-US-JennyNeural");
+
+        let client = builder()
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "it-IT-ElsaNeural");
+
+        let client = builder()
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .with_voice("it-IT-DiegoNeural".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "it-IT-DiegoNeural");
+
+        let res = builder().with_voice("Elsa".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+    }
+
+    #[test]
+    fn azure_book_language() {
+        let config = ProviderConfig {
+            api_key: Some("key".to_owned()),
+            base_url: Some("http://stub".to_owned()),
+            ..ProviderConfig::default()
+        };
+        let italian = super::super::book_language("it-IT");
+
+        let Ok(TtsClientDispatcher::Azure(client)) = TtsProvider::Azure.client(&config, italian)
+        else {
+            panic!("not an azure client");
+        };
+        assert_eq!(client.voice, "it-IT-ElsaNeural");
+        // a voice in the config still wins
+        let config = ProviderConfig {
+            voice: Some("it-IT-DiegoNeural".to_owned()),
+            ..config
+        };
+        let Ok(TtsClientDispatcher::Azure(client)) = TtsProvider::Azure.client(&config, italian)
+        else {
+            panic!("not an azure client");
+        };
+        assert_eq!(client.voice, "it-IT-DiegoNeural");
+    }
+
+    #[tokio::test]
+    async fn azure_voices() {
+        let (endpoint, requests) = start_mock_server().await;
+
+        let voices = AzureTtsClientBuilder::default()
+            .with_api_key("key".to_owned())
+            .with_endpoint(endpoint)
+            .list_voices()
+            .await
+            .unwrap();
+
+        assert_eq!(voices[0].id, "it-IT-ElsaNeural");
+        assert_eq!(voices[0].name, "Elsa");
+        assert_eq!(voices[0].gender, Some(VoiceGender::Female));
+        assert_eq!(voices[0].sample_rate, Some(48000));
+        assert_eq!(voices[1].languages, vec!["en-US", "it-IT", "es-ES"]);
+        assert!(voices.iter().all(|v| v.speaks("it")));
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.header("Ocp-Apim-Subscription-Key"), Some("key"));
+    }
+
+    #[tokio::test]
+    async fn azure_request() {
+        let (endpoint, requests) = start_mock_server().await;
+        let path = std::env::temp_dir().join("book2pod-azure.mp3");
+
+        let client = AzureTtsClientBuilder {
+            api_key: Some("key".to_owned()),
+            ..AzureTtsClientBuilder::default()
+        }
+        .with_endpoint(endpoint)
+        .for_language(locale_codes::language::lookup("es").unwrap())
+        .set_speed(SpeechSpeed::Slow)
+        .build()
+        .unwrap();
+        client
+            .speak_to_file("hola".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.header("Ocp-Apim-Subscription-Key"), Some("key"));
+        assert_eq!(
+            request.header(header::CONTENT_TYPE.as_str()),
+            Some("application/ssml+xml")
+        );
+        assert_eq!(
+            request.header("X-Microsoft-OutputFormat"),
+            Some("audio-24khz-96kbitrate-mono-mp3")
+        );
+        assert!(request
+            .text()
+            .contains(r#"<voice name="es-ES-ElviraNeural"><prosody rate="-15%">hola"#));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use locale_codes::language::LanguageInfo;
+use reqwest::multipart;
+
+use crate::util::hex_hash;
+
+use super::{
+    json_response, write_wav, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder, TtsError,
+    Voice,
+};
+
+/// One of the speakers bundled with XTTS-v2
+const DEFAULT_SPEAKER: &str = "Ana Florence";
+
+/// Languages of XTTS-v2, with the code it expects
+const LANGUAGES: &[(&str, &str)] = &[
+    ("en", "en"),
+    ("es", "es"),
+    ("fr", "fr"),
+    ("de", "de"),
+    ("it", "it"),
+    ("pt", "pt"),
+    ("pl", "pl"),
+    ("tr", "tr"),
+    ("ru", "ru"),
+    ("nl", "nl"),
+    ("cs", "cs"),
+    ("ar", "ar"),
+    ("zh", "zh-cn"),
+    ("hu", "hu"),
+    ("ko", "ko"),
+    ("ja", "ja"),
+    ("hi", "hi"),
+];
+
+#[derive(Clone)]
+enum CoquiSpeaker {
+    /// Wav of the voice to clone
+    Reference(PathBuf),
+    /// Latents of a speaker bundled with the model, asked at build
+    Studio(serde_json::Value),
+}
+
+/// Client of the XTTS-v2 streaming server (`coqui-ai/xtts-streaming-server`)
+#[derive(Clone)]
+pub struct CoquiTtsClient {
+    base_url: String,
+    speaker: CoquiSpeaker,
+    language: String,
+    /// Conditioning latents computed by the server, by sha256 of the
+    /// reference wav, shared by the clones of the client so a reference is
+    /// uploaded once and not for every chunk
+    speakers: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+}
+
+impl CoquiTtsClient {
+    /// `speaker_embedding` and `gpt_cond_latent` of the speaker
+    async fn speaker_latents(&self) -> Result<serde_json::Value, TtsError> {
+        let path = match &self.speaker {
+            CoquiSpeaker::Reference(path) => path,
+            CoquiSpeaker::Studio(latents) => return Ok(latents.clone()),
+        };
+        let wav = std::fs::read(path)
+            .map_err(|e| TtsError::Unknown(format!("can't read {}: {e}", path.display())))?;
+        // a reference is known by its content, so editing the wav clones it again
+        let key = hex_hash(&wav);
+        if let Some(latents) = self.speakers.lock().unwrap().get(&key) {
+            return Ok(latents.clone());
+        }
+
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or("reference.wav".to_owned());
+        let form = multipart::Form::new()
+            .part("wav_file", multipart::Part::bytes(wav).file_name(file_name));
+        let response = reqwest::Client::new()
+            .post(format!("{}/clone_speaker", self.base_url))
+            .multipart(form)
+            .send()
+            .await;
+        let latents = json_response(response).await?;
+        if !is_speaker(&latents) {
+            return Err(TtsError::Unknown(format!(
+                "xtts didn't clone {}",
+                path.display()
+            )));
+        }
+
+        self.speakers.lock().unwrap().insert(key, latents.clone());
+        Ok(latents)
+    }
+}
+
+fn is_speaker(latents: &serde_json::Value) -> bool {
+    !latents["speaker_embedding"].is_null() && !latents["gpt_cond_latent"].is_null()
+}
+
+/// Latents of the bundled speaker `name`, asked at build so a typo fails
+/// before the first chunk. It blocks, like the build of espeak.
+fn studio_latents(base_url: &str, name: &str) -> Result<serde_json::Value, TtsError> {
+    let mut speakers = reqwest::blocking::get(format!("{base_url}/studio_speakers"))
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json::<serde_json::Value>())
+        .map_err(|e| TtsError::ConnectionFailure(format!("can't list the xtts speakers: {e}")))?;
+    let latents = speakers[name].take();
+    if !is_speaker(&latents) {
+        return Err(TtsError::InvalidVoice(format!(
+            "{name} is not an xtts speaker, see `book2pod voices coqui`"
+        )));
+    }
+    Ok(latents)
+}
+
+impl TtsClient for CoquiTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let latents = self.speaker_latents().await?;
+        let response = reqwest::Client::new()
+            .post(format!("{}/tts", self.base_url))
+            .json(&serde_json::json!({
+                "text": text,
+                "language": self.language,
+                "speaker_embedding": latents["speaker_embedding"],
+                "gpt_cond_latent": latents["gpt_cond_latent"],
+            }))
+            .send()
+            .await;
+
+        // the wav comes back base64 encoded in a json string
+        let wav = json_response(response).await?;
+        let wav = STANDARD
+            .decode(wav.as_str().unwrap_or_default())
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        if wav.is_empty() {
+            return Err(TtsError::NoContent("xtts returned no audio".to_owned()));
+        }
+        write_wav(wav, &path).await
+    }
+}
+
+pub struct CoquiTtsClientBuilder {
+    base_url: String,
+    voice: Option<String>,
+    language: Option<String>,
+}
+
+impl CoquiTtsClientBuilder {
+    /// Defaults to `XTTS_URL` or `http://localhost:8000`
+    pub fn with_base_url(self, base_url: String) -> Self {
+        Self { base_url, ..self }
+    }
+}
+
+impl TtsClientBuilder<CoquiTtsClient> for CoquiTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::VoiceCloning,
+        ]
+    }
+
+    /// xtts starts to babble past ~250 characters, italian and portuguese
+    /// even sooner
+    fn max_input_length() -> usize {
+        200
+    }
+
+    fn default() -> Self {
+        Self {
+            base_url: std::env::var("XTTS_URL").unwrap_or("http://localhost:8000".to_owned()),
+            voice: std::env::var("XTTS_SPEAKER").ok(),
+            language: None,
+        }
+    }
+
+    fn authorize(self) -> Self {
+        self
+    }
+
+    /// Path of a reference wav to clone, a few seconds of clean speech, or
+    /// the name of a bundled speaker like `Ana Florence`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    /// xtts can't change speed
+    fn set_speed(self, _speed: SpeechSpeed) -> Self {
+        self
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    /// Speakers bundled with the model, any wav can be cloned too
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let response = reqwest::Client::new()
+            .get(format!(
+                "{}/studio_speakers",
+                self.base_url.trim_end_matches('/')
+            ))
+            .send()
+            .await;
+        let speakers = json_response(response).await?;
+        let mut names = speakers
+            .as_object()
+            .map(|speakers| speakers.keys().cloned().collect::<Vec<String>>())
+            .unwrap_or_default();
+        names.sort();
+        Ok(names
+            .into_iter()
+            .map(|name| Voice {
+                id: name.clone(),
+                name,
+                languages: LANGUAGES.iter().map(|(l, _)| l.to_string()).collect(),
+                gender: None,
+                sample_rate: Some(24000),
+            })
+            .collect())
+    }
+
+    /// Asks the server for the latents of a bundled speaker, a blocking
+    /// request
+    fn build(self) -> Result<CoquiTtsClient, TtsError> {
+        let base_url = self.base_url.trim_end_matches('/').to_owned();
+        let language = self.language.unwrap_or("en".to_owned());
+        let language = LANGUAGES
+            .iter()
+            .find(|(l, _)| *l == language)
+            .map(|(_, code)| code.to_string())
+            .ok_or(TtsError::InvalidVoice(format!(
+                "xtts can't speak {language}"
+            )))?;
+        let speaker = match self.voice {
+            Some(voice) if PathBuf::from(&voice).is_file() => CoquiSpeaker::Reference(voice.into()),
+            Some(voice) if voice.ends_with(".wav") => {
+                return Err(TtsError::InvalidVoice(format!(
+                    "{voice} doesn't exist, can't clone it"
+                )))
+            }
+            voice => CoquiSpeaker::Studio(studio_latents(
+                &base_url,
+                voice.as_deref().unwrap_or(DEFAULT_SPEAKER),
+            )?),
+        };
+
+        Ok(CoquiTtsClient {
+            base_url,
+            speaker,
+            language,
+            speakers: Arc::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::Method;
+
+    use crate::text_to_speach::{
+        chunker::wav,
+        provider::mock_server::{requests_to, Answer, MockServer, Requests},
+    };
+
+    use super::*;
+
+    fn latents(name: &str) -> serde_json::Value {
+        serde_json::json!({ "speaker_embedding": [name], "gpt_cond_latent": [[1.5]] })
+    }
+
+    /// Starts a fake xtts server answering with a wav of the text
+    async fn start_mock_server() -> (String, Requests) {
+        MockServer::new()
+            .route(Method::POST, "/clone_speaker", |_| {
+                Answer::json(latents("cloned"))
+            })
+            .route(Method::GET, "/studio_speakers", |_| {
+                Answer::json(serde_json::json!({
+                    "Ana Florence": latents("ana"),
+                    "Aaron Dreschner": latents("aaron"),
+                }))
+            })
+            .route(Method::POST, "/tts", |request| {
+                let text = request.json()["text"].as_str().unwrap().to_owned();
+                let wav = wav::pcm_to_wav(text.as_bytes(), 24000, 1, 16);
+                Answer::json(serde_json::json!(STANDARD.encode(wav)))
+            })
+            .start()
+            .await
+    }
+
+    /// Off the runtime like `TtsProvider::client`, the build of a bundled
+    /// speaker blocks
+    async fn build(builder: CoquiTtsClientBuilder) -> Result<CoquiTtsClient, TtsError> {
+        tokio::task::spawn_blocking(move || builder.build())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn coqui_studio_speaker() {
+        let (address, requests) = start_mock_server().await;
+        let dir = std::env::temp_dir().join("book2pod-coqui-studio");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let client = build(
+            CoquiTtsClientBuilder {
+                voice: None,
+                ..CoquiTtsClientBuilder::default()
+            }
+            .with_base_url(address)
+            .for_language(locale_codes::language::lookup("it").unwrap()),
+        )
+        .await
+        .unwrap();
+        // the chunks are spoken by clones of the client
+        for chunk in ["ciao", "mondo"] {
+            let path = dir.join(format!("{chunk}.wav"));
+            client
+                .clone()
+                .speak_to_file(chunk.to_owned(), path.to_string_lossy().to_string())
+                .await
+                .unwrap();
+            assert_eq!(
+                std::fs::read(&path).unwrap(),
+                wav::pcm_to_wav(chunk.as_bytes(), 24000, 1, 16)
+            );
+        }
+
+        assert_eq!(requests_to(&requests, "/studio_speakers").len(), 1);
+        assert_eq!(
+            requests_to(&requests, "/tts")[1].json(),
+            serde_json::json!({
+                "text": "mondo",
+                "language": "it",
+                "speaker_embedding": ["ana"],
+                "gpt_cond_latent": [[1.5]],
+            })
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn coqui_voice_cloning() {
+        let (address, requests) = start_mock_server().await;
+        let dir = std::env::temp_dir().join("book2pod-coqui-cloning");
+        std::fs::create_dir_all(&dir).unwrap();
+        let reference = dir.join("narrator.wav");
+        std::fs::write(&reference, wav::pcm_to_wav(b"narrator", 22050, 1, 16)).unwrap();
+
+        let client = build(
+            CoquiTtsClientBuilder::default()
+                .with_base_url(address)
+                .with_voice(reference.to_string_lossy().to_string()),
+        )
+        .await
+        .unwrap();
+        let speak = |text: &str| {
+            let path = dir.join("out.wav");
+            client
+                .clone()
+                .speak_to_file(text.to_owned(), path.to_string_lossy().to_string())
+        };
+        speak("hello").await.unwrap();
+        speak("again").await.unwrap();
+
+        let upload = requests_to(&requests, "/clone_speaker")[0].text();
+        assert!(upload.contains(r#"name="wav_file"; filename="narrator.wav""#));
+        assert!(upload.contains("narrator"));
+        let body = requests_to(&requests, "/tts")[0].json();
+        assert_eq!(body["speaker_embedding"], serde_json::json!(["cloned"]));
+        assert_eq!(body["language"], "en");
+        assert_eq!(requests_to(&requests, "/clone_speaker").len(), 1);
+
+        // a new take of the reference under the same name is cloned again
+        std::fs::write(&reference, wav::pcm_to_wav(b"new take", 22050, 1, 16)).unwrap();
+        speak("hello").await.unwrap();
+        let uploads = requests_to(&requests, "/clone_speaker");
+        assert_eq!(uploads.len(), 2);
+        assert!(uploads[1].text().contains("new take"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn coqui_voices() {
+        let (address, _) = start_mock_server().await;
+
+        let builder = CoquiTtsClientBuilder::default().with_base_url(address.clone());
+        let voices = builder.list_voices().await.unwrap();
+        let names = voices.iter().map(|v| v.id.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["Aaron Dreschner", "Ana Florence"]);
+        assert!(voices[0].speaks("it"));
+
+        let res = builder
+            .with_voice("/nowhere/narrator.wav".to_owned())
+            .build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+        // a typo in the name of a bundled speaker
+        let res = build(
+            CoquiTtsClientBuilder::default()
+                .with_base_url(address)
+                .with_voice("Ana Florense".to_owned()),
+        )
+        .await;
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+
+        let res = CoquiTtsClientBuilder::default()
+            .for_language(locale_codes::language::lookup("fi").unwrap())
+            .build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+    }
+}
@@ -0,0 +1,452 @@
+use locale_codes::language::LanguageInfo;
+
+use super::{
+    error_response, json_response, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder,
+    TtsError, Voice, VoiceGender,
+};
+
+const ELEVENLABS_BASE_URL: &str = "https://api.elevenlabs.io/v1";
+/// "Rachel", one of the premade voices every account has
+const DEFAULT_VOICE_ID: &str = "21m00Tcm4TlvDq8ikWAM";
+
+#[derive(Clone)]
+pub struct ElevenLabsTtsClient {
+    api_key: String,
+    base_url: String,
+    voice_id: String,
+    model_id: String,
+    stability: f64,
+    similarity_boost: f64,
+    speed: f64,
+    output_format: String,
+    language: Option<String>,
+    previous_text: Option<String>,
+    next_text: Option<String>,
+}
+
+impl TtsClient for ElevenLabsTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let url = format!(
+            "{}/text-to-speech/{}",
+            self.base_url.trim_end_matches('/'),
+            self.voice_id
+        );
+        let mut body = serde_json::json!({
+            "text": text,
+            "model_id": self.model_id,
+            "voice_settings": {
+                "stability": self.stability,
+                "similarity_boost": self.similarity_boost,
+                "speed": self.speed,
+            },
+        });
+        if let Some(previous_text) = self.previous_text {
+            body["previous_text"] = previous_text.into();
+        }
+        if let Some(next_text) = self.next_text {
+            body["next_text"] = next_text.into();
+        }
+        // only the v2.5 models accept a language, the others fail when it's set
+        if let Some(language) = self.language.filter(|_| self.model_id.ends_with("v2_5")) {
+            body["language_code"] = language.into();
+        }
+
+        let response = reqwest::Client::new()
+            .post(url)
+            .query(&[("output_format", &self.output_format)])
+            .header("xi-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(error_response(response).await);
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        std::fs::write(&path, bytes).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+    }
+
+    fn with_context(self, previous_text: Option<String>, next_text: Option<String>) -> Self {
+        Self {
+            previous_text,
+            next_text,
+            ..self
+        }
+    }
+}
+
+pub struct ElevenLabsTtsClientBuilder {
+    api_key: Option<String>,
+    base_url: Option<String>,
+    voice_id: Option<String>,
+    model_id: Option<String>,
+    stability: f64,
+    similarity_boost: f64,
+    speed: f64,
+    output_format: Option<String>,
+    language: Option<String>,
+}
+
+impl ElevenLabsTtsClientBuilder {
+    /// Defaults to `https://api.elevenlabs.io/v1`
+    pub fn with_base_url(self, base_url: String) -> Self {
+        Self {
+            base_url: Some(base_url),
+            ..self
+        }
+    }
+
+    /// `eleven_multilingual_v2` (the default), `eleven_turbo_v2_5`,
+    /// `eleven_flash_v2_5`...
+    pub fn with_model(self, model_id: String) -> Self {
+        Self {
+            model_id: Some(model_id),
+            ..self
+        }
+    }
+
+    /// Between 0 and 1 (0.5 by default), lower is more expressive
+    pub fn with_stability(self, stability: f64) -> Self {
+        Self {
+            stability: stability.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Between 0 and 1 (0.75 by default), higher sticks closer to the
+    /// original voice
+    pub fn with_similarity_boost(self, similarity_boost: f64) -> Self {
+        Self {
+            similarity_boost: similarity_boost.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// `mp3_samplerate_bitrate` like `mp3_44100_128` (the default), the
+    /// episodes are mp3
+    pub fn with_output_format(self, output_format: String) -> Self {
+        Self {
+            output_format: Some(output_format),
+            ..self
+        }
+    }
+}
+
+impl TtsClientBuilder<ElevenLabsTtsClient> for ElevenLabsTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::SpeechSpeedChoice,
+            TtsCapabilites::RequiresAuth,
+        ]
+    }
+
+    /// limit of `eleven_multilingual_v2`, the turbo and flash models take more
+    fn max_input_length() -> usize {
+        10_000
+    }
+
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            base_url: None,
+            voice_id: None,
+            model_id: None,
+            stability: 0.5,
+            similarity_boost: 0.75,
+            speed: 1.0,
+            output_format: None,
+            language: None,
+        }
+    }
+
+    fn authorize(self) -> Self {
+        Self {
+            api_key: std::env::var("ELEVENLABS_API_KEY").ok(),
+            ..self
+        }
+    }
+
+    fn with_api_key(self, api_key: String) -> Self {
+        Self {
+            api_key: Some(api_key),
+            ..self
+        }
+    }
+
+    /// Id of the voice, like `21m00Tcm4TlvDq8ikWAM`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice_id: Some(voice),
+            ..self
+        }
+    }
+
+    /// ElevenLabs only accepts speeds from 0.7 to 1.2
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            speed: Self::speed_factor(speed),
+            ..self
+        }
+    }
+
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        match speed {
+            SpeechSpeed::VeryVerySlow => 0.7,
+            SpeechSpeed::VerySlow => 0.75,
+            SpeechSpeed::Slow => 0.85,
+            SpeechSpeed::Normal => 1.0,
+            SpeechSpeed::Quick => 1.1,
+            SpeechSpeed::VeryQuick => 1.15,
+            SpeechSpeed::VeryVeryQuick => 1.2,
+        }
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    /// Premade voices and the ones in the voice library of the account
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let base_url = self.base_url.as_deref().unwrap_or(ELEVENLABS_BASE_URL);
+        let response = reqwest::Client::new()
+            .get(format!("{}/voices", base_url.trim_end_matches('/')))
+            .header("xi-api-key", self.api_key.as_deref().unwrap_or_default())
+            .send()
+            .await;
+        let voices = json_response(response).await?;
+        Ok(voices["voices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|voice| {
+                let mut languages = voice["verified_languages"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|l| l["language"].as_str())
+                    .chain(voice["labels"]["language"].as_str())
+                    .map(str::to_owned)
+                    .collect::<Vec<String>>();
+                languages.dedup();
+                Voice {
+                    id: voice["voice_id"].as_str().unwrap_or_default().to_owned(),
+                    name: voice["name"].as_str().unwrap_or_default().to_owned(),
+                    languages,
+                    gender: voice["labels"]["gender"]
+                        .as_str()
+                        .and_then(VoiceGender::parse),
+                    sample_rate: None,
+                }
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<ElevenLabsTtsClient, TtsError> {
+        let api_key = self.api_key.ok_or(TtsError::Unauthorized(
+            "ELEVENLABS_API_KEY or an api_key in the config is required".to_owned(),
+        ))?;
+        // a name instead of an id is the usual mistake
+        if let Some(voice_id) = self
+            .voice_id
+            .as_ref()
+            .filter(|v| v.len() != 20 || !v.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            return Err(TtsError::InvalidVoice(format!(
+                "{voice_id} is not an elevenlabs voice id, see `book2pod voices elevenlabs`"
+            )));
+        }
+        if let Some(output_format) = self
+            .output_format
+            .as_ref()
+            .filter(|f| !f.starts_with("mp3_"))
+        {
+            return Err(TtsError::Unknown(format!(
+                "{output_format} is not an mp3 format of elevenlabs, like mp3_44100_128"
+            )));
+        }
+        Ok(ElevenLabsTtsClient {
+            api_key,
+            base_url: self.base_url.unwrap_or(ELEVENLABS_BASE_URL.to_owned()),
+            voice_id: self.voice_id.unwrap_or(DEFAULT_VOICE_ID.to_owned()),
+            model_id: self.model_id.unwrap_or("eleven_multilingual_v2".to_owned()),
+            stability: self.stability,
+            similarity_boost: self.similarity_boost,
+            speed: self.speed,
+            output_format: self.output_format.unwrap_or("mp3_44100_128".to_owned()),
+            language: self.language,
+            previous_text: None,
+            next_text: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::Method;
+    use reqwest::StatusCode;
+
+    use crate::text_to_speach::{
+        provider::mock_server::{requests_to, Answer, MockServer, Request, Requests},
+        scheduler::{EpisodeJob, Limits, Scheduler},
+    };
+
+    use super::*;
+
+    /// Starts a fake ElevenLabs api answering with `status` and `[1, 2, 3]`,
+    /// returns its base url and what it received
+    async fn start_mock_server(status: StatusCode) -> (String, Requests) {
+        MockServer::new()
+            .route(Method::POST, "/v1/text-to-speech/:voice", move |_| {
+                Answer::bytes([1, 2, 3]).status(status)
+            })
+            .route(Method::GET, "/v1/voices", |_| {
+                Answer::json(serde_json::json!({ "voices": [{
+                    "voice_id": DEFAULT_VOICE_ID,
+                    "name": "Rachel",
+                    "labels": { "gender": "female", "accent": "american" },
+                    "verified_languages": [{ "language": "en" }, { "language": "it" }],
+                }]}))
+            })
+            .start_at("/v1")
+            .await
+    }
+
+    fn builder(base_url: String) -> ElevenLabsTtsClientBuilder {
+        ElevenLabsTtsClientBuilder {
+            api_key: Some("key".to_owned()),
+            ..ElevenLabsTtsClientBuilder::default()
+        }
+        .with_base_url(base_url)
+    }
+
+    #[tokio::test]
+    async fn elevenlabs_request() {
+        let (base_url, requests) = start_mock_server(StatusCode::OK).await;
+        let path = std::env::temp_dir().join("book2pod-elevenlabs.mp3");
+
+        let client = builder(base_url)
+            .with_voice("pNInz6obpgDQGcFmaJgB".to_owned())
+            .with_model("eleven_flash_v2_5".to_owned())
+            .with_stability(0.3)
+            .with_similarity_boost(0.9)
+            .with_output_format("mp3_22050_32".to_owned())
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .set_speed(SpeechSpeed::Quick)
+            .build()
+            .unwrap();
+        client
+            .speak_to_file("ciao".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        let requests = requests_to(&requests, "/v1/text-to-speech/:voice");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/v1/text-to-speech/pNInz6obpgDQGcFmaJgB");
+        assert_eq!(requests[0].query["output_format"], "mp3_22050_32");
+        assert_eq!(requests[0].header("xi-api-key"), Some("key"));
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "text": "ciao",
+                "model_id": "eleven_flash_v2_5",
+                "voice_settings": {
+                    "stability": 0.3,
+                    "similarity_boost": 0.9,
+                    "speed": 1.1,
+                },
+                "language_code": "it",
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn elevenlabs_chunks_continuity() {
+        let (base_url, requests) = start_mock_server(StatusCode::OK).await;
+        let path = std::env::temp_dir().join("book2pod-elevenlabs-chunks.mp3");
+
+        let limits = Limits {
+            concurrency: 1,
+            requests_per_minute: None,
+        };
+        Scheduler::new(builder(base_url).build().unwrap(), limits)
+            .speak_episodes(
+                vec![EpisodeJob {
+                    chunks: vec!["one".to_owned(), "two".to_owned(), "three".to_owned()],
+                    path: path.clone(),
+                    part_extension: "mp3",
+                }],
+                |_| (),
+            )
+            .await
+            .unwrap();
+
+        let bodies = requests_to(&requests, "/v1/text-to-speech/:voice")
+            .iter()
+            .map(Request::json)
+            .collect::<Vec<serde_json::Value>>();
+        let context = bodies
+            .iter()
+            .map(|b| (b["previous_text"].as_str(), b["next_text"].as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            context,
+            vec![
+                (None, Some("two")),
+                (Some("one"), Some("three")),
+                (Some("two"), None)
+            ]
+        );
+        assert!(bodies[0].get("language_code").is_none());
+        assert_eq!(std::fs::read(&path).unwrap(), [1, 2, 3].repeat(3));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn elevenlabs_errors() {
+        let path = std::env::temp_dir().join("book2pod-elevenlabs-errors.mp3");
+        let path = path.to_string_lossy().to_string();
+
+        let (base_url, _) = start_mock_server(StatusCode::UNAUTHORIZED).await;
+        let res = builder(base_url)
+            .build()
+            .unwrap()
+            .speak_to_file("hi".to_owned(), path.clone())
+            .await;
+        assert!(matches!(res, Err(TtsError::Unauthorized(_))));
+
+        let (base_url, _) = start_mock_server(StatusCode::TOO_MANY_REQUESTS).await;
+        let res = builder(base_url)
+            .build()
+            .unwrap()
+            .speak_to_file("hi".to_owned(), path)
+            .await;
+        assert!(matches!(res, Err(TtsError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn elevenlabs_voices() {
+        let (base_url, _) = start_mock_server(StatusCode::OK).await;
+
+        let voices = builder(base_url.clone()).list_voices().await.unwrap();
+        assert_eq!(voices.len(), 1);
+        assert_eq!(voices[0].id, DEFAULT_VOICE_ID);
+        assert_eq!(voices[0].name, "Rachel");
+        assert_eq!(voices[0].languages, vec!["en", "it"]);
+        assert_eq!(voices[0].gender, Some(VoiceGender::Female));
+
+        let res = builder(base_url).with_voice("Rachel".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+    }
+}
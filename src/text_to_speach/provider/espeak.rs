@@ -0,0 +1,322 @@
+use std::process::Stdio;
+
+use locale_codes::language::LanguageInfo;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::text_to_speach::chunker::wav;
+
+use super::{
+    write_wav, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder, TtsError, Voice,
+    VoiceGender,
+};
+
+/// Robotic but installed everywhere and fully offline, good for tests
+#[derive(Clone)]
+pub struct EspeakTtsClient {
+    binary: String,
+    voice: String,
+    words_per_minute: u32,
+}
+
+impl TtsClient for EspeakTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let mut espeak = Command::new(&self.binary)
+            .args(["-v", &self.voice])
+            .args(["-s", &self.words_per_minute.to_string()])
+            .args(["--stdin", "--stdout"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| TtsError::ConnectionFailure(format!("can't run {}: {e}", self.binary)))?;
+
+        // written from another task so a full stdout can't block the text
+        let mut stdin = espeak.stdin.take().ok_or(TtsError::ConnectionFailure(
+            "espeak stdin is closed".to_owned(),
+        ))?;
+        let writer = tokio::spawn(async move { stdin.write_all(text.as_bytes()).await });
+
+        let output = espeak
+            .wait_with_output()
+            .await
+            .map_err(|e| TtsError::Unknown(e.to_string()))?;
+        if !output.status.success() {
+            return Err(TtsError::Unknown(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        writer
+            .await
+            .map_err(|e| TtsError::Unknown(e.to_string()))?
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+
+        // the streamed wav has placeholder sizes in its header, rewriting it fixes them
+        let wav = wav::concat_wav(&[output.stdout])
+            .map_err(|e| TtsError::NoContent(format!("espeak returned no audio: {e}")))?;
+        write_wav(wav, &path).await
+    }
+}
+
+/// Parses the table printed by `espeak-ng --voices`
+fn parse_voices(table: &str) -> Vec<Voice> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace().skip(1);
+            let language = columns.next()?;
+            let gender = columns
+                .next()?
+                .rsplit('/')
+                .next()
+                .and_then(VoiceGender::parse);
+            let name = columns.next()?.replace('_', " ");
+            Some(Voice {
+                id: language.to_owned(),
+                name,
+                languages: vec![language.to_owned()],
+                gender,
+                sample_rate: Some(22050),
+            })
+        })
+        .collect()
+}
+
+/// Fails unless `espeak-ng --voices` lists `voice` by language or name,
+/// whatever variant is added to it
+fn check_voice(binary: &str, voice: &str) -> Result<(), TtsError> {
+    let output = std::process::Command::new(binary)
+        .arg("--voices")
+        .output()
+        .map_err(|e| TtsError::ConnectionFailure(format!("can't run {binary}: {e}")))?;
+    if !output.status.success() {
+        return Err(TtsError::Unknown(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    let name = voice.split('+').next().unwrap_or(voice).to_lowercase();
+    let known = parse_voices(&String::from_utf8_lossy(&output.stdout))
+        .iter()
+        .any(|v| v.id.to_lowercase() == name || v.name.to_lowercase() == name.replace('_', " "));
+    if !known {
+        return Err(TtsError::InvalidVoice(format!(
+            "{voice} is not an espeak voice, like en-us or it+f3"
+        )));
+    }
+    Ok(())
+}
+
+/// What espeak's `-s` takes, 175 is its default
+fn words_per_minute(speed: SpeechSpeed) -> u32 {
+    match speed {
+        SpeechSpeed::VeryVerySlow => 80,
+        SpeechSpeed::VerySlow => 110,
+        SpeechSpeed::Slow => 140,
+        SpeechSpeed::Normal => 175,
+        SpeechSpeed::Quick => 210,
+        SpeechSpeed::VeryQuick => 260,
+        SpeechSpeed::VeryVeryQuick => 320,
+    }
+}
+
+pub struct EspeakTtsClientBuilder {
+    binary: String,
+    voice: Option<String>,
+    language: Option<String>,
+    words_per_minute: u32,
+}
+
+impl TtsClientBuilder<EspeakTtsClient> for EspeakTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::SpeechSpeedChoice,
+        ]
+    }
+
+    /// espeak has no limit, this only keeps every call short
+    fn max_input_length() -> usize {
+        10_000
+    }
+
+    fn default() -> Self {
+        Self {
+            binary: std::env::var("ESPEAK_BIN").unwrap_or("espeak-ng".to_owned()),
+            voice: None,
+            language: None,
+            words_per_minute: 175,
+        }
+    }
+
+    fn authorize(self) -> Self {
+        self
+    }
+
+    /// Any `-v` value, like `en-us` or `it+f3`, a bare variant like `+f3` is
+    /// added to the language
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            words_per_minute: words_per_minute(speed),
+            ..self
+        }
+    }
+
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        words_per_minute(speed) as f64 / words_per_minute(SpeechSpeed::Normal) as f64
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    /// Variants like `+f3` can be added to any of them
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let voices = match &self.language {
+            Some(language) => format!("--voices={language}"),
+            None => "--voices".to_owned(),
+        };
+        let output = Command::new(&self.binary)
+            .arg(voices)
+            .output()
+            .await
+            .map_err(|e| TtsError::ConnectionFailure(format!("can't run {}: {e}", self.binary)))?;
+        if !output.status.success() {
+            return Err(TtsError::Unknown(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        Ok(parse_voices(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn build(self) -> Result<EspeakTtsClient, TtsError> {
+        let language = self.language.unwrap_or("en".to_owned());
+        let voice = match self.voice {
+            Some(variant) if variant.starts_with('+') => format!("{language}{variant}"),
+            Some(voice) => {
+                check_voice(&self.binary, &voice)?;
+                voice
+            }
+            None => language,
+        };
+        Ok(EspeakTtsClient {
+            binary: self.binary,
+            voice,
+            words_per_minute: self.words_per_minute,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn espeak_voice() {
+        let client = EspeakTtsClientBuilder::default().build().unwrap();
+        assert_eq!(client.voice, "en");
+        assert_eq!(client.words_per_minute, 175);
+
+        let client = EspeakTtsClientBuilder::default()
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .with_voice("+f3".to_owned())
+            .set_speed(SpeechSpeed::Quick)
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "it+f3");
+        assert_eq!(client.words_per_minute, 210);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn espeak_checks_voice() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = std::env::temp_dir().join("book2pod-espeak-voices");
+        fs::create_dir_all(&dir).unwrap();
+        // stands in for espeak-ng, lists two voices
+        let binary = dir.join("espeak.sh");
+        fs::write(
+            &binary,
+            "#!/bin/sh\nprintf '%s\\n' 'Pty Language Age/Gender VoiceName File' ' 5  af --/M Afrikaans gmw/af' ' 2  en-us --/F English_(America) gmw/en-US'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        let builder = || EspeakTtsClientBuilder {
+            binary: binary.to_string_lossy().to_string(),
+            ..EspeakTtsClientBuilder::default()
+        };
+
+        for voice in ["en-us", "EN-US+f3", "English_(America)"] {
+            let client = builder()
+                .for_language(locale_codes::language::lookup("it").unwrap())
+                .with_voice(voice.to_owned())
+                .build()
+                .unwrap();
+            assert_eq!(client.voice, voice);
+        }
+        let res = builder().with_voice("klingon".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn espeak_voices() {
+        let voices = parse_voices(
+            "Pty Language       Age/Gender VoiceName          File                 Other Languages
+ 5  af              --/M      Afrikaans          gmw/af
+ 2  en-us           --/F      English_(America)  gmw/en-US            (en 3)
+",
+        );
+
+        assert_eq!(voices.len(), 2);
+        assert_eq!(voices[1].id, "en-us");
+        assert_eq!(voices[1].name, "English (America)");
+        assert_eq!(voices[1].gender, Some(VoiceGender::Female));
+        assert!(voices[1].speaks("en"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn espeak_arguments_and_wav() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = std::env::temp_dir().join("book2pod-espeak");
+        fs::create_dir_all(&dir).unwrap();
+        // stands in for espeak-ng, answers with a streamed wav of its arguments
+        let binary = dir.join("espeak.sh");
+        fs::write(
+            &binary,
+            "#!/bin/sh\ncat > /dev/null\nprintf 'RIFF\\377\\377\\377\\377WAVEfmt \\020\\0\\0\\0\\1\\0\\1\\0\\042\\126\\0\\0\\104\\254\\0\\0\\2\\0\\020\\0data\\377\\377\\377\\377'\nprintf '%s' \"$*\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let client = EspeakTtsClient {
+            binary: binary.to_string_lossy().to_string(),
+            voice: "it".to_owned(),
+            words_per_minute: 140,
+        };
+        let path = dir.join("out.wav");
+        client
+            .speak_to_file("ciao".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            wav::pcm_to_wav(b"-v it -s 140 --stdin --stdout", 22050, 1, 16)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
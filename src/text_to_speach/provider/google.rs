@@ -1,26 +1,41 @@
 use std::str::FromStr;
 
 use locale_codes::language::LanguageInfo;
-use tts_rust::{languages::Languages, tts::GTTSClient};
+use tts_rust::languages::Languages;
 
-use super::{TtsCapabilites, TtsClient, TtsClientBuilder, TtsError};
+use super::{TtsCapabilites, TtsClient, TtsClientBuilder, TtsError, Voice};
+
+/// Language code and volume of a `tts_rust` client, which is made again for
+/// every chunk
+#[derive(Clone)]
+pub struct GTTSClient {
+    volume: f32,
+    language: String,
+}
 
 pub struct GTTSClientBuilder {
     volume: f32,
-    language: Languages,
+    language: String,
+    /// Asked language gtts can't speak, an error once built
+    unsupported_language: Option<String>,
 }
 
-//this provider is only for tests, google max len is 100 chars, so it's useless
+//demo provider, google translate max len is 100 chars, use google_cloud for real books
 
 impl TtsClientBuilder<GTTSClient> for GTTSClientBuilder {
     fn capabilities() -> &'static [TtsCapabilites] {
         &[TtsCapabilites::LanguageChoice]
     }
 
+    fn max_input_length() -> usize {
+        100
+    }
+
     fn default() -> Self {
         Self {
             volume: 1.0,
-            language: Languages::English,
+            language: "en".to_owned(),
+            unsupported_language: None,
         }
     }
 
@@ -33,17 +48,32 @@ impl TtsClientBuilder<GTTSClient> for GTTSClientBuilder {
     }
 
     fn for_language(mut self, language: &LanguageInfo) -> Self {
-        self.language =
-            Languages::from_str(language.short_code.to_owned().unwrap().as_str()).unwrap();
+        match language
+            .short_code
+            .as_deref()
+            .filter(|code| Languages::from_str(code).is_ok())
+        {
+            Some(code) => {
+                self.language = code.to_owned();
+                self.unsupported_language = None;
+            }
+            None => self.unsupported_language = Some(language.reference_name.clone()),
+        }
         self
     }
 
-    fn build(self) -> GTTSClient {
-        GTTSClient {
+    /// gtts has one voice per language
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        self.check_language()?;
+        Ok(vec![])
+    }
+
+    fn build(self) -> Result<GTTSClient, TtsError> {
+        self.check_language()?;
+        Ok(GTTSClient {
             volume: self.volume,
             language: self.language,
-            tld: "com",
-        }
+        })
     }
 
     fn set_speed(self, _speed: super::SpeechSpeed) -> Self {
@@ -51,10 +81,33 @@ impl TtsClientBuilder<GTTSClient> for GTTSClientBuilder {
     }
 }
 
+impl GTTSClientBuilder {
+    fn check_language(&self) -> Result<(), TtsError> {
+        match &self.unsupported_language {
+            Some(language) => Err(TtsError::InvalidVoice(format!(
+                "gtts doesn't speak {language}"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
 impl TtsClient for GTTSClient {
     async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
-        let result = self.save_to_file(text.as_str(), path.as_str());
-        result.map_err(TtsError::Unknown)
+        // tts_rust blocks on the request
+        tokio::task::spawn_blocking(move || {
+            let language = Languages::from_str(&self.language).map_err(|_| {
+                TtsError::InvalidVoice(format!("gtts doesn't speak {}", self.language))
+            })?;
+            let client = tts_rust::tts::GTTSClient {
+                volume: self.volume,
+                language,
+                tld: "com",
+            };
+            client.save_to_file(&text, &path).map_err(TtsError::Unknown)
+        })
+        .await
+        .map_err(|e| TtsError::Unknown(e.to_string()))?
     }
 }
 
@@ -63,10 +116,26 @@ mod test {
     use super::*;
 
     #[tokio::test]
+    async fn gtts_unsupported_language() {
+        // greenlandic isn't spoken, old english has no two letter code
+        for code in ["kl", "ang"] {
+            let builder = GTTSClientBuilder::default()
+                .for_language(locale_codes::language::lookup(code).unwrap());
+            assert!(matches!(
+                builder.list_voices().await,
+                Err(TtsError::InvalidVoice(_))
+            ));
+            assert!(matches!(builder.build(), Err(TtsError::InvalidVoice(_))));
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the network"]
     async fn google_test_it() {
         let client = GTTSClientBuilder::default()
             .for_language(locale_codes::language::lookup("it").unwrap())
-            .build();
+            .build()
+            .unwrap();
 
         client
             .speak_to_file("ciao ciao ciao".to_owned(), "test.it.mp3".to_owned())
@@ -75,10 +144,12 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "needs the network"]
     async fn google_test_en() {
         let client = GTTSClientBuilder::default()
             .for_language(locale_codes::language::lookup("en").unwrap())
-            .build();
+            .build()
+            .unwrap();
 
         client
             .speak_to_file("hello hello hello".to_owned(), "test.en.mp3".to_owned())
@@ -87,10 +158,12 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "needs the network"]
     async fn google_test_es() {
         let client = GTTSClientBuilder::default()
             .for_language(locale_codes::language::lookup("es").unwrap())
-            .build();
+            .build()
+            .unwrap();
 
         client
             .speak_to_file("hola hola hola".to_owned(), "test.es.mp3".to_owned())
@@ -0,0 +1,737 @@
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use locale_codes::language::LanguageInfo;
+use reqwest::RequestBuilder;
+use xml::writer::{EmitterConfig, XmlEvent};
+
+use super::{
+    error_response, json_response, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder,
+    TtsError, Voice, VoiceGender,
+};
+
+const GOOGLE_CLOUD_BASE_URL: &str = "https://texttospeech.googleapis.com/v1";
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+/// The api takes at most this many bytes of ssml, tags included
+const MAX_SSML_BYTES: usize = 5000;
+
+/// Locale used for a language, when it is not `<code>-<CODE>`
+const LOCALES: &[(&str, &str)] = &[
+    ("en", "en-US"),
+    ("pt", "pt-BR"),
+    ("zh", "cmn-CN"),
+    ("ja", "ja-JP"),
+    ("ko", "ko-KR"),
+    ("hi", "hi-IN"),
+    ("uk", "uk-UA"),
+    ("cs", "cs-CZ"),
+    ("el", "el-GR"),
+    ("da", "da-DK"),
+    ("sv", "sv-SE"),
+    ("nb", "nb-NO"),
+    ("vi", "vi-VN"),
+    ("ar", "ar-XA"),
+];
+
+/// The few locales with a studio voice, and the voice
+const STUDIO_VOICES: &[(&str, &str)] = &[
+    ("en-US", "en-US-Studio-O"),
+    ("en-GB", "en-GB-Studio-B"),
+    ("de-DE", "de-DE-Studio-B"),
+    ("fr-FR", "fr-FR-Studio-A"),
+    ("es-US", "es-US-Studio-B"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoogleVoiceType {
+    Standard,
+    Wavenet,
+    Neural2,
+    /// Made for narration, only a handful of locales have one
+    Studio,
+}
+
+impl FromStr for GoogleVoiceType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(GoogleVoiceType::Standard),
+            "wavenet" => Ok(GoogleVoiceType::Wavenet),
+            "neural2" => Ok(GoogleVoiceType::Neural2),
+            "studio" => Ok(GoogleVoiceType::Studio),
+            other => Err(anyhow!("unknown google voice type: {other}")),
+        }
+    }
+}
+
+impl GoogleVoiceType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GoogleVoiceType::Standard => "Standard",
+            GoogleVoiceType::Wavenet => "Wavenet",
+            GoogleVoiceType::Neural2 => "Neural2",
+            GoogleVoiceType::Studio => "Studio",
+        }
+    }
+}
+
+/// Access token and its expiry
+type AccessToken = Option<(String, SystemTime)>;
+
+/// The interesting fields of the json key of a service account
+#[derive(Clone)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+    /// Tokens last an hour, every chunk reuses the last one, the clones of a
+    /// client share it
+    token: Arc<Mutex<AccessToken>>,
+}
+
+impl ServiceAccount {
+    fn from_file(path: &PathBuf) -> Result<Self, String> {
+        let key = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let key = serde_json::from_slice::<serde_json::Value>(&key)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let field = |name: &str| {
+            key[name]
+                .as_str()
+                .map(|v| v.to_owned())
+                .ok_or(format!("{} has no {name}", path.display()))
+        };
+        Ok(Self {
+            client_email: field("client_email")?,
+            private_key: field("private_key")?,
+            token_uri: field("token_uri")
+                .unwrap_or("https://oauth2.googleapis.com/token".to_owned()),
+            token: Arc::default(),
+        })
+    }
+
+    /// Signed jwt exchanged for an access token
+    fn assertion(&self, now: SystemTime) -> Result<String, TtsError> {
+        let iat = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let claims = serde_json::json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": iat,
+            "exp": iat + 3600,
+        });
+        let key = EncodingKey::from_rsa_pem(self.private_key.as_bytes())
+            .map_err(|e| TtsError::Unauthorized(format!("bad service account key: {e}")))?;
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+            .map_err(|e| TtsError::Unauthorized(e.to_string()))
+    }
+}
+
+async fn access_token(account: &ServiceAccount) -> Result<String, TtsError> {
+    let now = SystemTime::now();
+    if let Some((token, expiry)) = account.token.lock().unwrap().as_ref() {
+        if *expiry > now + Duration::from_secs(60) {
+            return Ok(token.clone());
+        }
+    }
+
+    let response = reqwest::Client::new()
+        .post(&account.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", &account.assertion(now)?),
+        ])
+        .send()
+        .await
+        .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+    if !response.status().is_success() {
+        let message = response.text().await.unwrap_or("".to_owned());
+        return Err(TtsError::Unauthorized(message));
+    }
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| TtsError::Unauthorized(e.to_string()))?;
+    let token = body["access_token"]
+        .as_str()
+        .ok_or(TtsError::Unauthorized(
+            "no access_token in the answer".to_owned(),
+        ))?
+        .to_owned();
+    let expiry = now + Duration::from_secs(body["expires_in"].as_u64().unwrap_or(3600));
+
+    *account.token.lock().unwrap() = Some((token.clone(), expiry));
+    Ok(token)
+}
+
+#[derive(Clone)]
+enum GoogleAuth {
+    ApiKey(String),
+    ServiceAccount(ServiceAccount),
+    None,
+}
+
+impl GoogleAuth {
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder, TtsError> {
+        Ok(match self {
+            GoogleAuth::ApiKey(key) => request.header("x-goog-api-key", key),
+            GoogleAuth::ServiceAccount(account) => {
+                request.bearer_auth(access_token(account).await?)
+            }
+            GoogleAuth::None => request,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct GoogleCloudTtsClient {
+    auth: GoogleAuth,
+    base_url: String,
+    language_code: String,
+    voice: String,
+    speaking_rate: f64,
+    audio_encoding: String,
+}
+
+impl TtsClient for GoogleCloudTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let ssml =
+            ssml(&text).map_err(|e| TtsError::Unknown(format!("can't write the ssml: {e}")))?;
+        if ssml.len() > MAX_SSML_BYTES {
+            return Err(TtsError::Unknown(format!(
+                "the ssml of this chunk is {} bytes, google takes {MAX_SSML_BYTES}",
+                ssml.len()
+            )));
+        }
+        let body = serde_json::json!({
+            "input": { "ssml": ssml },
+            "voice": { "languageCode": self.language_code, "name": self.voice },
+            "audioConfig": {
+                "audioEncoding": self.audio_encoding,
+                "speakingRate": self.speaking_rate,
+            },
+        });
+
+        let url = format!("{}/text:synthesize", self.base_url.trim_end_matches('/'));
+        let request = reqwest::Client::new().post(url).json(&body);
+        let response = self
+            .auth
+            .authorize(request)
+            .await?
+            .send()
+            .await
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(error_response(response).await);
+        }
+
+        let body = response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        let audio = body["audioContent"].as_str().ok_or(TtsError::NoContent(
+            "no audioContent in the answer".to_owned(),
+        ))?;
+        let audio = STANDARD
+            .decode(audio)
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        std::fs::write(&path, audio).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+    }
+}
+
+/// One `<p>` per paragraph
+fn ssml(text: &str) -> Result<String, xml::writer::Error> {
+    let mut ssml = vec![];
+    let mut writer = EmitterConfig::new()
+        .write_document_declaration(false)
+        .create_writer(&mut ssml);
+
+    writer.write(XmlEvent::start_element("speak"))?;
+    for paragraph in text.split('\n').map(str::trim).filter(|p| !p.is_empty()) {
+        writer.write(XmlEvent::start_element("p"))?;
+        writer.write(XmlEvent::characters(paragraph))?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(String::from_utf8_lossy(&ssml).to_string())
+}
+
+/// BCP-47 code google uses for an ISO 639-1 language, `it` becomes `it-IT`
+fn language_locale(language: &str) -> String {
+    LOCALES
+        .iter()
+        .find(|(l, _)| *l == language)
+        .map(|(_, locale)| locale.to_string())
+        .unwrap_or_else(|| format!("{language}-{}", language.to_uppercase()))
+}
+
+/// Locale of a voice name, `it-IT` for `it-IT-Neural2-A`
+fn voice_locale(voice: &str) -> String {
+    voice
+        .splitn(3, '-')
+        .take(2)
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+pub struct GoogleCloudTtsClientBuilder {
+    api_key: Option<String>,
+    service_account: Option<PathBuf>,
+    base_url: Option<String>,
+    voice: Option<String>,
+    voice_type: GoogleVoiceType,
+    language: Option<String>,
+    speaking_rate: f64,
+    audio_encoding: Option<String>,
+}
+
+impl GoogleCloudTtsClientBuilder {
+    /// Defaults to `https://texttospeech.googleapis.com/v1`, the credentials
+    /// become optional
+    pub fn with_base_url(self, base_url: String) -> Self {
+        Self {
+            base_url: Some(base_url),
+            ..self
+        }
+    }
+
+    /// Json key of a service account, used instead of an api key
+    pub fn with_service_account(self, key_file: PathBuf) -> Self {
+        Self {
+            service_account: Some(key_file),
+            ..self
+        }
+    }
+
+    /// Family of the voice picked for the language, defaults to Neural2,
+    /// ignored when a voice is chosen
+    pub fn with_voice_type(self, voice_type: GoogleVoiceType) -> Self {
+        Self { voice_type, ..self }
+    }
+
+    /// `MP3` (the default), `OGG_OPUS`, `LINEAR16`...
+    pub fn with_audio_encoding(self, audio_encoding: String) -> Self {
+        Self {
+            audio_encoding: Some(audio_encoding),
+            ..self
+        }
+    }
+}
+
+impl GoogleCloudTtsClientBuilder {
+    fn auth(&self) -> Result<GoogleAuth, TtsError> {
+        match (&self.api_key, &self.service_account) {
+            (Some(key), _) => Ok(GoogleAuth::ApiKey(key.clone())),
+            (None, Some(key_file)) => Ok(GoogleAuth::ServiceAccount(
+                ServiceAccount::from_file(key_file).map_err(|e| {
+                    TtsError::Unauthorized(format!("can't read the service account key {e}"))
+                })?,
+            )),
+            (None, None) if self.base_url.is_some() => Ok(GoogleAuth::None),
+            (None, None) => Err(TtsError::Unauthorized(
+                "GOOGLE_API_KEY, GOOGLE_APPLICATION_CREDENTIALS or a key in the config is required"
+                    .to_owned(),
+            )),
+        }
+    }
+}
+
+impl TtsClientBuilder<GoogleCloudTtsClient> for GoogleCloudTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::SpeechSpeedChoice,
+            TtsCapabilites::RequiresAuth,
+        ]
+    }
+
+    /// the api takes 5000 bytes of ssml, chinese, japanese or hindi are 3
+    /// bytes a character, this leaves room for them and a `<p>` every few
+    /// lines
+    fn max_input_length() -> usize {
+        1200
+    }
+
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            service_account: None,
+            base_url: None,
+            voice: None,
+            voice_type: GoogleVoiceType::Neural2,
+            language: None,
+            speaking_rate: 1.0,
+            audio_encoding: None,
+        }
+    }
+
+    /// Reads `GOOGLE_API_KEY`, or the service account key file in
+    /// `GOOGLE_APPLICATION_CREDENTIALS`
+    fn authorize(self) -> Self {
+        Self {
+            api_key: std::env::var("GOOGLE_API_KEY").ok(),
+            service_account: self.service_account.or_else(|| {
+                std::env::var("GOOGLE_APPLICATION_CREDENTIALS")
+                    .ok()
+                    .map(PathBuf::from)
+            }),
+            ..self
+        }
+    }
+
+    fn with_api_key(self, api_key: String) -> Self {
+        Self {
+            api_key: Some(api_key),
+            ..self
+        }
+    }
+
+    /// Name of a voice, like `it-IT-Wavenet-A`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            speaking_rate: Self::speed_factor(speed),
+            ..self
+        }
+    }
+
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        match speed {
+            SpeechSpeed::VeryVerySlow => 0.6,
+            SpeechSpeed::VerySlow => 0.75,
+            SpeechSpeed::Slow => 0.9,
+            SpeechSpeed::Normal => 1.0,
+            SpeechSpeed::Quick => 1.15,
+            SpeechSpeed::VeryQuick => 1.3,
+            SpeechSpeed::VeryVeryQuick => 1.6,
+        }
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let base_url = self.base_url.as_deref().unwrap_or(GOOGLE_CLOUD_BASE_URL);
+        let mut request =
+            reqwest::Client::new().get(format!("{}/voices", base_url.trim_end_matches('/')));
+        if let Some(language) = &self.language {
+            request = request.query(&[("languageCode", language_locale(language))]);
+        }
+        let response = self.auth()?.authorize(request).await?.send().await;
+        let body = json_response(response).await?;
+
+        Ok(body["voices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|voice| {
+                let id = voice["name"].as_str()?.to_owned();
+                Some(Voice {
+                    name: id.clone(),
+                    id,
+                    languages: voice["languageCodes"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|l| l.as_str().map(|l| l.to_owned()))
+                        .collect(),
+                    gender: voice["ssmlGender"].as_str().and_then(VoiceGender::parse),
+                    sample_rate: voice["naturalSampleRateHertz"].as_u64().map(|r| r as u32),
+                })
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<GoogleCloudTtsClient, TtsError> {
+        let auth = self.auth()?;
+        let voice = match self.voice {
+            // <locale>-<type>-<variant>, like en-US-Neural2-A or en-US-Chirp3-HD-Aoede
+            Some(voice) if voice.split('-').count() < 4 => {
+                return Err(TtsError::InvalidVoice(format!(
+                    "{voice} is not a google voice, they look like en-US-Neural2-A"
+                )))
+            }
+            Some(voice) => voice,
+            None => {
+                let locale = language_locale(&self.language.unwrap_or("en".to_owned()));
+                match self.voice_type {
+                    GoogleVoiceType::Studio => STUDIO_VOICES
+                        .iter()
+                        .find(|(l, _)| *l == locale)
+                        .map(|(_, voice)| voice.to_string())
+                        .ok_or(TtsError::InvalidVoice(format!(
+                            "no studio voice for {locale}, choose one"
+                        )))?,
+                    voice_type => format!("{locale}-{}-A", voice_type.as_str()),
+                }
+            }
+        };
+
+        Ok(GoogleCloudTtsClient {
+            auth,
+            base_url: self.base_url.unwrap_or(GOOGLE_CLOUD_BASE_URL.to_owned()),
+            language_code: voice_locale(&voice),
+            voice,
+            speaking_rate: self.speaking_rate,
+            audio_encoding: self.audio_encoding.unwrap_or("MP3".to_owned()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::OnceLock;
+
+    use axum::http::Method;
+    use rsa::{
+        pkcs8::{EncodePrivateKey, LineEnding},
+        rand_core::OsRng,
+        RsaPrivateKey,
+    };
+
+    use super::*;
+    use crate::text_to_speach::{
+        chunker,
+        provider::mock_server::{requests_to, Answer, MockServer, Requests},
+    };
+
+    /// Only signs test tokens, generated once for every test
+    fn test_private_key() -> &'static str {
+        static KEY: OnceLock<String> = OnceLock::new();
+        KEY.get_or_init(|| {
+            let key = RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+            key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string()
+        })
+    }
+
+    /// Starts a fake api with the synthesize, voices and token endpoints,
+    /// answering with `[1, 2, 3]` as audio
+    async fn start_mock_server() -> (String, Requests) {
+        MockServer::new()
+            .route(Method::POST, "/v1/text:synthesize", |_| {
+                Answer::json(serde_json::json!({ "audioContent": "AQID" }))
+            })
+            .route(Method::GET, "/v1/voices", |_| {
+                Answer::json(serde_json::json!({ "voices": [{
+                    "languageCodes": ["it-IT"],
+                    "name": "it-IT-Neural2-A",
+                    "ssmlGender": "FEMALE",
+                    "naturalSampleRateHertz": 24000,
+                }]}))
+            })
+            .route(Method::POST, "/token", |_| {
+                Answer::json(serde_json::json!({ "access_token": "token", "expires_in": 3599 }))
+            })
+            .start()
+            .await
+    }
+
+    #[test]
+    fn google_cloud_ssml() {
+        let ssml = ssml("One & two.\n\n<Three>").unwrap();
+        assert_eq!(
+            ssml,
+            "<speak><p>One &amp; two.</p><p>&lt;Three&gt;</p></speak>"
+        );
+    }
+
+    #[test]
+    fn google_cloud_ssml_fits() {
+        let text = ["天地玄黄，宇宙洪荒。"; 500].join("\n");
+        for chunk in chunker::split_text(&text, GoogleCloudTtsClientBuilder::max_input_length()) {
+            assert!(ssml(&chunk).unwrap().len() <= MAX_SSML_BYTES);
+        }
+        let text = "नमस्ते दुनिया ".repeat(500);
+        for chunk in chunker::split_text(&text, GoogleCloudTtsClientBuilder::max_input_length()) {
+            assert!(ssml(&chunk).unwrap().len() <= MAX_SSML_BYTES);
+        }
+    }
+
+    #[test]
+    fn google_cloud_voice_for_language() {
+        let builder =
+            || GoogleCloudTtsClientBuilder::default().with_base_url("http://stub".to_owned());
+
+        let client = builder().build().unwrap();
+        assert_eq!(client.voice, "en-US-Neural2-A");
+        assert_eq!(client.language_code, "en-US");
+
+        let client = builder()
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .with_voice_type(GoogleVoiceType::Wavenet)
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "it-IT-Wavenet-A");
+        assert_eq!(client.language_code, "it-IT");
+
+        let client = builder()
+            .with_voice_type(GoogleVoiceType::Studio)
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "en-US-Studio-O");
+        let res = builder()
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .with_voice_type(GoogleVoiceType::Studio)
+            .build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+
+        let client = builder()
+            .with_voice("cmn-CN-Wavenet-B".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(client.language_code, "cmn-CN");
+    }
+
+    #[tokio::test]
+    async fn google_cloud_voices() {
+        let (address, requests) = start_mock_server().await;
+        let builder = GoogleCloudTtsClientBuilder::default()
+            .with_api_key("key".to_owned())
+            .with_base_url(format!("{address}/v1"))
+            .for_language(locale_codes::language::lookup("it").unwrap());
+
+        let voices = builder.list_voices().await.unwrap();
+        assert_eq!(
+            voices,
+            vec![Voice {
+                id: "it-IT-Neural2-A".to_owned(),
+                name: "it-IT-Neural2-A".to_owned(),
+                languages: vec!["it-IT".to_owned()],
+                gender: Some(VoiceGender::Female),
+                sample_rate: Some(24000),
+            }]
+        );
+        assert_eq!(requests.lock().unwrap()[0].query["languageCode"], "it-IT");
+
+        let res = builder.with_voice("alloy".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+    }
+
+    #[tokio::test]
+    async fn google_cloud_api_key() {
+        let (address, requests) = start_mock_server().await;
+        let path = std::env::temp_dir().join("book2pod-google-cloud.mp3");
+
+        let client = GoogleCloudTtsClientBuilder {
+            api_key: Some("key".to_owned()),
+            ..GoogleCloudTtsClientBuilder::default()
+        }
+        .with_base_url(format!("{address}/v1"))
+        .for_language(locale_codes::language::lookup("fr").unwrap())
+        .set_speed(SpeechSpeed::Quick)
+        .build()
+        .unwrap();
+        client
+            .speak_to_file("bonjour".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.header("x-goog-api-key"), Some("key"));
+        assert_eq!(
+            request.json(),
+            serde_json::json!({
+                "input": { "ssml": "<speak><p>bonjour</p></speak>" },
+                "voice": { "languageCode": "fr-FR", "name": "fr-FR-Neural2-A" },
+                "audioConfig": { "audioEncoding": "MP3", "speakingRate": 1.15 },
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn google_cloud_service_account() {
+        let (address, requests) = start_mock_server().await;
+        let dir = std::env::temp_dir().join("book2pod-google-cloud-account");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_file = dir.join("key.json");
+        std::fs::write(
+            &key_file,
+            serde_json::json!({
+                "type": "service_account",
+                "client_email": "book2pod@test.iam.gserviceaccount.com",
+                "private_key": test_private_key(),
+                "token_uri": format!("{address}/token"),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let client = GoogleCloudTtsClientBuilder::default()
+            .with_service_account(key_file)
+            .with_base_url(format!("{address}/v1"))
+            .build()
+            .unwrap();
+        let path = dir.join("out.mp3");
+        client
+            .speak_to_file("hello".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        let token = &requests_to(&requests, "/token")[0];
+        assert!(token
+            .text()
+            .contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer"));
+        let synthesize = &requests_to(&requests, "/v1/text:synthesize")[0];
+        assert_eq!(synthesize.header("authorization"), Some("Bearer token"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn google_cloud_token_reuse() {
+        let (address, requests) = start_mock_server().await;
+        let dir = std::env::temp_dir().join("book2pod-google-cloud-token");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_file = dir.join("key.json");
+        std::fs::write(
+            &key_file,
+            serde_json::json!({
+                "client_email": "book2pod@test.iam.gserviceaccount.com",
+                "private_key": test_private_key(),
+                "token_uri": format!("{address}/token"),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let client = GoogleCloudTtsClientBuilder::default()
+            .with_service_account(key_file)
+            .with_base_url(format!("{address}/v1"))
+            .build()
+            .unwrap();
+        // the chunks are spoken by clones of the client
+        for chunk in ["one", "two"] {
+            let path = dir.join(format!("{chunk}.mp3"));
+            client
+                .clone()
+                .speak_to_file(chunk.to_owned(), path.to_string_lossy().to_string())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(requests_to(&requests, "/token").len(), 1);
+        assert_eq!(requests_to(&requests, "/v1/text:synthesize").len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    Router,
+};
+use tokio::net::TcpListener;
+
+/// A request the stub got
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Request {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+/// What a route answers
+pub struct Answer {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Answer {
+    pub fn bytes(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn json(value: serde_json::Value) -> Self {
+        Self::bytes(value.to_string()).header(header::CONTENT_TYPE.as_str(), "application/json")
+    }
+
+    pub fn status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers
+            .insert(name, HeaderValue::from_str(value).unwrap());
+        self
+    }
+}
+
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+type Responder = Box<dyn Fn(&Request) -> Answer + Send + Sync>;
+
+/// Stub of the http api of a provider for its tests, answers the routes it's
+/// given and records every request, the rest is a 404. `:name` segments of a
+/// route match anything.
+#[derive(Default)]
+pub struct MockServer {
+    routes: Vec<(Method, &'static str, Responder)>,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(
+        mut self,
+        method: Method,
+        path: &'static str,
+        answer: impl Fn(&Request) -> Answer + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push((method, path, Box::new(answer)));
+        self
+    }
+
+    /// Listens on a free port, the address is like `http://127.0.0.1:1234`
+    pub async fn start(self) -> (String, Requests) {
+        self.start_at("").await
+    }
+
+    /// Like `start`, with `base_path` appended to the address, for the
+    /// providers whose base url ends with a path like `/v1`
+    pub async fn start_at(self, base_path: &str) -> (String, Requests) {
+        let requests = Requests::default();
+        let router = Router::new()
+            .fallback(answer)
+            .with_state((Arc::new(self), requests.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}{base_path}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (address, requests)
+    }
+}
+
+/// The requests to `route`, in the order they came
+pub fn requests_to(requests: &Requests, route: &str) -> Vec<Request> {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| matches(route, &r.path))
+        .cloned()
+        .collect()
+}
+
+fn matches(route: &str, path: &str) -> bool {
+    let route = route.split('/').collect::<Vec<&str>>();
+    let path = path.split('/').collect::<Vec<&str>>();
+    route.len() == path.len()
+        && route
+            .iter()
+            .zip(&path)
+            .all(|(r, p)| r.starts_with(':') || r == p)
+}
+
+async fn answer(
+    State((server, requests)): State<(Arc<MockServer>, Requests)>,
+    method: Method,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let request = Request {
+        method,
+        path: uri.path().to_owned(),
+        query,
+        headers,
+        body,
+    };
+    requests.lock().unwrap().push(request.clone());
+    match server
+        .routes
+        .iter()
+        .find(|(method, route, _)| *method == request.method && matches(route, &request.path))
+    {
+        Some((_, _, responder)) => {
+            let answer = responder(&request);
+            (answer.status, answer.headers, answer.body)
+        }
+        None => (StatusCode::NOT_FOUND, HeaderMap::new(), vec![]),
+    }
+}
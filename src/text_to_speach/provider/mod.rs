@@ -1,55 +1,586 @@
-use std::{error::Error, fmt::Display};
+use std::{
+    error::Error,
+    fmt::Display,
+    future::Future,
+    path::Path,
+    process::Stdio,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
+use anyhow::anyhow;
+use locale_codes::language::LanguageInfo;
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{config::ProviderConfig, text_to_speach::scheduler::Limits};
+
+pub mod azure;
+pub mod coqui;
+pub mod elevenlabs;
+pub mod espeak;
 mod google;
-mod openai;
+pub mod google_cloud;
+#[cfg(test)]
+mod mock_server;
+pub mod openai;
+pub mod piper;
+pub mod polly;
+pub mod retry;
 
-enum TtsClientDispatcher {
+#[derive(Clone)]
+pub enum TtsClientDispatcher {
     OpenAi(openai::OpenAiTtsClient),
-    Google(tts_rust::tts::GTTSClient),
+    Google(google_cloud::GoogleCloudTtsClient),
+    Gtts(google::GTTSClient),
+    Piper(piper::PiperTtsClient),
+    Espeak(espeak::EspeakTtsClient),
+    ElevenLabs(elevenlabs::ElevenLabsTtsClient),
+    Azure(azure::AzureTtsClient),
+    Polly(polly::PollyTtsClient),
+    Coqui(coqui::CoquiTtsClient),
 }
 
 impl TtsClient for TtsClientDispatcher {
     async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
         match self {
-            TtsClientDispatcher::OpenAi(c) => c.speak_to_file(text, path).await,
-            TtsClientDispatcher::Google(c) => c.speak_to_file(text, path).await,
+            TtsClientDispatcher::OpenAi(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Google(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Gtts(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Piper(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Espeak(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::ElevenLabs(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Azure(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Polly(c) => TtsClient::speak_to_file(c, text, path).await,
+            TtsClientDispatcher::Coqui(c) => TtsClient::speak_to_file(c, text, path).await,
+        }
+    }
+
+    fn with_context(self, previous_text: Option<String>, next_text: Option<String>) -> Self {
+        match self {
+            TtsClientDispatcher::ElevenLabs(c) => TtsClientDispatcher::ElevenLabs(
+                TtsClient::with_context(c, previous_text, next_text),
+            ),
+            other => other,
         }
     }
 }
 
-enum TtsProvider {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtsProvider {
     OpenAi,
+    /// Google Cloud Text-to-Speech
     Google,
+    /// Google translate voice, only for demos: 100 characters per request
+    Gtts,
+    Piper,
+    Espeak,
+    ElevenLabs,
+    Azure,
+    Polly,
+    Coqui,
+}
+
+impl FromStr for TtsProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(TtsProvider::OpenAi),
+            "google" | "google-cloud" => Ok(TtsProvider::Google),
+            "gtts" => Ok(TtsProvider::Gtts),
+            "piper" => Ok(TtsProvider::Piper),
+            "espeak" | "espeak-ng" => Ok(TtsProvider::Espeak),
+            "elevenlabs" => Ok(TtsProvider::ElevenLabs),
+            "azure" => Ok(TtsProvider::Azure),
+            "polly" => Ok(TtsProvider::Polly),
+            "coqui" | "xtts" => Ok(TtsProvider::Coqui),
+            other => Err(anyhow!("unknown tts provider: {other}")),
+        }
+    }
+}
+
+impl Display for TtsProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TtsProvider::OpenAi => "openai",
+            TtsProvider::Google => "google",
+            TtsProvider::Gtts => "gtts",
+            TtsProvider::Piper => "piper",
+            TtsProvider::Espeak => "espeak",
+            TtsProvider::ElevenLabs => "elevenlabs",
+            TtsProvider::Azure => "azure",
+            TtsProvider::Polly => "polly",
+            TtsProvider::Coqui => "coqui",
+        };
+        write!(f, "{name}")
+    }
 }
 
 impl TtsProvider {
-    fn default(self) -> TtsClientDispatcher {
+    pub const ALL: [TtsProvider; 9] = [
+        TtsProvider::OpenAi,
+        TtsProvider::Google,
+        TtsProvider::Gtts,
+        TtsProvider::Piper,
+        TtsProvider::Espeak,
+        TtsProvider::ElevenLabs,
+        TtsProvider::Azure,
+        TtsProvider::Polly,
+        TtsProvider::Coqui,
+    ];
+
+    pub fn capabilities(self) -> &'static [TtsCapabilites] {
+        match self {
+            TtsProvider::OpenAi => openai::OpenAiTtsClientBuilder::capabilities(),
+            TtsProvider::Google => google_cloud::GoogleCloudTtsClientBuilder::capabilities(),
+            TtsProvider::Gtts => google::GTTSClientBuilder::capabilities(),
+            TtsProvider::Piper => piper::PiperTtsClientBuilder::capabilities(),
+            TtsProvider::Espeak => espeak::EspeakTtsClientBuilder::capabilities(),
+            TtsProvider::ElevenLabs => elevenlabs::ElevenLabsTtsClientBuilder::capabilities(),
+            TtsProvider::Azure => azure::AzureTtsClientBuilder::capabilities(),
+            TtsProvider::Polly => polly::PollyTtsClientBuilder::capabilities(),
+            TtsProvider::Coqui => coqui::CoquiTtsClientBuilder::capabilities(),
+        }
+    }
+
+    pub fn max_input_length(self) -> usize {
+        match self {
+            TtsProvider::OpenAi => openai::OpenAiTtsClientBuilder::max_input_length(),
+            TtsProvider::Google => google_cloud::GoogleCloudTtsClientBuilder::max_input_length(),
+            TtsProvider::Gtts => google::GTTSClientBuilder::max_input_length(),
+            TtsProvider::Piper => piper::PiperTtsClientBuilder::max_input_length(),
+            TtsProvider::Espeak => espeak::EspeakTtsClientBuilder::max_input_length(),
+            TtsProvider::ElevenLabs => elevenlabs::ElevenLabsTtsClientBuilder::max_input_length(),
+            TtsProvider::Azure => azure::AzureTtsClientBuilder::max_input_length(),
+            TtsProvider::Polly => polly::PollyTtsClientBuilder::max_input_length(),
+            TtsProvider::Coqui => coqui::CoquiTtsClientBuilder::max_input_length(),
+        }
+    }
+
+    /// How much faster than normal the provider reads at `speed`
+    pub fn speed_factor(self, speed: SpeechSpeed) -> f64 {
+        match self {
+            TtsProvider::OpenAi => openai::OpenAiTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Google => google_cloud::GoogleCloudTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Gtts => google::GTTSClientBuilder::speed_factor(speed),
+            TtsProvider::Piper => piper::PiperTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Espeak => espeak::EspeakTtsClientBuilder::speed_factor(speed),
+            TtsProvider::ElevenLabs => elevenlabs::ElevenLabsTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Azure => azure::AzureTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Polly => polly::PollyTtsClientBuilder::speed_factor(speed),
+            TtsProvider::Coqui => coqui::CoquiTtsClientBuilder::speed_factor(speed),
+        }
+    }
+
+    /// Extension of the audio the provider makes without encoding it again,
+    /// local engines make wav so they need nothing but themselves
+    pub fn audio_extension(self) -> &'static str {
         match self {
-            TtsProvider::OpenAi => TtsClientDispatcher::OpenAi(create_tts_client(
-                openai::OpenAiTtsClientBuilder::default(),
-            )),
+            TtsProvider::Piper | TtsProvider::Espeak | TtsProvider::Coqui => "wav",
+            TtsProvider::OpenAi
+            | TtsProvider::Google
+            | TtsProvider::Gtts
+            | TtsProvider::ElevenLabs
+            | TtsProvider::Azure
+            | TtsProvider::Polly => "mp3",
+        }
+    }
+
+    /// What the provider takes by default on its entry level plan, the
+    /// config can raise or lower it
+    pub fn limits(self, config: &ProviderConfig) -> Limits {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let (concurrency, requests_per_minute) = match self {
+            TtsProvider::OpenAi => (4, Some(50)),
+            TtsProvider::Google => (4, Some(1000)),
+            TtsProvider::Gtts => (1, None),
+            TtsProvider::Piper | TtsProvider::Espeak => (cores, None),
+            TtsProvider::ElevenLabs => (2, None),
+            TtsProvider::Azure => (4, None),
+            TtsProvider::Polly => (8, None),
+            // a gpu synthesizes one at a time
+            TtsProvider::Coqui => (1, None),
+        };
+        Limits {
+            concurrency: config.concurrency.unwrap_or(concurrency),
+            requests_per_minute: config.requests_per_minute.or(requests_per_minute),
+        }
+    }
+
+    /// Dollars per million characters, `price_per_million_chars` of the config
+    /// or the list price of the model, None when it's billed by plan
+    pub fn price_per_million_chars(self, config: &ProviderConfig) -> Option<f64> {
+        if config.price_per_million_chars.is_some() {
+            return config.price_per_million_chars;
+        }
+        let model = config.model.as_deref().map(str::to_lowercase);
+        match (self, model.as_deref()) {
+            // synthesized locally or free
+            (
+                TtsProvider::Gtts | TtsProvider::Piper | TtsProvider::Espeak | TtsProvider::Coqui,
+                _,
+            ) => Some(0.0),
+            // a proxy or a compatible server isn't billed at the list price
+            _ if config.base_url.is_some() => None,
+            (TtsProvider::OpenAi, None | Some("tts-1")) => Some(15.0),
+            (TtsProvider::OpenAi, Some("tts-1-hd")) => Some(30.0),
+            (TtsProvider::Google | TtsProvider::Polly, Some("standard")) => Some(4.0),
+            (TtsProvider::Google, None | Some("wavenet" | "neural2")) => Some(16.0),
+            (TtsProvider::Google, Some("studio")) => Some(160.0),
+            (TtsProvider::Polly, None | Some("neural")) => Some(16.0),
+            (TtsProvider::Polly, Some("long-form")) => Some(100.0),
+            (TtsProvider::Azure, _) => Some(16.0),
+            _ => None,
+        }
+    }
+
+    /// Builds a client of this provider with its section of the config, once
+    /// for a whole book: espeak runs its binary and coqui asks its server to
+    /// check the voice, google reads the key of its service account
+    pub fn client(
+        self,
+        config: &ProviderConfig,
+        language: Option<&LanguageInfo>,
+    ) -> Result<TtsClientDispatcher, TtsError> {
+        let base_url = config.base_url.clone();
+        let model = config.model.clone();
+        Ok(match self {
+            TtsProvider::OpenAi => {
+                let mut builder = openai::OpenAiTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                if let Some(model) = model {
+                    builder = builder.with_model(model);
+                }
+                TtsClientDispatcher::OpenAi(build_client(builder, config, language)?)
+            }
+            TtsProvider::Google => {
+                let (mut builder, config) = google_cloud_builder(config);
+                if let Some(voice_type) = model {
+                    let voice_type = voice_type
+                        .parse()
+                        .map_err(|e| TtsError::Unknown(format!("{e}")))?;
+                    builder = builder.with_voice_type(voice_type);
+                }
+                TtsClientDispatcher::Google(build_client(builder, &config, language)?)
+            }
+            TtsProvider::Gtts => TtsClientDispatcher::Gtts(build_client(
+                google::GTTSClientBuilder::default(),
+                config,
+                language,
+            )?),
+            TtsProvider::Piper => TtsClientDispatcher::Piper(build_client(
+                piper::PiperTtsClientBuilder::default(),
+                config,
+                language,
+            )?),
+            TtsProvider::Espeak => TtsClientDispatcher::Espeak(build_client(
+                espeak::EspeakTtsClientBuilder::default(),
+                config,
+                language,
+            )?),
+            TtsProvider::ElevenLabs => {
+                let mut builder = elevenlabs::ElevenLabsTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                if let Some(model) = model {
+                    builder = builder.with_model(model);
+                }
+                if let Some(stability) = config.stability {
+                    builder = builder.with_stability(stability);
+                }
+                if let Some(similarity_boost) = config.similarity_boost {
+                    builder = builder.with_similarity_boost(similarity_boost);
+                }
+                if let Some(output_format) = config.output_format.clone() {
+                    builder = builder.with_output_format(output_format);
+                }
+                TtsClientDispatcher::ElevenLabs(build_client(builder, config, language)?)
+            }
+            TtsProvider::Azure => {
+                let mut builder = azure::AzureTtsClientBuilder::default();
+                if let Some(endpoint) = base_url {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                TtsClientDispatcher::Azure(build_client(builder, config, language)?)
+            }
+            TtsProvider::Polly => {
+                let mut builder = polly::PollyTtsClientBuilder::default();
+                if let Some(endpoint) = base_url {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                if let Some(engine) = model {
+                    let engine = engine
+                        .parse()
+                        .map_err(|e| TtsError::Unknown(format!("{e}")))?;
+                    builder = builder.with_engine(engine);
+                }
+                TtsClientDispatcher::Polly(build_client(builder, config, language)?)
+            }
+            TtsProvider::Coqui => {
+                let mut builder = coqui::CoquiTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                TtsClientDispatcher::Coqui(build_client(builder, config, language)?)
+            }
+        })
+    }
+
+    /// Voices of this provider reachable with its section of the config,
+    /// only the ones speaking `language` when given
+    pub async fn list_voices(
+        self,
+        config: &ProviderConfig,
+        language: Option<&LanguageInfo>,
+    ) -> Result<Vec<Voice>, TtsError> {
+        let base_url = config.base_url.clone();
+        let model = config.model.clone();
+        match self {
+            TtsProvider::OpenAi => {
+                let mut builder = openai::OpenAiTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                list_voices(builder, config, language).await
+            }
             TtsProvider::Google => {
-                TtsClientDispatcher::Google(create_tts_client(google::GTTSClientBuilder::default()))
+                let (builder, config) = google_cloud_builder(config);
+                list_voices(builder, &config, language).await
+            }
+            TtsProvider::Gtts => {
+                list_voices(google::GTTSClientBuilder::default(), config, language).await
+            }
+            TtsProvider::Piper => {
+                list_voices(piper::PiperTtsClientBuilder::default(), config, language).await
+            }
+            TtsProvider::Espeak => {
+                list_voices(espeak::EspeakTtsClientBuilder::default(), config, language).await
+            }
+            TtsProvider::ElevenLabs => {
+                let mut builder = elevenlabs::ElevenLabsTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                list_voices(builder, config, language).await
+            }
+            TtsProvider::Azure => {
+                let mut builder = azure::AzureTtsClientBuilder::default();
+                if let Some(endpoint) = base_url {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                list_voices(builder, config, language).await
+            }
+            TtsProvider::Polly => {
+                let mut builder = polly::PollyTtsClientBuilder::default();
+                if let Some(endpoint) = base_url {
+                    builder = builder.with_endpoint(endpoint);
+                }
+                // voices differ between engines
+                if let Some(engine) = model {
+                    let engine = engine
+                        .parse()
+                        .map_err(|e| TtsError::Unknown(format!("{e}")))?;
+                    builder = builder.with_engine(engine);
+                }
+                list_voices(builder, config, language).await
+            }
+            TtsProvider::Coqui => {
+                let mut builder = coqui::CoquiTtsClientBuilder::default();
+                if let Some(base_url) = base_url {
+                    builder = builder.with_base_url(base_url);
+                }
+                list_voices(builder, config, language).await
             }
         }
     }
 }
 
-fn create_tts_client<Builder, Client>(builder: Builder) -> Client
+/// The builder of google cloud with the config left to apply: the key file of
+/// google is a service account, not a bare key
+fn google_cloud_builder(
+    config: &ProviderConfig,
+) -> (google_cloud::GoogleCloudTtsClientBuilder, ProviderConfig) {
+    let mut builder = google_cloud::GoogleCloudTtsClientBuilder::default();
+    if let Some(base_url) = config.base_url.clone() {
+        builder = builder.with_base_url(base_url);
+    }
+    match config.api_key_file() {
+        Some(key_file) if config.api_key.is_none() => (
+            builder.with_service_account(key_file),
+            ProviderConfig {
+                api_key_file: None,
+                ..config.clone()
+            },
+        ),
+        _ => (builder, config.clone()),
+    }
+}
+
+async fn list_voices<Builder, Client>(
+    builder: Builder,
+    config: &ProviderConfig,
+    language: Option<&LanguageInfo>,
+) -> Result<Vec<Voice>, TtsError>
 where
     Builder: TtsClientBuilder<Client>,
     Client: TtsClient,
 {
+    let mut builder = builder.authorize();
+    if let Some(api_key) = config.api_key()? {
+        builder = builder.with_api_key(api_key);
+    }
+    let code = language.and_then(|l| l.short_code.clone());
+    if let Some(language) = language {
+        builder = builder.for_language(language);
+    }
+    let voices = builder.list_voices().await?;
+    Ok(match code {
+        Some(code) => voices.into_iter().filter(|v| v.speaks(&code)).collect(),
+        None => voices,
+    })
+}
+
+/// Applies the parts of the config every builder understands, the key of the
+/// config wins over the environment
+fn build_client<Builder, Client>(
+    builder: Builder,
+    config: &ProviderConfig,
+    language: Option<&LanguageInfo>,
+) -> Result<Client, TtsError>
+where
+    Builder: TtsClientBuilder<Client>,
+    Client: TtsClient,
+{
+    let mut builder = builder.authorize();
+    if let Some(api_key) = config.api_key()? {
+        builder = builder.with_api_key(api_key);
+    }
+    // before the voice, the default voice depends on it
+    if let Some(language) = language {
+        builder = builder.for_language(language);
+    }
+    if let Some(voice) = &config.voice {
+        builder = builder.with_voice(voice.clone());
+    }
     builder
-        .authorize()
-        .with_voice("alloy".to_owned())
-        .set_speed(SpeechSpeed::Normal)
+        .set_speed(config.speed.unwrap_or(SpeechSpeed::Normal))
         .build()
 }
 
-#[trait_variant::make(HttpService: Send)]
-pub trait TtsClient {
+/// Json body of a response, or the error matching its status
+async fn json_response(
+    response: reqwest::Result<reqwest::Response>,
+) -> Result<serde_json::Value, TtsError> {
+    let response = response.map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(error_response(response).await);
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| TtsError::NoContent(e.to_string()))
+}
+
+/// Error of a failed http call, 429 and 503 are rate limits with the wait
+/// asked by `Retry-After` when there is one
+async fn error_response(response: reqwest::Response) -> TtsError {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(retry_after);
+    let message = response.text().await.unwrap_or("".to_owned());
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => TtsError::Unauthorized(message),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => TtsError::RateLimited {
+            retry_after,
+            message,
+        },
+        _ if status.is_server_error() => TtsError::ServerError(format!("{status}: {message}")),
+        _ => TtsError::Unknown(message),
+    }
+}
+
+/// `Retry-After` is either seconds or an http date
+fn retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) => Duration::try_from_secs_f64(seconds).ok(),
+        Err(_) => httpdate::parse_http_date(value.trim())
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+/// Fails unless ffmpeg runs, for a conversion that will need it
+pub async fn check_ffmpeg() -> Result<(), TtsError> {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .ok()
+        .filter(|status| status.success())
+        .map(|_| ())
+        .ok_or(TtsError::WriteToFileFailure(
+            "ffmpeg is needed to encode the episodes, install it or write wav".to_owned(),
+        ))
+}
+
+/// Writes a wav produced by a local engine to `path`, encoding it with
+/// ffmpeg when `path` is not a `.wav`
+pub(crate) async fn write_wav(wav: Vec<u8>, path: &str) -> Result<(), TtsError> {
+    if Path::new(path).extension().is_some_and(|e| e == "wav") {
+        return std::fs::write(path, wav).map_err(|e| TtsError::WriteToFileFailure(e.to_string()));
+    }
+
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "wav", "-i", "-", path])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| TtsError::WriteToFileFailure(format!("can't run ffmpeg: {e}")))?;
+    let mut stdin = ffmpeg.stdin.take().ok_or(TtsError::WriteToFileFailure(
+        "ffmpeg stdin is closed".to_owned(),
+    ))?;
+    stdin
+        .write_all(&wav)
+        .await
+        .map_err(|e| TtsError::WriteToFileFailure(e.to_string()))?;
+    drop(stdin);
+
+    let output = ffmpeg
+        .wait_with_output()
+        .await
+        .map_err(|e| TtsError::WriteToFileFailure(e.to_string()))?;
+    if !output.status.success() {
+        return Err(TtsError::WriteToFileFailure(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `TtsClient` is the `Send` variant, what every client implements so
+/// chunks can be synthesized on other tasks
+#[trait_variant::make(TtsClient: Send)]
+pub trait LocalTtsClient {
     async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError>;
+
+    /// Text spoken right before and after the next `speak_to_file`, providers
+    /// supporting it keep the intonation consistent across chunks
+    fn with_context(self, _previous_text: Option<String>, _next_text: Option<String>) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
 }
 
 pub trait TtsClientBuilder<Client>
@@ -57,12 +588,105 @@ where
     Client: TtsClient,
 {
     fn capabilities() -> &'static [TtsCapabilites];
+    /// Longest text, in characters, accepted by a single `speak_to_file` call
+    fn max_input_length() -> usize;
     fn default() -> Self;
     fn authorize(self) -> Self;
+    /// Key given in the config, providers without auth ignore it
+    fn with_api_key(self, _api_key: String) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
     fn with_voice(self, voice: String) -> Self;
     fn set_speed(self, speed: SpeechSpeed) -> Self;
+    /// How much faster than normal `speed` reads once `set_speed` maps it,
+    /// providers ignoring the speed always read at normal speed
+    fn speed_factor(_speed: SpeechSpeed) -> f64
+    where
+        Self: Sized,
+    {
+        1.0
+    }
     fn for_language(self, language: &locale_codes::language::LanguageInfo) -> Self;
-    fn build(self) -> Client;
+    /// Voices accepted by `with_voice`, asked to the api when it can list them
+    fn list_voices(&self) -> impl Future<Output = Result<Vec<Voice>, TtsError>> + Send;
+    /// Fails with `TtsError::Unauthorized` when the credentials are missing and
+    /// `TtsError::InvalidVoice` when the voice can't exist
+    fn build(self) -> Result<Client, TtsError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceGender {
+    Female,
+    Male,
+    Neutral,
+}
+
+impl VoiceGender {
+    /// Parses the many spellings providers use, `FEMALE`, `female`, `F`...
+    fn parse(gender: &str) -> Option<Self> {
+        match gender.to_lowercase().as_str() {
+            "female" | "f" => Some(VoiceGender::Female),
+            "male" | "m" => Some(VoiceGender::Male),
+            "neutral" | "non-binary" => Some(VoiceGender::Neutral),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice {
+    /// What `with_voice` takes
+    pub id: String,
+    pub name: String,
+    /// Codes as the provider gives them, like `en-US` or `en`, empty when the
+    /// voice speaks any language
+    pub languages: Vec<String>,
+    pub gender: Option<VoiceGender>,
+    pub sample_rate: Option<u32>,
+}
+
+impl Voice {
+    /// `language` is an ISO 639-1 code like `it` or a locale like `it-IT`
+    pub fn speaks(&self, language: &str) -> bool {
+        let language = language.to_lowercase().replace('_', "-");
+        self.languages.is_empty()
+            || self.languages.iter().any(|l| {
+                let l = l.to_lowercase().replace('_', "-");
+                l == language || l.split('-').next() == Some(language.as_str())
+            })
+    }
+}
+
+/// Language of a book, `lang` is a code like `it` or a locale like `it-IT`
+pub fn book_language(lang: &str) -> Option<&'static LanguageInfo> {
+    let code = lang.trim().split(['-', '_']).next()?.to_lowercase();
+    // lookup panics on anything but a 2 or 3 letters code
+    match code.len() {
+        2 | 3 => locale_codes::language::lookup(&code),
+        _ => None,
+    }
+}
+
+impl Display for Voice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)?;
+        if self.name != self.id {
+            write!(f, " ({})", self.name)?;
+        }
+        if !self.languages.is_empty() {
+            write!(f, " {}", self.languages.join(", "))?;
+        }
+        if let Some(gender) = self.gender {
+            write!(f, " {gender:?}")?;
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            write!(f, " {sample_rate}Hz")?;
+        }
+        Ok(())
+    }
 }
 
 pub enum TtsCapabilites {
@@ -70,8 +694,25 @@ pub enum TtsCapabilites {
     VoiceChoice,
     RequiresAuth,
     SpeechSpeedChoice,
+    /// Speaks with the voice of a reference recording
+    VoiceCloning,
+}
+
+impl Display for TtsCapabilites {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TtsCapabilites::LanguageChoice => "language choice",
+            TtsCapabilites::VoiceChoice => "voice choice",
+            TtsCapabilites::RequiresAuth => "requires auth",
+            TtsCapabilites::SpeechSpeedChoice => "speech speed choice",
+            TtsCapabilites::VoiceCloning => "voice cloning",
+        };
+        write!(f, "{name}")
+    }
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SpeechSpeed {
     VeryVerySlow,
     VerySlow,
@@ -85,10 +726,19 @@ pub enum SpeechSpeed {
 #[derive(Debug)]
 pub enum TtsError {
     Unauthorized(String),
+    /// Too many requests or an overloaded service, `retry_after` is how long
+    /// the provider asked to wait
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// 5xx answers, usually gone on the next try
+    ServerError(String),
     Unknown(String),
     NoContent(String),
     ConnectionFailure(String),
     WriteToFileFailure(String),
+    InvalidVoice(String),
 }
 
 impl Error for TtsError {}
@@ -96,31 +746,141 @@ impl Display for TtsError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TtsError::Unauthorized(error_str) => write!(f, "Unauthorized: {}", error_str),
+            TtsError::RateLimited {
+                retry_after: Some(retry_after),
+                message,
+            } => write!(
+                f,
+                "RateLimited: {message}, retry after {}s",
+                retry_after.as_secs_f64()
+            ),
+            TtsError::RateLimited { message, .. } => write!(f, "RateLimited: {}", message),
+            TtsError::ServerError(error_str) => write!(f, "ServerError: {}", error_str),
             TtsError::Unknown(error_str) => write!(f, "Unknown: {}", error_str),
             TtsError::NoContent(error_str) => write!(f, "NoContent: {}", error_str),
             TtsError::ConnectionFailure(error_str) => write!(f, "ConnectionFailure: {}", error_str),
             TtsError::WriteToFileFailure(error_str) => {
                 write!(f, "WriteToFileFailure: {}", error_str)
             }
+            TtsError::InvalidVoice(error_str) => write!(f, "InvalidVoice: {}", error_str),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{TtsClient, TtsProvider};
+    use std::time::{Duration, SystemTime};
+
+    use super::{book_language, retry_after, ProviderConfig, TtsClient, TtsError, TtsProvider};
+
+    #[test]
+    fn provider_book_language() {
+        for lang in ["it", "ita", "it-IT", "IT_it", " it "] {
+            assert_eq!(
+                book_language(lang).unwrap().short_code.as_deref(),
+                Some("it")
+            );
+        }
+        assert!(book_language("italian").is_none());
+        assert!(book_language("").is_none());
+    }
+
+    #[test]
+    fn provider_retry_after() {
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_after("soon"), None);
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let wait = retry_after(&date).unwrap();
+        assert!(wait > Duration::from_secs(28) && wait <= Duration::from_secs(30));
+        let past = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(30));
+        assert_eq!(retry_after(&past), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn provider_client_config() {
+        let config = ProviderConfig {
+            api_key: Some("key".to_owned()),
+            model: Some("eleven_flash_v2_5".to_owned()),
+            ..ProviderConfig::default()
+        };
+        assert!(TtsProvider::ElevenLabs.client(&config, None).is_ok());
+        // the episodes of elevenlabs are mp3
+        let pcm = ProviderConfig {
+            output_format: Some("pcm_16000".to_owned()),
+            ..config.clone()
+        };
+        assert!(TtsProvider::ElevenLabs.client(&pcm, None).is_err());
+
+        let config = ProviderConfig {
+            api_key_file: Some("/nonexistent/book2pod/key".into()),
+            ..ProviderConfig::default()
+        };
+        let res = TtsProvider::OpenAi.client(&config, None);
+        assert!(matches!(res, Err(TtsError::Unauthorized(_))));
+
+        let config = ProviderConfig {
+            model: Some("warp".to_owned()),
+            ..config
+        };
+        assert!(TtsProvider::Polly.client(&config, None).is_err());
+    }
+
+    #[test]
+    fn provider_from_str() {
+        assert!(matches!("openai".parse(), Ok(TtsProvider::OpenAi)));
+        assert!(matches!("Google".parse(), Ok(TtsProvider::Google)));
+        assert!(matches!("gtts".parse(), Ok(TtsProvider::Gtts)));
+        assert!(matches!("piper".parse(), Ok(TtsProvider::Piper)));
+        assert!(matches!("espeak-ng".parse(), Ok(TtsProvider::Espeak)));
+        assert!(matches!("ElevenLabs".parse(), Ok(TtsProvider::ElevenLabs)));
+        assert!(matches!("azure".parse(), Ok(TtsProvider::Azure)));
+        assert!(matches!("Polly".parse(), Ok(TtsProvider::Polly)));
+        assert!(matches!("xtts".parse(), Ok(TtsProvider::Coqui)));
+        for provider in TtsProvider::ALL {
+            assert_eq!(
+                provider.to_string().parse::<TtsProvider>().unwrap(),
+                provider
+            );
+        }
+        assert!("nope".parse::<TtsProvider>().is_err());
+    }
 
     #[tokio::test]
-    async fn google() {
-        let test = TtsProvider::Google.default();
+    async fn espeak() {
+        let path = std::env::temp_dir().join(format!(
+            "book2pod.provider.mod.test.{}",
+            TtsProvider::Espeak.audio_extension()
+        ));
+        let test = TtsProvider::Espeak
+            .client(&ProviderConfig::default(), None)
+            .unwrap();
+        test.speak_to_file("hello world".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert!(std::fs::read(&path).unwrap().starts_with(b"RIFF"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the network"]
+    async fn gtts() {
+        let test = TtsProvider::Gtts
+            .client(&ProviderConfig::default(), None)
+            .unwrap();
         test.speak_to_file("hello world".to_owned(), "provider.mod.test.mp3".to_owned())
             .await
             .unwrap();
     }
 
     #[tokio::test]
+    #[ignore = "needs the network and OPENAI_API_KEY"]
     async fn openai() {
-        let test = TtsProvider::OpenAi.default();
+        let test = TtsProvider::OpenAi
+            .client(&ProviderConfig::default(), None)
+            .unwrap();
         test.speak_to_file("hello world".to_owned(), "provider.mod.test.mp3".to_owned())
             .await
             .unwrap();
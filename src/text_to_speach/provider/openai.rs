@@ -3,37 +3,83 @@ use reqwest::header;
 
 use crate::text_to_speach::provider;
 
-use super::{TtsCapabilites, TtsClient, TtsClientBuilder};
+use super::{TtsCapabilites, TtsClient, TtsClientBuilder, Voice};
 
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Built in voices, they speak every language; `ballad` and `verse` only
+/// work with `gpt-4o-mini-tts`
+const VOICES: &[&str] = &[
+    "alloy", "ash", "ballad", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer", "verse",
+];
+
+/// Audio encodings accepted as `response_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAiAudioFormat {
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    Pcm,
+}
+
+impl OpenAiAudioFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OpenAiAudioFormat::Mp3 => "mp3",
+            OpenAiAudioFormat::Opus => "opus",
+            OpenAiAudioFormat::Aac => "aac",
+            OpenAiAudioFormat::Flac => "flac",
+            OpenAiAudioFormat::Wav => "wav",
+            OpenAiAudioFormat::Pcm => "pcm",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct OpenAiTtsClient {
-    api_key: String,
+    api_key: Option<String>,
+    base_url: String,
+    model: String,
     voice: String,
     speed: f32,
+    response_format: OpenAiAudioFormat,
+    instructions: Option<String>,
 }
 
 impl TtsClient for OpenAiTtsClient {
     async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
-        let url = "https://api.openai.com/v1/audio/speech";
+        let url = format!("{}/audio/speech", self.base_url.trim_end_matches('/'));
         let client = reqwest::Client::new();
         let mut headers = header::HeaderMap::new();
-        headers.insert(
-            header::AUTHORIZATION,
-            header::HeaderValue::from_str(&format!("Bearer {}", self.api_key)).unwrap(),
-        );
+        if let Some(api_key) = &self.api_key {
+            headers.insert(
+                header::AUTHORIZATION,
+                header::HeaderValue::from_str(&format!("Bearer {}", api_key))
+                    .map_err(|e| TtsError::Unauthorized(e.to_string()))?,
+            );
+        }
         headers.insert(
             header::CONTENT_TYPE,
             header::HeaderValue::from_static("application/json"),
         );
 
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "input": text,
+            "voice": self.voice,
+            "speed": self.speed,
+            "response_format": self.response_format.as_str(),
+        });
+        if let Some(instructions) = self.instructions {
+            body["instructions"] = instructions.into();
+        }
+
         let response = client
             .post(url)
             .headers(headers)
-            .json(&serde_json::json!({
-                "model": "tts-1",
-                "input": text,
-                "voice": self.voice,
-                "speed": self.speed,
-            }))
+            .json(&body)
             .send()
             .await
             .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
@@ -47,14 +93,7 @@ impl TtsClient for OpenAiTtsClient {
             std::fs::write(&path, bytes)
                 .map_err(|e| TtsError::WriteToFileFailure(e.to_string()))?;
         } else {
-            if 401 == response.status() {
-                return Err(TtsError::Unauthorized(
-                    response.text().await.unwrap_or("".to_owned()),
-                ));
-            }
-            return Err(TtsError::Unknown(
-                response.text().await.unwrap_or("".to_owned()),
-            ));
+            return Err(provider::error_response(response).await);
         }
         Ok(())
     }
@@ -62,8 +101,49 @@ impl TtsClient for OpenAiTtsClient {
 
 pub struct OpenAiTtsClientBuilder {
     api_key: Option<String>,
+    base_url: Option<String>,
+    model: Option<String>,
     voice: Option<String>,
     speed: Option<f32>,
+    response_format: Option<OpenAiAudioFormat>,
+    instructions: Option<String>,
+}
+
+impl OpenAiTtsClientBuilder {
+    /// Root of an OpenAI compatible api, `/audio/speech` is appended to it,
+    /// defaults to `https://api.openai.com/v1`.
+    /// The api key becomes optional, local servers often don't need one.
+    pub fn with_base_url(self, base_url: String) -> Self {
+        Self {
+            base_url: Some(base_url),
+            ..self
+        }
+    }
+
+    /// `tts-1` (the default), `tts-1-hd`, `gpt-4o-mini-tts` or whatever model
+    /// the compatible server expects
+    pub fn with_model(self, model: String) -> Self {
+        Self {
+            model: Some(model),
+            ..self
+        }
+    }
+
+    /// Defaults to mp3
+    pub fn with_response_format(self, response_format: OpenAiAudioFormat) -> Self {
+        Self {
+            response_format: Some(response_format),
+            ..self
+        }
+    }
+
+    /// How the voice should sound, only supported by `gpt-4o-mini-tts`
+    pub fn with_instructions(self, instructions: String) -> Self {
+        Self {
+            instructions: Some(instructions),
+            ..self
+        }
+    }
 }
 
 impl TtsClientBuilder<OpenAiTtsClient> for OpenAiTtsClientBuilder {
@@ -75,11 +155,19 @@ impl TtsClientBuilder<OpenAiTtsClient> for OpenAiTtsClientBuilder {
         ]
     }
 
+    fn max_input_length() -> usize {
+        4096
+    }
+
     fn default() -> Self {
         Self {
             api_key: None,
+            base_url: None,
+            model: None,
             voice: None,
             speed: None,
+            response_format: None,
+            instructions: None,
         }
     }
 
@@ -90,6 +178,13 @@ impl TtsClientBuilder<OpenAiTtsClient> for OpenAiTtsClientBuilder {
         }
     }
 
+    fn with_api_key(self, api_key: String) -> Self {
+        Self {
+            api_key: Some(api_key),
+            ..self
+        }
+    }
+
     fn with_voice(self, voice: String) -> Self {
         Self {
             voice: Some(voice),
@@ -101,43 +196,183 @@ impl TtsClientBuilder<OpenAiTtsClient> for OpenAiTtsClientBuilder {
         self
     }
 
-    fn build(self) -> OpenAiTtsClient {
-        OpenAiTtsClient {
-            api_key: self.api_key.expect("API key is required"),
-            voice: self.voice.expect("Voice is required"),
-            speed: self.speed.unwrap_or(1.0),
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        Ok(VOICES
+            .iter()
+            .map(|voice| Voice {
+                id: voice.to_string(),
+                name: voice.to_string(),
+                languages: vec![],
+                gender: None,
+                sample_rate: Some(24000),
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<OpenAiTtsClient, TtsError> {
+        if self.api_key.is_none() && self.base_url.is_none() {
+            return Err(TtsError::Unauthorized(
+                "OPENAI_API_KEY or an api_key in the config is required".to_owned(),
+            ));
+        }
+        // compatible servers come with their own voices
+        if let Some(voice) = self
+            .voice
+            .as_ref()
+            .filter(|v| !VOICES.contains(&v.as_str()))
+        {
+            if self.base_url.is_none() {
+                return Err(TtsError::InvalidVoice(format!(
+                    "{voice} is not an openai voice, use one of {}",
+                    VOICES.join(", ")
+                )));
+            }
         }
+        Ok(OpenAiTtsClient {
+            api_key: self.api_key,
+            base_url: self.base_url.unwrap_or(OPENAI_BASE_URL.to_owned()),
+            model: self.model.unwrap_or("tts-1".to_owned()),
+            voice: self.voice.unwrap_or("alloy".to_owned()),
+            speed: self.speed.unwrap_or(1.0),
+            response_format: self.response_format.unwrap_or(OpenAiAudioFormat::Mp3),
+            instructions: self.instructions,
+        })
     }
 
     fn set_speed(self, speed: super::SpeechSpeed) -> Self {
         Self {
-            speed: Some(match speed {
-                super::SpeechSpeed::VeryVerySlow => 0.25,
-                super::SpeechSpeed::VerySlow => 0.50,
-                super::SpeechSpeed::Slow => 0.75,
-                super::SpeechSpeed::Normal => 1.0,
-                super::SpeechSpeed::Quick => 1.25,
-                super::SpeechSpeed::VeryQuick => 1.5,
-                super::SpeechSpeed::VeryVeryQuick => 2.0,
-            }),
+            speed: Some(Self::speed_factor(speed) as f32),
             ..self
         }
     }
+
+    fn speed_factor(speed: super::SpeechSpeed) -> f64 {
+        match speed {
+            super::SpeechSpeed::VeryVerySlow => 0.25,
+            super::SpeechSpeed::VerySlow => 0.50,
+            super::SpeechSpeed::Slow => 0.75,
+            super::SpeechSpeed::Normal => 1.0,
+            super::SpeechSpeed::Quick => 1.25,
+            super::SpeechSpeed::VeryQuick => 1.5,
+            super::SpeechSpeed::VeryVeryQuick => 2.0,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::text_to_speach::provider::SpeechSpeed;
+    use axum::http::Method;
+
+    use crate::text_to_speach::provider::{
+        mock_server::{Answer, MockServer, Requests},
+        SpeechSpeed,
+    };
 
     use super::*;
 
+    /// Starts an OpenAI compatible server answering every request with
+    /// `[1, 2, 3]`, returns its base url and the received requests
+    async fn start_mock_server() -> (String, Requests) {
+        MockServer::new()
+            .route(Method::POST, "/v1/audio/speech", |_| {
+                Answer::bytes([1, 2, 3])
+            })
+            .start_at("/v1")
+            .await
+    }
+
+    #[tokio::test]
+    async fn openai_compatible_server() {
+        let (base_url, requests) = start_mock_server().await;
+        let path = std::env::temp_dir().join("book2pod-openai-compatible.wav");
+
+        let client = OpenAiTtsClientBuilder::default()
+            .with_base_url(base_url)
+            .with_model("gpt-4o-mini-tts".to_owned())
+            .with_voice("nova".to_owned())
+            .with_response_format(OpenAiAudioFormat::Wav)
+            .with_instructions("speak calmly".to_owned())
+            .set_speed(SpeechSpeed::Quick)
+            .build()
+            .unwrap();
+        client
+            .speak_to_file("hello".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.header("authorization"), None);
+        assert_eq!(
+            request.json(),
+            serde_json::json!({
+                "model": "gpt-4o-mini-tts",
+                "input": "hello",
+                "voice": "nova",
+                "speed": 1.25,
+                "response_format": "wav",
+                "instructions": "speak calmly",
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn openai_compatible_defaults() {
+        let (base_url, requests) = start_mock_server().await;
+        let path = std::env::temp_dir().join("book2pod-openai-defaults.mp3");
+
+        let client = OpenAiTtsClientBuilder::default()
+            .with_api_key("key".to_owned())
+            .with_base_url(format!("{base_url}/"))
+            .build()
+            .unwrap();
+        client
+            .speak_to_file("hello".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        let request = &requests.lock().unwrap()[0];
+        let body = request.json();
+        assert_eq!(request.header("authorization"), Some("Bearer key"));
+        assert_eq!(body["model"], "tts-1");
+        assert_eq!(body["voice"], "alloy");
+        assert_eq!(body["response_format"], "mp3");
+        assert!(body.get("instructions").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn openai_requires_key() {
+        let res = OpenAiTtsClientBuilder::default().build();
+        assert!(matches!(res, Err(TtsError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn openai_voices() {
+        let builder = OpenAiTtsClientBuilder::default().with_api_key("key".to_owned());
+        let voices = builder.list_voices().await.unwrap();
+        assert!(voices.iter().any(|v| v.id == "nova" && v.speaks("it")));
+
+        let res = builder.with_voice("Nova".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+        let client = OpenAiTtsClientBuilder::default()
+            .with_base_url("http://localhost:8880/v1".to_owned())
+            .with_voice("af_bella".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "af_bella");
+    }
+
     #[tokio::test]
+    #[ignore = "needs the network and OPENAI_API_KEY"]
     async fn openai_it_test() {
         let client = OpenAiTtsClientBuilder::default()
             .authorize()
             .with_voice("alloy".to_owned())
             .set_speed(SpeechSpeed::Normal)
-            .build();
+            .build()
+            .unwrap();
 
         let res = client
             .speak_to_file("sono un bot, ciao".to_owned(), "test.it.mp3".to_owned())
@@ -0,0 +1,382 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use locale_codes::language::LanguageInfo;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::text_to_speach::chunker::wav;
+
+use super::{write_wav, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder, TtsError, Voice};
+
+/// Piper voices are `<language>_<REGION>-<name>-<quality>.onnx` models, each
+/// with a `.onnx.json` config next to it
+struct PiperVoice {
+    model: PathBuf,
+    /// language code of the config, like `en_US`
+    language: Option<String>,
+    sample_rate: u32,
+}
+
+impl PiperVoice {
+    fn from_model(model: &Path) -> Option<Self> {
+        if !model.is_file() {
+            return None;
+        }
+        let config = fs::read(model.with_extension("onnx.json"))
+            .ok()
+            .and_then(|c| serde_json::from_slice::<serde_json::Value>(&c).ok())
+            .unwrap_or_default();
+        let file_name = model.file_name()?.to_string_lossy().to_string();
+        let language = config["language"]["code"]
+            .as_str()
+            .map(|c| c.to_owned())
+            .or_else(|| file_name.split_once('-').map(|(l, _)| l.to_owned()));
+
+        Some(Self {
+            model: model.to_owned(),
+            language,
+            sample_rate: config["audio"]["sample_rate"].as_u64().unwrap_or(22050) as u32,
+        })
+    }
+
+    /// ISO 639-1 code of the voice language, like `en`
+    fn short_language(&self) -> Option<String> {
+        self.language
+            .as_ref()
+            .map(|l| l.split(['_', '-']).next().unwrap_or(l).to_lowercase())
+    }
+}
+
+fn default_binary() -> String {
+    std::env::var("PIPER_BIN").unwrap_or("piper".to_owned())
+}
+
+fn default_voices_dir() -> PathBuf {
+    std::env::var("PIPER_VOICES_DIR")
+        .unwrap_or("voices".to_owned())
+        .into()
+}
+
+/// Voices installed in `dir`, sorted by file name
+fn installed_voices(dir: &Path) -> Vec<PiperVoice> {
+    let mut models = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "onnx"))
+        .collect::<Vec<PathBuf>>();
+    models.sort();
+    models
+        .iter()
+        .filter_map(|m| PiperVoice::from_model(m))
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct PiperTtsClient {
+    binary: String,
+    model: PathBuf,
+    sample_rate: u32,
+    length_scale: f32,
+}
+
+impl TtsClient for PiperTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let mut piper = Command::new(&self.binary)
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--output_raw")
+            .arg("--length_scale")
+            .arg(self.length_scale.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| TtsError::ConnectionFailure(format!("can't run {}: {e}", self.binary)))?;
+
+        // written from another task, piper starts writing audio before the end
+        // of the text and would block on a full stdout
+        let mut stdin = piper.stdin.take().ok_or(TtsError::ConnectionFailure(
+            "piper stdin is closed".to_owned(),
+        ))?;
+        let writer = tokio::spawn(async move { stdin.write_all(text.as_bytes()).await });
+
+        let output = piper
+            .wait_with_output()
+            .await
+            .map_err(|e| TtsError::Unknown(e.to_string()))?;
+        if !output.status.success() {
+            return Err(TtsError::Unknown(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        writer
+            .await
+            .map_err(|e| TtsError::Unknown(e.to_string()))?
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+        if output.stdout.is_empty() {
+            return Err(TtsError::NoContent("piper returned no audio".to_owned()));
+        }
+
+        write_wav(
+            wav::pcm_to_wav(&output.stdout, self.sample_rate, 1, 16),
+            &path,
+        )
+        .await
+    }
+}
+
+/// Piper stretches the phonemes, a longer scale reads slower
+fn length_scale(speed: SpeechSpeed) -> f32 {
+    match speed {
+        SpeechSpeed::VeryVerySlow => 2.0,
+        SpeechSpeed::VerySlow => 1.6,
+        SpeechSpeed::Slow => 1.25,
+        SpeechSpeed::Normal => 1.0,
+        SpeechSpeed::Quick => 0.85,
+        SpeechSpeed::VeryQuick => 0.7,
+        SpeechSpeed::VeryVeryQuick => 0.5,
+    }
+}
+
+pub struct PiperTtsClientBuilder {
+    binary: String,
+    voices_dir: PathBuf,
+    voice: Option<String>,
+    language: Option<String>,
+    length_scale: f32,
+}
+
+impl TtsClientBuilder<PiperTtsClient> for PiperTtsClientBuilder {
+    /// Depends on the voices installed in `PIPER_VOICES_DIR`
+    fn capabilities() -> &'static [TtsCapabilites] {
+        let voices = installed_voices(&default_voices_dir());
+        let languages = voices
+            .iter()
+            .filter_map(|v| v.short_language())
+            .collect::<HashSet<String>>();
+        match (languages.len() > 1, voices.len() > 1) {
+            (true, _) => &[
+                TtsCapabilites::LanguageChoice,
+                TtsCapabilites::VoiceChoice,
+                TtsCapabilites::SpeechSpeedChoice,
+            ],
+            (false, true) => &[
+                TtsCapabilites::VoiceChoice,
+                TtsCapabilites::SpeechSpeedChoice,
+            ],
+            (false, false) => &[TtsCapabilites::SpeechSpeedChoice],
+        }
+    }
+
+    /// piper has no limit, this only keeps every call short
+    fn max_input_length() -> usize {
+        10_000
+    }
+
+    fn default() -> Self {
+        Self {
+            binary: default_binary(),
+            voices_dir: default_voices_dir(),
+            voice: None,
+            language: None,
+            length_scale: 1.0,
+        }
+    }
+
+    fn authorize(self) -> Self {
+        self
+    }
+
+    /// Path of a `.onnx` model, or the name of one installed in `PIPER_VOICES_DIR`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            length_scale: length_scale(speed),
+            ..self
+        }
+    }
+
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        1.0 / length_scale(speed) as f64
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        Ok(installed_voices(&self.voices_dir)
+            .into_iter()
+            .filter_map(|voice| {
+                let id = voice.model.file_stem()?.to_string_lossy().to_string();
+                Some(Voice {
+                    name: id.clone(),
+                    id,
+                    languages: voice.language.into_iter().collect(),
+                    gender: None,
+                    sample_rate: Some(voice.sample_rate),
+                })
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<PiperTtsClient, TtsError> {
+        let voice = match self.voice {
+            Some(voice) => {
+                let path = PathBuf::from(&voice);
+                let model = if path.is_file() {
+                    path
+                } else {
+                    self.voices_dir.join(format!("{voice}.onnx"))
+                };
+                PiperVoice::from_model(&model).ok_or(TtsError::InvalidVoice(format!(
+                    "piper voice not found: {}",
+                    model.display()
+                )))?
+            }
+            None => {
+                let language = self.language.unwrap_or("en".to_owned());
+                let mut voices = installed_voices(&self.voices_dir);
+                let position = voices
+                    .iter()
+                    .position(|v| v.short_language().as_ref() == Some(&language))
+                    .ok_or(TtsError::InvalidVoice(format!(
+                        "no piper voice for {language} in {}",
+                        self.voices_dir.display()
+                    )))?;
+                voices.swap_remove(position)
+            }
+        };
+
+        Ok(PiperTtsClient {
+            binary: self.binary,
+            model: voice.model,
+            sample_rate: voice.sample_rate,
+            length_scale: self.length_scale,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn voices_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("en_US-lessac-medium.onnx"), []).unwrap();
+        fs::write(
+            dir.join("en_US-lessac-medium.onnx.json"),
+            r#"{"audio": {"sample_rate": 22050}, "language": {"code": "en_US"}}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("it_IT-paola-medium.onnx"), []).unwrap();
+        fs::write(
+            dir.join("it_IT-paola-medium.onnx.json"),
+            r#"{"audio": {"sample_rate": 16000}, "language": {"code": "it_IT"}}"#,
+        )
+        .unwrap();
+        dir
+    }
+
+    fn builder(voices_dir: PathBuf) -> PiperTtsClientBuilder {
+        PiperTtsClientBuilder {
+            voices_dir,
+            ..PiperTtsClientBuilder::default()
+        }
+    }
+
+    #[test]
+    fn piper_voices() {
+        let dir = voices_dir("book2pod-piper-voices");
+
+        let client = builder(dir.clone()).build().unwrap();
+        assert_eq!(client.model, dir.join("en_US-lessac-medium.onnx"));
+        assert_eq!(client.sample_rate, 22050);
+
+        let client = builder(dir.clone())
+            .for_language(locale_codes::language::lookup("it").unwrap())
+            .set_speed(SpeechSpeed::Slow)
+            .build()
+            .unwrap();
+        assert_eq!(client.model, dir.join("it_IT-paola-medium.onnx"));
+        assert_eq!(client.sample_rate, 16000);
+        assert_eq!(client.length_scale, 1.25);
+
+        let client = builder(dir.clone())
+            .with_voice("it_IT-paola-medium".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(client.model, dir.join("it_IT-paola-medium.onnx"));
+
+        let res = builder(dir.clone()).with_voice("nope".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn piper_list_voices() {
+        let dir = voices_dir("book2pod-piper-list");
+
+        let voices = builder(dir.clone()).list_voices().await.unwrap();
+        let voices = voices
+            .iter()
+            .map(|v| (v.id.as_str(), v.languages.clone(), v.sample_rate))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            voices,
+            vec![
+                ("en_US-lessac-medium", vec!["en_US".to_owned()], Some(22050)),
+                ("it_IT-paola-medium", vec!["it_IT".to_owned()], Some(16000)),
+            ]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn piper_speaks_wav() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = voices_dir("book2pod-piper-speak");
+        // stands in for piper, answers with the text as samples
+        let binary = dir.join("piper.sh");
+        fs::write(&binary, "#!/bin/sh\ncat\n").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        let client = PiperTtsClientBuilder {
+            binary: binary.to_string_lossy().to_string(),
+            ..builder(dir.clone())
+        }
+        .build()
+        .unwrap();
+
+        let path = dir.join("out.wav");
+        client
+            .speak_to_file("abcd".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            wav::pcm_to_wav(b"abcd", 22050, 1, 16)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use locale_codes::language::LanguageInfo;
+use reqwest::{header, Method, RequestBuilder, Url};
+use sha2::{Digest, Sha256};
+
+use crate::util::hex;
+
+use super::{
+    error_response, json_response, SpeechSpeed, TtsCapabilites, TtsClient, TtsClientBuilder,
+    TtsError, Voice, VoiceGender,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollyEngine {
+    Standard,
+    Neural,
+    /// Most natural for books, only a few english and spanish voices
+    LongForm,
+}
+
+impl FromStr for PollyEngine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(PollyEngine::Standard),
+            "neural" => Ok(PollyEngine::Neural),
+            "long-form" => Ok(PollyEngine::LongForm),
+            other => Err(anyhow!("unknown polly engine: {other}")),
+        }
+    }
+}
+
+impl PollyEngine {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PollyEngine::Standard => "standard",
+            PollyEngine::Neural => "neural",
+            PollyEngine::LongForm => "long-form",
+        }
+    }
+}
+
+/// Voice used for a language when none is chosen, for the standard/neural
+/// engines and for the long-form one
+const DEFAULT_VOICES: &[(&str, &str, Option<&str>)] = &[
+    ("en", "Joanna", Some("Danielle")),
+    ("es", "Lucia", Some("Alba")),
+    ("it", "Bianca", None),
+    ("fr", "Lea", None),
+    ("de", "Vicki", None),
+    ("pt", "Camila", None),
+    ("ja", "Takumi", None),
+];
+
+#[derive(Clone)]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct PollyTtsClient {
+    credentials: AwsCredentials,
+    region: String,
+    endpoint: String,
+    engine: PollyEngine,
+    voice: String,
+    rate: Option<&'static str>,
+}
+
+impl TtsClient for PollyTtsClient {
+    async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+        let url = Url::parse(&format!(
+            "{}/v1/speech",
+            self.endpoint.trim_end_matches('/')
+        ))
+        .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+        let (text, text_type) = match self.rate {
+            Some(rate) => (prosody_ssml(&text, rate), "ssml"),
+            None => (text, "text"),
+        };
+        let body = serde_json::json!({
+            "Engine": self.engine.as_str(),
+            "OutputFormat": "mp3",
+            "Text": text,
+            "TextType": text_type,
+            "VoiceId": self.voice,
+        })
+        .to_string();
+
+        let response = signed_request(&self.credentials, &self.region, Method::POST, url, body)
+            .send()
+            .await
+            .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(match error_response(response).await {
+                // polly answers 400 with a ThrottlingException too
+                TtsError::Unknown(message) if message.contains("Throttling") => {
+                    TtsError::RateLimited {
+                        retry_after: None,
+                        message,
+                    }
+                }
+                error => error,
+            });
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| TtsError::NoContent(e.to_string()))?;
+        std::fs::write(&path, bytes).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+    }
+}
+
+/// Request to polly with the sigv4 headers, `url` must be already encoded
+fn signed_request(
+    credentials: &AwsCredentials,
+    region: &str,
+    method: Method,
+    url: Url,
+    body: String,
+) -> RequestBuilder {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_owned(),
+    };
+    let amz_date = amz_date(SystemTime::now());
+    let mut headers = vec![
+        ("content-type", "application/json".to_owned()),
+        ("host", host),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let authorization = sigv4_authorization(
+        credentials,
+        region,
+        "polly",
+        method.as_str(),
+        url.path(),
+        url.query().unwrap_or_default(),
+        &headers,
+        body.as_bytes(),
+        &amz_date,
+    );
+
+    let mut request = reqwest::Client::new()
+        .request(method, url)
+        .header(header::AUTHORIZATION, authorization)
+        .body(body);
+    for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+        request = request.header(name, value);
+    }
+    request
+}
+
+fn prosody_ssml(text: &str, rate: &str) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(r#"<speak><prosody rate="{rate}">{escaped}</prosody></speak>"#)
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `YYYYMMDDTHHMMSSZ` in UTC
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// `Authorization` header of an aws signature version 4, `headers` must have
+/// lowercase names and include `host` and `x-amz-date`, `query` must be
+/// already encoded
+#[allow(clippy::too_many_arguments)]
+fn sigv4_authorization(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, String)],
+    payload: &[u8],
+    amz_date: &str,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort_by_key(|(name, _)| *name);
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&str>>()
+        .join(";");
+    let mut query = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<&str>>();
+    query.sort();
+    let query = query.join("&");
+    let canonical_request = format!(
+        "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{}",
+        hex(&Sha256::digest(payload))
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex(&hmac_sha256(&key, &string_to_sign));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+pub struct PollyTtsClientBuilder {
+    credentials: Option<AwsCredentials>,
+    /// The config had a key that is no `ACCESS_KEY:SECRET`, an error once built
+    malformed_key: bool,
+    region: Option<String>,
+    endpoint: Option<String>,
+    engine: PollyEngine,
+    voice: Option<String>,
+    language: Option<String>,
+    rate: Option<&'static str>,
+}
+
+impl PollyTtsClientBuilder {
+    /// Replaces `https://polly.<region>.amazonaws.com`
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self {
+            endpoint: Some(endpoint),
+            ..self
+        }
+    }
+
+    /// Defaults to `AWS_REGION`, `AWS_DEFAULT_REGION` or `us-east-1`
+    pub fn with_region(self, region: String) -> Self {
+        Self {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    /// Defaults to neural
+    pub fn with_engine(self, engine: PollyEngine) -> Self {
+        Self { engine, ..self }
+    }
+
+    fn region(&self) -> String {
+        self.region
+            .clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .unwrap_or("us-east-1".to_owned())
+    }
+
+    fn endpoint(&self, region: &str) -> String {
+        self.endpoint
+            .clone()
+            .unwrap_or(format!("https://polly.{region}.amazonaws.com"))
+    }
+
+    fn credentials(&self) -> Result<&AwsCredentials, TtsError> {
+        if self.malformed_key {
+            return Err(TtsError::Unauthorized(
+                "the polly api_key must be <access key id>:<secret access key>".to_owned(),
+            ));
+        }
+        self.credentials.as_ref().ok_or(TtsError::Unauthorized(
+            "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY or an api_key in the config are required"
+                .to_owned(),
+        ))
+    }
+}
+
+impl TtsClientBuilder<PollyTtsClient> for PollyTtsClientBuilder {
+    fn capabilities() -> &'static [TtsCapabilites] {
+        &[
+            TtsCapabilites::LanguageChoice,
+            TtsCapabilites::VoiceChoice,
+            TtsCapabilites::SpeechSpeedChoice,
+            TtsCapabilites::RequiresAuth,
+        ]
+    }
+
+    /// billed characters accepted by a single SynthesizeSpeech call
+    fn max_input_length() -> usize {
+        3000
+    }
+
+    fn default() -> Self {
+        Self {
+            credentials: None,
+            malformed_key: false,
+            region: None,
+            endpoint: None,
+            engine: PollyEngine::Neural,
+            voice: None,
+            language: None,
+            rate: None,
+        }
+    }
+
+    /// Reads `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and the optional
+    /// `AWS_SESSION_TOKEN`
+    fn authorize(self) -> Self {
+        let credentials = std::env::var("AWS_ACCESS_KEY_ID")
+            .ok()
+            .zip(std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+            .map(|(access_key_id, secret_access_key)| AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        Self {
+            credentials,
+            ..self
+        }
+    }
+
+    /// `<access key id>:<secret access key>`
+    fn with_api_key(self, api_key: String) -> Self {
+        match api_key.split_once(':') {
+            Some((access_key_id, secret_access_key))
+                if !access_key_id.is_empty() && !secret_access_key.is_empty() =>
+            {
+                Self {
+                    credentials: Some(AwsCredentials {
+                        access_key_id: access_key_id.to_owned(),
+                        secret_access_key: secret_access_key.to_owned(),
+                        session_token: None,
+                    }),
+                    malformed_key: false,
+                    ..self
+                }
+            }
+            _ => Self {
+                malformed_key: true,
+                ..self
+            },
+        }
+    }
+
+    /// Polly voice id, like `Joanna`
+    fn with_voice(self, voice: String) -> Self {
+        Self {
+            voice: Some(voice),
+            ..self
+        }
+    }
+
+    fn set_speed(self, speed: SpeechSpeed) -> Self {
+        Self {
+            rate: match speed {
+                SpeechSpeed::VeryVerySlow => Some("x-slow"),
+                SpeechSpeed::VerySlow => Some("slow"),
+                SpeechSpeed::Slow => Some("90%"),
+                SpeechSpeed::Normal => None,
+                SpeechSpeed::Quick => Some("110%"),
+                SpeechSpeed::VeryQuick => Some("fast"),
+                SpeechSpeed::VeryVeryQuick => Some("x-fast"),
+            },
+            ..self
+        }
+    }
+
+    /// polly doesn't say how fast its named rates are, these are by ear
+    fn speed_factor(speed: SpeechSpeed) -> f64 {
+        match speed {
+            SpeechSpeed::VeryVerySlow => 0.6,
+            SpeechSpeed::VerySlow => 0.8,
+            SpeechSpeed::Slow => 0.9,
+            SpeechSpeed::Normal => 1.0,
+            SpeechSpeed::Quick => 1.1,
+            SpeechSpeed::VeryQuick => 1.3,
+            SpeechSpeed::VeryVeryQuick => 1.6,
+        }
+    }
+
+    fn for_language(self, language: &LanguageInfo) -> Self {
+        Self {
+            language: language.short_code.clone(),
+            ..self
+        }
+    }
+
+    /// Voices supporting the engine of the builder
+    async fn list_voices(&self) -> Result<Vec<Voice>, TtsError> {
+        let region = self.region();
+        let mut url = Url::parse(&format!(
+            "{}/v1/voices",
+            self.endpoint(&region).trim_end_matches('/')
+        ))
+        .map_err(|e| TtsError::ConnectionFailure(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("Engine", self.engine.as_str());
+        let response = signed_request(
+            self.credentials()?,
+            &region,
+            Method::GET,
+            url,
+            String::new(),
+        )
+        .send()
+        .await;
+
+        let voices = json_response(response).await?;
+        let sample_rate = match self.engine {
+            PollyEngine::Standard => 22050,
+            _ => 24000,
+        };
+        Ok(voices["Voices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|voice| Voice {
+                id: voice["Id"].as_str().unwrap_or_default().to_owned(),
+                name: voice["Name"].as_str().unwrap_or_default().to_owned(),
+                languages: voice["LanguageCode"]
+                    .as_str()
+                    .into_iter()
+                    .chain(
+                        voice["AdditionalLanguageCodes"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|l| l.as_str()),
+                    )
+                    .map(str::to_owned)
+                    .collect(),
+                gender: voice["Gender"].as_str().and_then(VoiceGender::parse),
+                sample_rate: Some(sample_rate),
+            })
+            .collect())
+    }
+
+    fn build(self) -> Result<PollyTtsClient, TtsError> {
+        let credentials = self.credentials()?.clone();
+        let region = self.region();
+        let endpoint = self.endpoint(&region);
+        let engine = self.engine;
+        let voice = match self.voice {
+            // ids are single capitalized words
+            Some(voice) if voice.is_empty() || !voice.chars().all(|c| c.is_ascii_alphabetic()) => {
+                return Err(TtsError::InvalidVoice(format!(
+                    "{voice} is not a polly voice id, like Joanna"
+                )))
+            }
+            Some(voice) => voice,
+            None => {
+                let language = self.language.unwrap_or("en".to_owned());
+                DEFAULT_VOICES
+                    .iter()
+                    .find(|(l, _, _)| *l == language)
+                    .and_then(|(_, voice, long_form_voice)| match engine {
+                        PollyEngine::LongForm => *long_form_voice,
+                        _ => Some(*voice),
+                    })
+                    .map(|voice| voice.to_owned())
+                    .ok_or(TtsError::InvalidVoice(format!(
+                        "no default {} polly voice for {language}",
+                        engine.as_str()
+                    )))?
+            }
+        };
+
+        Ok(PollyTtsClient {
+            credentials,
+            endpoint,
+            region,
+            engine,
+            voice,
+            rate: self.rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::http::Method;
+
+    use crate::text_to_speach::provider::mock_server::{Answer, MockServer};
+
+    use super::*;
+
+    fn credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn polly_amz_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(1440938160);
+        assert_eq!(amz_date(time), "20150830T123600Z");
+        let time = UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(amz_date(time), "20000229T000000Z");
+    }
+
+    /// `get-vanilla` from the aws signature version 4 test suite
+    #[test]
+    fn polly_sigv4() {
+        let authorization = sigv4_authorization(
+            &credentials(),
+            "us-east-1",
+            "service",
+            "GET",
+            "/",
+            "",
+            &[
+                ("host", "example.amazonaws.com".to_owned()),
+                ("x-amz-date", "20150830T123600Z".to_owned()),
+            ],
+            b"",
+            "20150830T123600Z",
+        );
+
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    /// Signs with the example credentials of the aws docs
+    fn builder() -> PollyTtsClientBuilder {
+        PollyTtsClientBuilder {
+            credentials: Some(credentials()),
+            ..PollyTtsClientBuilder::default()
+        }
+    }
+
+    #[test]
+    fn polly_api_key() {
+        let client = PollyTtsClientBuilder::default()
+            .with_api_key("AKIDEXAMPLE:secret".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(client.credentials.access_key_id, "AKIDEXAMPLE");
+        assert_eq!(client.credentials.secret_access_key, "secret");
+
+        // the credentials of the environment don't hide a broken key
+        for api_key in ["AKIDEXAMPLE", ":secret", "AKIDEXAMPLE:"] {
+            let res = builder().with_api_key(api_key.to_owned()).build();
+            assert!(matches!(res, Err(TtsError::Unauthorized(_))));
+        }
+    }
+
+    #[test]
+    fn polly_voice_for_language() {
+        assert_eq!(builder().build().unwrap().voice, "Joanna");
+        let italian = locale_codes::language::lookup("it").unwrap();
+        assert_eq!(
+            builder().for_language(italian).build().unwrap().voice,
+            "Bianca"
+        );
+        let spanish = locale_codes::language::lookup("es").unwrap();
+        let client = builder()
+            .with_engine(PollyEngine::LongForm)
+            .for_language(spanish)
+            .build()
+            .unwrap();
+        assert_eq!(client.voice, "Alba");
+
+        let res = builder().with_voice("Joanna (neural)".to_owned()).build();
+        assert!(matches!(res, Err(TtsError::InvalidVoice(_))));
+    }
+
+    #[tokio::test]
+    async fn polly_voices() {
+        let (endpoint, requests) = MockServer::new()
+            .route(Method::GET, "/v1/voices", |_| {
+                Answer::json(serde_json::json!({ "Voices": [{
+                    "Id": "Bianca",
+                    "Name": "Bianca",
+                    "Gender": "Female",
+                    "LanguageCode": "it-IT",
+                }, {
+                    "Id": "Aditi",
+                    "Name": "Aditi",
+                    "Gender": "Female",
+                    "LanguageCode": "en-IN",
+                    "AdditionalLanguageCodes": ["hi-IN"],
+                }]}))
+            })
+            .start()
+            .await;
+
+        let voices = builder()
+            .with_endpoint(endpoint)
+            .with_engine(PollyEngine::Standard)
+            .list_voices()
+            .await
+            .unwrap();
+
+        assert_eq!(voices.len(), 2);
+        assert_eq!(voices[0].id, "Bianca");
+        assert_eq!(voices[0].sample_rate, Some(22050));
+        assert_eq!(voices[1].languages, vec!["en-IN", "hi-IN"]);
+        assert_eq!(voices[1].gender, Some(VoiceGender::Female));
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.query["Engine"], "standard");
+        assert!(request
+            .header(header::AUTHORIZATION.as_str())
+            .unwrap()
+            .contains("/polly/aws4_request"));
+    }
+
+    #[tokio::test]
+    async fn polly_request() {
+        let (endpoint, requests) = MockServer::new()
+            .route(Method::POST, "/v1/speech", |_| Answer::bytes([1, 2, 3]))
+            .start()
+            .await;
+        let path = std::env::temp_dir().join("book2pod-polly.mp3");
+
+        let client = builder()
+            .with_endpoint(endpoint)
+            .with_region("eu-west-1".to_owned())
+            .with_engine(PollyEngine::LongForm)
+            .set_speed(SpeechSpeed::Quick)
+            .build()
+            .unwrap();
+        client
+            .speak_to_file("a & b".to_owned(), path.to_string_lossy().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        let request = &requests.lock().unwrap()[0];
+        let authorization = request.header(header::AUTHORIZATION.as_str()).unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains(
+            "/eu-west-1/polly/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature="
+        ));
+        assert!(request.headers.contains_key("x-amz-date"));
+        assert_eq!(
+            request.json(),
+            serde_json::json!({
+                "Engine": "long-form",
+                "OutputFormat": "mp3",
+                "Text": r#"<speak><prosody rate="110%">a &amp; b</prosody></speak>"#,
+                "TextType": "ssml",
+                "VoiceId": "Danielle",
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+use super::TtsError;
+
+/// How hard `retry` tries before giving up
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Including the first one
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled at every attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// No retry is started once waiting would go past this
+    pub max_elapsed: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_elapsed: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the attempt after `attempt` (the first is 1), half of it
+    /// random so parallel clients don't retry all together
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+
+    /// How long to wait before trying again after `error` failed `attempt`,
+    /// `elapsed` since the first one, None when it's not worth trying again
+    pub fn wait_after(
+        &self,
+        error: &TtsError,
+        attempt: u32,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        if !is_transient(error) || attempt >= self.max_attempts {
+            return None;
+        }
+        // the provider knows better than our guess
+        let wait = match error {
+            TtsError::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            } => *retry_after,
+            _ => self.backoff(attempt),
+        };
+        (elapsed + wait <= self.max_elapsed).then_some(wait)
+    }
+}
+
+/// Runs `attempt` again until it works or `policy` gives up on its error,
+/// like `retry(&policy, || client.clone().speak_to_file(text.clone(), path.clone()))`
+pub async fn retry<T, Fut>(
+    policy: &RetryPolicy,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, TtsError>
+where
+    Fut: Future<Output = Result<T, TtsError>>,
+{
+    let start = Instant::now();
+    let mut attempts = 1;
+    loop {
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        let Some(wait) = policy.wait_after(&error, attempts, start.elapsed()) else {
+            return Err(error);
+        };
+        tokio::time::sleep(wait).await;
+        attempts += 1;
+    }
+}
+
+/// Errors that could go away by trying again
+fn is_transient(error: &TtsError) -> bool {
+    matches!(
+        error,
+        TtsError::RateLimited { .. } | TtsError::ServerError(_) | TtsError::ConnectionFailure(_)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            max_elapsed: Duration::from_secs(5),
+        }
+    }
+
+    fn rate_limited(retry_after: Option<Duration>) -> TtsError {
+        TtsError::RateLimited {
+            retry_after,
+            message: "slow down".to_owned(),
+        }
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let policy = policy();
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(5) && first <= Duration::from_millis(10));
+            let capped = policy.backoff(10);
+            assert!(capped >= Duration::from_millis(20) && capped <= Duration::from_millis(40));
+        }
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        let policy = policy();
+        // the provider knows better than our guess
+        assert_eq!(
+            policy.wait_after(
+                &rate_limited(Some(Duration::from_millis(50))),
+                1,
+                Duration::ZERO
+            ),
+            Some(Duration::from_millis(50))
+        );
+        for error in [
+            rate_limited(None),
+            TtsError::ServerError("502".to_owned()),
+            TtsError::ConnectionFailure("reset".to_owned()),
+        ] {
+            let wait = policy.wait_after(&error, 2, Duration::ZERO).unwrap();
+            assert!(wait >= Duration::from_millis(10) && wait <= Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let policy = policy();
+        let error = TtsError::ServerError("500".to_owned());
+        assert!(policy.wait_after(&error, 3, Duration::ZERO).is_some());
+        assert_eq!(policy.wait_after(&error, 4, Duration::ZERO), None);
+
+        for error in [
+            TtsError::Unknown("bad request".to_owned()),
+            TtsError::Unauthorized("no key".to_owned()),
+            TtsError::InvalidVoice("nope".to_owned()),
+        ] {
+            assert_eq!(policy.wait_after(&error, 1, Duration::ZERO), None);
+        }
+
+        // waiting an hour goes past max_elapsed
+        let error = rate_limited(Some(Duration::from_secs(3600)));
+        assert_eq!(policy.wait_after(&error, 1, Duration::ZERO), None);
+        let error = rate_limited(Some(Duration::from_secs(1)));
+        assert_eq!(
+            policy.wait_after(&error, 1, Duration::from_millis(4500)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_until_it_works() {
+        let policy = policy();
+        let mut attempts = 0;
+        let res = retry(&policy, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    1 => Err(rate_limited(Some(Duration::from_millis(1)))),
+                    2 => Err(TtsError::ConnectionFailure("reset".to_owned())),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 3);
+
+        let mut attempts = 0;
+        let res: Result<(), TtsError> = retry(&policy, || {
+            attempts += 1;
+            async { Err(TtsError::ServerError("500".to_owned())) }
+        })
+        .await;
+        assert!(matches!(res, Err(TtsError::ServerError(_))));
+        assert_eq!(attempts, 4);
+
+        let mut attempts = 0;
+        let res: Result<(), TtsError> = retry(&policy, || {
+            attempts += 1;
+            async { Err(TtsError::Unauthorized("no key".to_owned())) }
+        })
+        .await;
+        assert!(matches!(res, Err(TtsError::Unauthorized(_))));
+        assert_eq!(attempts, 1);
+    }
+}
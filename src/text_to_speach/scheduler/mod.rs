@@ -0,0 +1,602 @@
+use std::{
+    fs,
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+
+use crate::jobs::{JobStore, Status};
+
+use super::{
+    chunker,
+    provider::{
+        retry::{retry, RetryPolicy},
+        TtsClient, TtsError,
+    },
+};
+
+/// How much a provider takes at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Requests in flight
+    pub concurrency: usize,
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Spaces the requests evenly so a minute never goes over the budget
+struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// Audio file of an episode and the chunks spoken in it
+pub struct EpisodeJob {
+    pub chunks: Vec<String>,
+    pub path: PathBuf,
+    /// What the provider makes, wav parts are joined and encoded once
+    pub part_extension: &'static str,
+}
+
+/// Synthesizes the chunks of many episodes at once within the limits of the
+/// provider, each chunk with a clone of the client built once for the run.
+///
+/// Chunks finish in any order but are joined by position, so the audio is
+/// the same as synthesizing one chunk after the other.
+pub struct Scheduler<C> {
+    client: C,
+    permits: Arc<Semaphore>,
+    /// Episodes spawned at once, the chunks of a few are enough to use every
+    /// permit
+    episode_tasks: Arc<Semaphore>,
+    /// Chunks spawned at once across the episodes, more than `permits` so a
+    /// chunk waiting to retry leaves its permit to another
+    chunk_tasks: Arc<Semaphore>,
+    pacer: Option<Arc<Pacer>>,
+    retry: Option<RetryPolicy>,
+    jobs: Option<Arc<JobStore>>,
+}
+
+impl<C: Clone> Clone for Scheduler<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            permits: self.permits.clone(),
+            episode_tasks: self.episode_tasks.clone(),
+            chunk_tasks: self.chunk_tasks.clone(),
+            pacer: self.pacer.clone(),
+            retry: self.retry.clone(),
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<C> Scheduler<C>
+where
+    C: TtsClient + Clone + Sync + 'static,
+{
+    pub fn new(client: C, limits: Limits) -> Self {
+        Self {
+            client,
+            permits: Arc::new(Semaphore::new(limits.concurrency.max(1))),
+            episode_tasks: Arc::new(Semaphore::new(limits.concurrency.max(1))),
+            chunk_tasks: Arc::new(Semaphore::new(limits.concurrency.max(1) * 2)),
+            pacer: limits
+                .requests_per_minute
+                .filter(|rpm| *rpm > 0)
+                .map(|rpm| {
+                    Arc::new(Pacer {
+                        interval: Duration::from_secs(60) / rpm,
+                        next: Mutex::new(Instant::now()),
+                    })
+                }),
+            retry: None,
+            jobs: None,
+        }
+    }
+
+    /// Writes every episode, `on_done` gets the index of each episode as
+    /// it's written. Stops at the first error.
+    pub async fn speak_episodes(
+        &self,
+        episodes: Vec<EpisodeJob>,
+        on_done: impl FnMut(usize),
+    ) -> Result<(), TtsError> {
+        let episodes = episodes.into_iter().enumerate().map(|(i, episode)| {
+            let scheduler = self.clone();
+            async move { scheduler.speak_episode(i, episode).await.map(|()| i) }
+        });
+        spawn_bounded(&self.episode_tasks, episodes, on_done).await
+    }
+
+    /// Records the progress in `jobs`, skipping the chunks and episodes it
+    /// says are done when their audio is still there
+    pub fn with_jobs(self, jobs: Arc<JobStore>) -> Self {
+        Self {
+            jobs: Some(jobs),
+            ..self
+        }
+    }
+
+    /// Tries the chunks that fail on rate limits and server errors again,
+    /// every attempt waits its turn within `requests_per_minute`
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        Self {
+            retry: Some(policy),
+            ..self
+        }
+    }
+
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, TtsError> {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| TtsError::Unknown(e.to_string()))
+    }
+
+    /// Speaks one chunk, every attempt takes a permit and its turn within
+    /// `requests_per_minute`, so other chunks go while it waits to try again
+    async fn speak_chunk(
+        &self,
+        text: &str,
+        path: &str,
+        previous: Option<&String>,
+        next: Option<&String>,
+    ) -> Result<(), TtsError> {
+        let attempt = || {
+            let client = self
+                .client
+                .clone()
+                .with_context(previous.cloned(), next.cloned());
+            let (text, path) = (text.to_owned(), path.to_owned());
+            async move {
+                // the semaphore is fair, the first episodes mostly go first
+                let _permit = self.acquire().await?;
+                if let Some(pacer) = &self.pacer {
+                    pacer.wait().await;
+                }
+                client.speak_to_file(text, path).await
+            }
+        };
+        match &self.retry {
+            Some(policy) => retry(policy, attempt).await,
+            None => attempt().await,
+        }
+    }
+
+    fn record(&self, result: anyhow::Result<()>) -> Result<(), TtsError> {
+        result.map_err(|e| TtsError::WriteToFileFailure(format!("job journal: {e}")))
+    }
+
+    async fn speak_episode(self, index: usize, episode: EpisodeJob) -> Result<(), TtsError> {
+        if let Some(jobs) = &self.jobs {
+            if jobs.episode_status(index, &episode.chunks) == Some(Status::Done)
+                && episode.path.exists()
+            {
+                return Ok(());
+            }
+        }
+        let parts = (0..episode.chunks.len())
+            .map(|i| chunker::part_path(&episode.path, i, episode.part_extension))
+            .collect::<Vec<PathBuf>>();
+        let chunks = episode
+            .chunks
+            .iter()
+            .enumerate()
+            // skipping the ones done by an earlier run
+            .filter(|(i, chunk)| {
+                !self.jobs.as_ref().is_some_and(|jobs| {
+                    jobs.chunk_status(index, *i, chunk) == Some(Status::Done) && parts[*i].exists()
+                })
+            })
+            .map(|(i, chunk)| {
+                let previous = i.checked_sub(1).map(|p| episode.chunks[p].clone());
+                let next = episode.chunks.get(i + 1).cloned();
+                let (chunk, part) = (chunk.clone(), parts[i].to_string_lossy().to_string());
+                let scheduler = self.clone();
+                async move {
+                    let speak =
+                        scheduler.speak_chunk(&chunk, &part, previous.as_ref(), next.as_ref());
+                    let Some(jobs) = scheduler.jobs.clone() else {
+                        return speak.await;
+                    };
+                    scheduler.record(jobs.set_chunk(index, i, &chunk, Status::Running, None))?;
+                    let result = speak.await;
+                    let (status, error) = match &result {
+                        Ok(()) => (Status::Done, None),
+                        Err(e) => (Status::Failed, Some(e.to_string())),
+                    };
+                    scheduler.record(jobs.set_chunk(index, i, &chunk, status, error))?;
+                    result
+                }
+            })
+            .collect::<Vec<_>>();
+        let result = spawn_bounded(&self.chunk_tasks, chunks.into_iter(), |()| ()).await;
+        if result.is_err() && self.jobs.is_some() {
+            // the parts already done are kept for the next run
+            return result;
+        }
+        let result = match result {
+            Ok(()) => chunker::concat_audio_files(&parts, &episode.path).await,
+            Err(e) => Err(e),
+        };
+        for part in parts {
+            let _ = fs::remove_file(part);
+        }
+        if let (Ok(()), Some(jobs)) = (&result, &self.jobs) {
+            self.record(jobs.set_episode(index, Status::Done))?;
+        }
+        result
+    }
+}
+
+/// Runs `futures` on their own tasks, each holding a permit of `tasks` so a
+/// big book doesn't wait in thousands of them. Stops at the first error,
+/// nothing is still running once it returns.
+async fn spawn_bounded<T, Fut>(
+    tasks: &Arc<Semaphore>,
+    futures: impl Iterator<Item = Fut>,
+    mut on_done: impl FnMut(T),
+) -> Result<(), TtsError>
+where
+    T: Send + 'static,
+    Fut: Future<Output = Result<T, TtsError>> + Send + 'static,
+{
+    let mut futures = futures.peekable();
+    let mut running = JoinSet::new();
+    loop {
+        let result = tokio::select! {
+            // a failure stops the spawning
+            biased;
+            Some(result) = running.join_next() => result
+                .unwrap_or_else(|e| Err(TtsError::Unknown(format!("synthesis task failed: {e}")))),
+            permit = tasks.clone().acquire_owned(), if futures.peek().is_some() => {
+                let permit = permit.map_err(|e| TtsError::Unknown(e.to_string()))?;
+                if let Some(future) = futures.next() {
+                    running.spawn(async move {
+                        let _permit = permit;
+                        future.await
+                    });
+                }
+                continue;
+            }
+            else => return Ok(()),
+        };
+        if let Err(e) = result.map(&mut on_done) {
+            running.shutdown().await;
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Requests running and the most that ran at once
+    #[derive(Default)]
+    struct Running(AtomicUsize, AtomicUsize);
+
+    /// Writes the text after a delay that makes later chunks finish first
+    #[derive(Clone)]
+    struct SlowClient(Arc<Running>);
+
+    impl TtsClient for SlowClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            let running = self.0 .0.fetch_add(1, Ordering::SeqCst) + 1;
+            self.0 .1.fetch_max(running, Ordering::SeqCst);
+            let delay = 40u64.saturating_sub(text.len() as u64 * 10);
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            self.0 .0.fetch_sub(1, Ordering::SeqCst);
+            if text == "fail" {
+                return Err(TtsError::Unknown("fail".to_owned()));
+            }
+            fs::write(path, text).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    /// Fails on "flaky" until it's fixed, remembering what it spoke
+    #[derive(Default)]
+    struct Spoken(Mutex<Vec<String>>, AtomicBool);
+
+    #[derive(Clone)]
+    struct FlakyClient(Arc<Spoken>);
+
+    impl TtsClient for FlakyClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            tokio::task::yield_now().await;
+            if text == "flaky" && !self.0 .1.load(Ordering::SeqCst) {
+                return Err(TtsError::ServerError("flaky".to_owned()));
+            }
+            self.0 .0.lock().unwrap().push(text.clone());
+            fs::write(path, text).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    /// Speaks the text as the samples of a wav, like a local engine
+    #[derive(Clone)]
+    struct WavClient;
+
+    impl TtsClient for WavClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            assert!(path.ends_with(".wav"));
+            fs::write(
+                path,
+                chunker::wav::pcm_to_wav(text.as_bytes(), 16000, 1, 16),
+            )
+            .map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    /// Rate limited on the first two attempts
+    #[derive(Clone)]
+    struct FailingClient(Arc<AtomicUsize>);
+
+    impl TtsClient for FailingClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            if self.0.fetch_add(1, Ordering::SeqCst) < 2 {
+                return Err(TtsError::RateLimited {
+                    retry_after: None,
+                    message: "slow down".to_owned(),
+                });
+            }
+            fs::write(path, text).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    /// "a" is rate limited for 100ms once, "b" takes 100ms
+    #[derive(Clone)]
+    struct PatientClient(Arc<AtomicBool>);
+
+    impl TtsClient for PatientClient {
+        async fn speak_to_file(self, text: String, path: String) -> Result<(), TtsError> {
+            if text == "a" && !self.0.swap(true, Ordering::SeqCst) {
+                return Err(TtsError::RateLimited {
+                    retry_after: Some(Duration::from_millis(100)),
+                    message: "slow down".to_owned(),
+                });
+            }
+            if text == "b" {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            fs::write(path, text).map_err(|e| TtsError::WriteToFileFailure(e.to_string()))
+        }
+    }
+
+    fn jobs(dir: &std::path::Path, episodes: &[&[&str]]) -> Vec<EpisodeJob> {
+        episodes
+            .iter()
+            .enumerate()
+            .map(|(i, chunks)| EpisodeJob {
+                chunks: chunks.iter().map(|c| c.to_string()).collect(),
+                path: dir.join(format!("{i}.mp3")),
+                part_extension: "mp3",
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scheduler_order_and_concurrency() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let running = Arc::new(Running::default());
+        let scheduler = Scheduler::new(
+            SlowClient(running.clone()),
+            Limits {
+                concurrency: 3,
+                requests_per_minute: None,
+            },
+        );
+
+        let mut done = vec![];
+        scheduler
+            .speak_episodes(
+                jobs(&dir, &[&["a", "bb", "ccc"], &["d", "ee"], &["f"]]),
+                |i| done.push(i),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("0.mp3")).unwrap(), "abbccc");
+        assert_eq!(fs::read_to_string(dir.join("1.mp3")).unwrap(), "dee");
+        assert_eq!(fs::read_to_string(dir.join("2.mp3")).unwrap(), "f");
+        done.sort();
+        assert_eq!(done, vec![0, 1, 2]);
+        assert_eq!(running.1.load(Ordering::SeqCst), 3);
+        // only the episodes are left
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        let res = scheduler
+            .speak_episodes(jobs(&dir, &[&["a", "fail"]]), |_| ())
+            .await;
+        assert!(matches!(res, Err(TtsError::Unknown(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scheduler_wav_parts() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler-wav");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let scheduler = Scheduler::new(
+            WavClient,
+            Limits {
+                concurrency: 2,
+                requests_per_minute: None,
+            },
+        );
+
+        let episode = EpisodeJob {
+            chunks: vec!["ab".to_owned(), "cd".to_owned(), "ef".to_owned()],
+            path: dir.join("0.wav"),
+            part_extension: "wav",
+        };
+        scheduler
+            .speak_episodes(vec![episode], |_| ())
+            .await
+            .unwrap();
+
+        // a single header for the whole episode
+        assert_eq!(
+            fs::read(dir.join("0.wav")).unwrap(),
+            chunker::wav::pcm_to_wav(b"abcdef", 16000, 1, 16)
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scheduler_requests_per_minute() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler-rpm");
+        fs::create_dir_all(&dir).unwrap();
+        let scheduler = Scheduler::new(
+            SlowClient(Arc::default()),
+            Limits {
+                concurrency: 10,
+                requests_per_minute: Some(1200),
+            },
+        );
+
+        let start = Instant::now();
+        scheduler
+            .speak_episodes(jobs(&dir, &[&["aaaa", "bbbb"], &["cccc", "dddd"]]), |_| ())
+            .await
+            .unwrap();
+
+        // one request every 50ms
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scheduler_paces_retries() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler-retries");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let scheduler = Scheduler::new(
+            FailingClient(attempts.clone()),
+            Limits {
+                concurrency: 4,
+                requests_per_minute: Some(600),
+            },
+        )
+        .with_retry(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            ..RetryPolicy::default()
+        });
+
+        let start = Instant::now();
+        scheduler
+            .speak_episodes(jobs(&dir, &[&["a"]]), |_| ())
+            .await
+            .unwrap();
+
+        // the retries waited their turn, one request every 100ms
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(dir.join("0.mp3")).unwrap(), "a");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scheduler_backoff_frees_the_slot() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler-backoff");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let limited = Arc::new(AtomicBool::new(false));
+        let scheduler = Scheduler::new(
+            PatientClient(limited),
+            Limits {
+                concurrency: 1,
+                requests_per_minute: None,
+            },
+        )
+        .with_retry(RetryPolicy::default());
+
+        let start = Instant::now();
+        scheduler
+            .speak_episodes(jobs(&dir, &[&["a", "b"]]), |_| ())
+            .await
+            .unwrap();
+
+        // b was spoken while a waited to try again
+        assert!(start.elapsed() < Duration::from_millis(190));
+        assert_eq!(fs::read_to_string(dir.join("0.mp3")).unwrap(), "ab");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scheduler_resume() {
+        let dir = std::env::temp_dir().join("book2pod-scheduler-resume");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let spoken = Arc::new(Spoken::default());
+        let episodes: &[&[&str]] = &[&["a", "flaky"], &["b"]];
+        // every run is a restart of book2pod
+        let run = || {
+            let planned = self::jobs(&dir, episodes);
+            let chunks = planned
+                .iter()
+                .map(|episode| episode.chunks.as_slice())
+                .collect::<Vec<&[String]>>();
+            let jobs = Arc::new(JobStore::open(&dir, "Book", "settings", &chunks).unwrap());
+            for (i, episode) in planned.iter().enumerate() {
+                jobs.plan_episode(i, "", &episode.path, &episode.chunks)
+                    .unwrap();
+            }
+            Scheduler::new(
+                FlakyClient(spoken.clone()),
+                Limits {
+                    concurrency: 1,
+                    requests_per_minute: None,
+                },
+            )
+            .with_jobs(jobs)
+        };
+
+        let res = run().speak_episodes(jobs(&dir, episodes), |_| ()).await;
+        assert!(matches!(res, Err(TtsError::ServerError(_))));
+        assert!(chunker::part_path(&dir.join("0.mp3"), 0, "mp3").exists());
+
+        spoken.1.store(true, Ordering::SeqCst);
+        run()
+            .speak_episodes(jobs(&dir, episodes), |_| ())
+            .await
+            .unwrap();
+        run()
+            .speak_episodes(jobs(&dir, episodes), |_| ())
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("0.mp3")).unwrap(), "aflaky");
+        assert_eq!(fs::read_to_string(dir.join("1.mp3")).unwrap(), "b");
+        // nothing was spoken twice
+        let mut spoken = spoken.0.lock().unwrap().clone();
+        spoken.sort();
+        assert_eq!(spoken, vec!["a", "b", "flaky"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
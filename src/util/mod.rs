@@ -0,0 +1,10 @@
+use sha2::{Digest, Sha256};
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Sha256 of `bytes` in hex
+pub(crate) fn hex_hash(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}